@@ -0,0 +1,73 @@
+//! End-to-end latency over localhost HTTP: straight to a key server and
+//! through a router, with small and large routing tables. Run with
+//! `cargo bench --bench router`.
+
+use std::{collections::HashMap, hint::black_box, sync::Arc};
+
+use axum::Router;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_key_store::server::{
+    key_server::{self, Db, Store},
+    shard_router, Lifecycle,
+};
+use serde_json::json;
+use tokio::{net::TcpListener, runtime::Runtime};
+
+async fn spawn(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// A key server holding `app.key`, and routers in front of it with 1 and
+/// 10 000 routes. Returns (label, base URL, key as addressed there).
+async fn targets() -> Vec<(String, String, &'static str)> {
+    let values = HashMap::from([(".key".to_string(), json!({ "name": "Alice", "age": 31 }))]);
+    let store: Store = Arc::new(Db::from_values(values));
+    let backend = spawn(key_server::app(store, Lifecycle::new("key-server"))).await;
+
+    let mut targets = vec![("direct".to_string(), backend.clone(), ".key")];
+    for extra in [0, 9_999] {
+        let mut routes: HashMap<String, String> =
+            (0..extra).map(|i| (format!("svc{}", i), backend.clone())).collect();
+        routes.insert("app".to_string(), backend.clone());
+        let router = spawn(shard_router::app(routes, Lifecycle::new("shard-router"))).await;
+        targets.push((format!("router_{}_routes", extra + 1), router, "app.key"));
+    }
+    targets
+}
+
+fn round_trips(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let targets = rt.block_on(targets());
+    let client = reqwest::Client::new();
+
+    let mut group = c.benchmark_group("http/get");
+    for (label, base, key) in &targets {
+        let url = format!("{}/keys/{}", base, urlencoding::encode(key));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.to_async(&rt).iter(|| async {
+                let res = client.get(&url).send().await.unwrap();
+                black_box(res.bytes().await.unwrap());
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("http/put");
+    for (label, base, key) in &targets {
+        let url = format!("{}/keys/{}", base, urlencoding::encode(key));
+        let value = json!({ "name": "Alice", "age": 32 });
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.to_async(&rt).iter(|| async {
+                let res = client.put(&url).json(&value).send().await.unwrap();
+                black_box(res.bytes().await.unwrap());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, round_trips);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+//! `RouteTable::find` against route tables of different sizes and keys of
+//! different depths, and with `*` routes. Run with `cargo bench --bench routing`.
+
+use std::{collections::HashMap, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_key_store::routing::RouteTable;
+
+/// `services` top-level prefixes (`svc0`, `svc1`, ...), each with `nested`
+/// second-level prefixes (`svc0.shard0`, ...).
+fn routes(services: usize, nested: usize) -> RouteTable {
+    let mut routes = HashMap::new();
+    for s in 0..services {
+        routes.insert(format!("svc{}", s), format!("http://10.0.{}.1:3000", s % 256));
+        for n in 0..nested {
+            routes.insert(format!("svc{}.shard{}", s, n), format!("http://10.1.{}.{}:3000", s % 256, n % 256));
+        }
+    }
+    RouteTable::new(routes)
+}
+
+/// A key under `svc{service}.shard0` with `depth` segments in total.
+fn key(service: usize, depth: usize) -> String {
+    let mut key = format!("svc{}.shard0", service);
+    for i in 2..depth {
+        key.push_str(&format!(".field{}", i));
+    }
+    key
+}
+
+fn find_by_table_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find/table_size");
+    for services in [10, 1_000, 100_000] {
+        let routes = routes(services, 4);
+        let hit = key(services / 2, 4);
+        let miss = "unrouted.a.b.c".to_string();
+        group.bench_with_input(BenchmarkId::new("hit", routes.len()), &hit, |b, key| {
+            b.iter(|| routes.find(black_box(key)))
+        });
+        group.bench_with_input(BenchmarkId::new("miss", routes.len()), &miss, |b, key| {
+            b.iter(|| routes.find(black_box(key)))
+        });
+    }
+    group.finish();
+}
+
+fn find_by_key_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find/key_depth");
+    let routes = routes(1_000, 4);
+    for depth in [1, 2, 8, 32, 128] {
+        let key = if depth == 1 { "svc7".to_string() } else { key(7, depth) };
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &key, |b, key| {
+            b.iter(|| routes.find(black_box(key)))
+        });
+    }
+    group.finish();
+}
+
+/// `tenant.*.cache` and `tenant.*.*.data` routes next to 1 000 literal ones,
+/// plus a literal override for one tenant.
+fn find_with_wildcards(c: &mut Criterion) {
+    let mut routes: HashMap<String, String> = (0..1_000)
+        .map(|t| (format!("tenant{}", t), format!("http://10.2.{}.1:3000", t % 256)))
+        .collect();
+    routes.insert("tenant.*.cache".to_string(), "http://10.3.0.1:3000".to_string());
+    routes.insert("tenant.*.*.data".to_string(), "http://10.3.0.2:3000".to_string());
+    routes.insert("tenant.acme.cache".to_string(), "http://10.3.0.3:3000".to_string());
+    let routes = RouteTable::new(routes);
+
+    let mut group = c.benchmark_group("find/wildcard");
+    for (label, key) in [
+        ("literal", "tenant.acme.cache.session.1"),
+        ("one_star", "tenant.globex.cache.session.1"),
+        ("two_stars", "tenant.globex.eu.data.orders.1"),
+        ("miss", "tenant.globex.logs.1"),
+    ] {
+        group.bench_with_input(BenchmarkId::from_parameter(label), key, |b, key| {
+            b.iter(|| routes.find(black_box(key)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, find_by_table_size, find_by_key_depth, find_with_wildcards);
+criterion_main!(benches);
@@ -0,0 +1,134 @@
+//! Key-server handlers called in-process (no sockets) at different value sizes,
+//! alone and with concurrent readers. Run with `cargo bench --bench store`.
+
+use std::{collections::HashMap, hint::black_box, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_key_store::server::{
+    key_server::{self, Db, Store},
+    Lifecycle,
+};
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+const SIZES: [usize; 4] = [16, 1_024, 64 * 1_024, 1_024 * 1_024];
+
+/// An object of roughly `size` bytes of JSON: string fields of 64 bytes.
+fn value(size: usize) -> Value {
+    let fields = (size / 80).max(1);
+    let object: serde_json::Map<String, Value> =
+        (0..fields).map(|i| (format!("f{:05}", i), json!("x".repeat(64)))).collect();
+    Value::Object(object)
+}
+
+fn app_with(key: &str, value: &Value) -> Router {
+    let values = HashMap::from([(key.to_string(), value.clone())]);
+    let store: Store = Arc::new(Db::from_values(values));
+    key_server::app(store, Lifecycle::new("key-server"))
+}
+
+async fn call(app: &Router, method: Method, uri: &str, body: Option<&[u8]>) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if body.is_some() {
+        request = request.header("content-type", "application/json");
+    }
+    let body = body.map_or_else(Body::empty, |body| Body::from(body.to_vec()));
+    let res = app.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+    assert!(res.status() == StatusCode::OK, "{}", res.status());
+    black_box(to_bytes(res.into_body(), usize::MAX).await.unwrap());
+}
+
+fn handlers_by_value_size(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    for (name, method) in [("get", Method::GET), ("put", Method::PUT)] {
+        let mut group = c.benchmark_group(format!("store/{}", name));
+        for size in SIZES {
+            let value = value(size);
+            let encoded = serde_json::to_vec(&value).unwrap();
+            let app = app_with("bench.key", &value);
+            let body = (method == Method::PUT).then_some(encoded.as_slice());
+            group.throughput(Throughput::Bytes(encoded.len() as u64));
+            group.bench_function(BenchmarkId::from_parameter(size), |b| {
+                b.to_async(&rt).iter(|| call(&app, method.clone(), "/keys/bench.key", body))
+            });
+        }
+        group.finish();
+    }
+
+    let mut group = c.benchmark_group("store/get_pointer");
+    for size in SIZES {
+        let app = app_with("bench.key", &value(size));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.to_async(&rt).iter(|| call(&app, Method::GET, "/keys/bench.key?pointer=/f00000", None))
+        });
+    }
+    group.finish();
+}
+
+/// 16 GETs of the same key in flight at once, on a multi-threaded runtime: how
+/// much readers of one hot key slow each other down.
+fn concurrent_gets(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("store/get_x16_concurrent");
+    for size in SIZES {
+        let app = app_with("bench.key", &value(size));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.to_async(&rt).iter(|| async {
+                let readers: Vec<_> = (0..16)
+                    .map(|_| {
+                        let app = app.clone();
+                        tokio::spawn(async move { call(&app, Method::GET, "/keys/bench.key", None).await })
+                    })
+                    .collect();
+                for reader in readers {
+                    reader.await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// 8 PUTs and 8 GETs in flight at once, each on its own key, so they only
+/// contend for the store, not for a key.
+fn concurrent_mixed(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("store/mixed_x16_concurrent");
+    for size in SIZES {
+        let value = value(size);
+        let values = (0..16).map(|i| (format!("bench.{}", i), value.clone())).collect();
+        let store: Store = Arc::new(Db::from_values(values));
+        let app = key_server::app(store, Lifecycle::new("key-server"));
+        let encoded = serde_json::to_vec(&value).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.to_async(&rt).iter(|| async {
+                let tasks: Vec<_> = (0..16)
+                    .map(|i| {
+                        let app = app.clone();
+                        let body = encoded.clone();
+                        tokio::spawn(async move {
+                            let uri = format!("/keys/bench.{}", i);
+                            match i % 2 {
+                                0 => call(&app, Method::PUT, &uri, Some(&body)).await,
+                                _ => call(&app, Method::GET, &uri, None).await,
+                            }
+                        })
+                    })
+                    .collect();
+                for task in tasks {
+                    task.await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, handlers_by_value_size, concurrent_gets, concurrent_mixed);
+criterion_main!(benches);
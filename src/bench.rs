@@ -0,0 +1,297 @@
+//! Closed-loop load generator behind `kvs-client bench`. Works against a key
+//! server or a router, since it only uses [`KvClient`].
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rand::{distributions::Alphanumeric, rngs::SmallRng, Rng, SeedableRng};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{KvClient, KvError};
+
+/// How keys are picked from the key space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    Uniform,
+    /// Key `i` is picked with probability proportional to `1 / (i + 1)^exponent`
+    Zipfian { exponent: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    /// Concurrent workers, each with at most one request in flight
+    pub concurrency: usize,
+    /// Number of distinct keys, named `<prefix><n>`
+    pub keys: usize,
+    /// Prepended to every key; through a router it must match a route
+    pub prefix: String,
+    /// Length of the string value written
+    pub value_size: usize,
+    /// Fraction of operations that are reads, from 0.0 to 1.0
+    pub read_ratio: f64,
+    pub duration: Duration,
+    pub distribution: KeyDistribution,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            concurrency: 16,
+            keys: 1000,
+            prefix: "bench.".to_string(),
+            value_size: 100,
+            read_ratio: 0.9,
+            duration: Duration::from_secs(10),
+            distribution: KeyDistribution::Uniform,
+        }
+    }
+}
+
+/// Latency percentiles of one operation type, in microseconds.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub p999_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BenchReport {
+    pub duration_secs: f64,
+    /// Operations attempted, failed ones included
+    pub operations: u64,
+    /// Successful operations per second; failures are often fast and would inflate it
+    pub throughput: f64,
+    pub reads: LatencySummary,
+    pub writes: LatencySummary,
+    /// Failed operations by kind (`"404"`, `"transport"`, ...); not in the latencies
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl BenchReport {
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Writes every key in the key space (existing keys are left as they are),
+/// then runs the mix for `config.duration`.
+pub async fn run(client: &KvClient, config: &BenchConfig) -> Result<BenchReport, KvError> {
+    if config.keys == 0 || config.concurrency == 0 {
+        return Err(KvError::InvalidInput("keys and concurrency must be at least 1".to_string()));
+    }
+    if !(0.0..=1.0).contains(&config.read_ratio) {
+        return Err(KvError::InvalidInput("read ratio must be between 0 and 1".to_string()));
+    }
+
+    let value = Value::String(random_string(&mut SmallRng::from_entropy(), config.value_size));
+    preload(client, config, &value).await?;
+
+    let sampler = Arc::new(KeySampler::new(config.keys, config.distribution));
+    let started = Instant::now();
+    let deadline = started + config.duration;
+    let workers: Vec<_> = (0..config.concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let sampler = sampler.clone();
+            let value = value.clone();
+            let prefix = config.prefix.clone();
+            let read_ratio = config.read_ratio;
+            tokio::spawn(async move {
+                let mut rng = SmallRng::from_entropy();
+                let mut stats = WorkerStats::default();
+                while Instant::now() < deadline {
+                    let key = format!("{}{}", prefix, sampler.sample(&mut rng));
+                    let read = rng.gen_bool(read_ratio);
+                    let op_started = Instant::now();
+                    let result = if read {
+                        client.get::<Value>(&key).await.map(drop)
+                    } else {
+                        client.update(&key, &value).await.map(drop)
+                    };
+                    let micros = op_started.elapsed().as_micros() as u64;
+                    match result {
+                        Ok(()) if read => stats.reads.push(micros),
+                        Ok(()) => stats.writes.push(micros),
+                        Err(e) => *stats.errors.entry(error_kind(&e)).or_default() += 1,
+                    }
+                }
+                stats
+            })
+        })
+        .collect();
+
+    let mut total = WorkerStats::default();
+    for worker in workers {
+        let stats = worker.await.expect("bench worker panicked");
+        total.reads.extend(stats.reads);
+        total.writes.extend(stats.writes);
+        for (kind, count) in stats.errors {
+            *total.errors.entry(kind).or_default() += count;
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let succeeded = (total.reads.len() + total.writes.len()) as u64;
+    Ok(BenchReport {
+        duration_secs: elapsed,
+        operations: succeeded + total.errors.values().sum::<u64>(),
+        throughput: succeeded as f64 / elapsed,
+        reads: summarize(total.reads),
+        writes: summarize(total.writes),
+        errors: total.errors,
+    })
+}
+
+async fn preload(client: &KvClient, config: &BenchConfig, value: &Value) -> Result<(), KvError> {
+    let mut next = 0;
+    while next < config.keys {
+        let batch_end = (next + config.concurrency).min(config.keys);
+        let batch: Vec<_> = (next..batch_end)
+            .map(|i| {
+                let client = client.clone();
+                let key = format!("{}{}", config.prefix, i);
+                let value = value.clone();
+                tokio::spawn(async move {
+                    match client.set(&key, &value).await {
+                        Ok(_) | Err(KvError::AlreadyExists { .. }) => Ok(()),
+                        Err(e) => Err(e),
+                    }
+                })
+            })
+            .collect();
+        for task in batch {
+            task.await.expect("preload task panicked")?;
+        }
+        next = batch_end;
+    }
+    Ok(())
+}
+
+fn error_kind(e: &KvError) -> String {
+    match e {
+        KvError::NotFound { .. } => "404".to_string(),
+        KvError::Status { status, .. } => status.as_u16().to_string(),
+        KvError::Transport(e) if e.is_timeout() => "timeout".to_string(),
+        KvError::Transport(_) => "transport".to_string(),
+        KvError::Decode(_) => "decode".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn summarize(mut latencies: Vec<u64>) -> LatencySummary {
+    if latencies.is_empty() {
+        return LatencySummary::default();
+    }
+    latencies.sort_unstable();
+    let at = |q: f64| latencies[((latencies.len() as f64 * q).ceil() as usize).clamp(1, latencies.len()) - 1];
+    LatencySummary {
+        count: latencies.len() as u64,
+        p50_us: at(0.5),
+        p99_us: at(0.99),
+        p999_us: at(0.999),
+        max_us: *latencies.last().expect("not empty"),
+    }
+}
+
+fn random_string(rng: &mut SmallRng, len: usize) -> String {
+    rng.sample_iter(&Alphanumeric).take(len).map(char::from).collect()
+}
+
+/// Picks key indices. Zipfian sampling inverts a precomputed CDF.
+struct KeySampler {
+    keys: usize,
+    cdf: Option<Vec<f64>>,
+}
+
+impl KeySampler {
+    fn new(keys: usize, distribution: KeyDistribution) -> Self {
+        let cdf = match distribution {
+            KeyDistribution::Uniform => None,
+            KeyDistribution::Zipfian { exponent } => {
+                let mut sum = 0.0;
+                let mut cdf: Vec<f64> = (1..=keys)
+                    .map(|rank| {
+                        sum += 1.0 / (rank as f64).powf(exponent);
+                        sum
+                    })
+                    .collect();
+                cdf.iter_mut().for_each(|c| *c /= sum);
+                Some(cdf)
+            }
+        };
+        KeySampler { keys, cdf }
+    }
+
+    fn sample(&self, rng: &mut SmallRng) -> usize {
+        match &self.cdf {
+            None => rng.gen_range(0..self.keys),
+            Some(cdf) => {
+                let u: f64 = rng.gen();
+                cdf.partition_point(|&c| c < u).min(self.keys - 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_index_the_sorted_latencies() {
+        assert_eq!(summarize(Vec::new()), LatencySummary::default());
+        assert_eq!(
+            summarize(vec![7]),
+            LatencySummary { count: 1, p50_us: 7, p99_us: 7, p999_us: 7, max_us: 7 }
+        );
+        // p50 of two samples is the lower one; every higher percentile is the max
+        assert_eq!(
+            summarize(vec![20, 10]),
+            LatencySummary { count: 2, p50_us: 10, p99_us: 20, p999_us: 20, max_us: 20 }
+        );
+        let thousand: Vec<u64> = (1..=1000).rev().collect();
+        assert_eq!(
+            summarize(thousand),
+            LatencySummary { count: 1000, p50_us: 500, p99_us: 990, p999_us: 999, max_us: 1000 }
+        );
+    }
+
+    #[test]
+    fn zipfian_cdf_rises_to_one() {
+        for (keys, exponent) in [(1, 1.0), (10, 0.0), (1000, 0.99), (1000, 2.0)] {
+            let cdf = KeySampler::new(keys, KeyDistribution::Zipfian { exponent }).cdf.unwrap();
+            assert_eq!(cdf.len(), keys);
+            assert!(cdf.windows(2).all(|pair| pair[0] < pair[1]), "{} keys, exponent {}", keys, exponent);
+            assert_eq!(*cdf.last().unwrap(), 1.0);
+        }
+        assert!(KeySampler::new(10, KeyDistribution::Uniform).cdf.is_none());
+    }
+
+    #[test]
+    fn samples_stay_in_the_key_space_and_favour_low_ranks() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let sampler = KeySampler::new(100, KeyDistribution::Zipfian { exponent: 1.0 });
+        let mut hits = vec![0u32; 100];
+        for _ in 0..10_000 {
+            hits[sampler.sample(&mut rng)] += 1;
+        }
+        assert!(hits[0] > hits[9] && hits[9] > hits[99]);
+
+        let uniform = KeySampler::new(3, KeyDistribution::Uniform);
+        assert!((0..100).all(|_| uniform.sample(&mut rng) < 3));
+    }
+}
@@ -0,0 +1,58 @@
+// src/chaos.rs
+
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::Parser;
+use rust_key_store::server::fault_proxy::{self, FaultConfig};
+use tokio::net::TcpListener;
+
+/// Fault-injecting proxy to put in front of a key server: point a router's
+/// route at it instead of at the key server.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Key server to forward to (e.g., http://localhost:3001)
+    #[clap(short, long, env = "KVS_CHAOS_TARGET")]
+    target: String,
+
+    /// Port to bind to
+    #[clap(short, long, env = "KVS_CHAOS_PORT", default_value_t = 4000)]
+    port: u16,
+
+    /// Address to bind to
+    #[clap(short, long, env = "KVS_CHAOS_BIND", default_value = "127.0.0.1")]
+    bind: IpAddr,
+
+    /// TOML file with the faults to inject; without one, requests pass through
+    #[clap(short, long, env = "KVS_CHAOS_CONFIG")]
+    config: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let faults = match &args.config {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read fault config {:?}: {}", path, e));
+            toml::from_str::<FaultConfig>(&text).unwrap_or_else(|e| panic!("Invalid fault config {:?}: {}", path, e))
+        }
+        None => FaultConfig::default(),
+    };
+    if let Err(e) = faults.validate() {
+        eprintln!("Invalid fault config: {}", e);
+        std::process::exit(1);
+    }
+
+    let listener = TcpListener::bind((args.bind, args.port))
+        .await
+        .unwrap_or_else(|e| panic!("Failed to bind {}:{}: {}", args.bind, args.port, e));
+    println!("🐒 Fault proxy listening on http://{}, forwarding to {}", listener.local_addr().unwrap(), args.target);
+    println!("   {:?}", faults);
+
+    if let Err(e) = fault_proxy::serve(listener, args.target, faults).await {
+        eprintln!("Fault proxy failed: {}", e);
+        std::process::exit(1);
+    }
+}
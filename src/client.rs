@@ -1,9 +1,29 @@
 // src/bin/client.rs
 
-use clap::{Parser, Subcommand};
-use reqwest::Client;
-use serde_json::Value;
-use std::io::{self, Write};
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_key_store::{
+    bench::{self, BenchConfig, BenchReport, KeyDistribution},
+    dump::{ConflictPolicy, ImportSummary},
+    lease::MAX_TTL,
+    query::Query,
+    Increment, KvClient, KvError,
+};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::FileHistory,
+    validate::Validator,
+    CompletionType, Context, Editor, Helper,
+};
+use serde_json::{json, Number, Value};
+use similar::TextDiff;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -12,6 +32,30 @@ struct Cli {
     #[clap(short, long, default_value = "http://localhost:3000")]
     server: String,
 
+    /// Request timeout in seconds
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// Bearer token sent with every request
+    #[clap(long, env = "KVS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Routes file (router format); send key requests straight to the owning shard
+    #[clap(long, conflicts_with = "direct")]
+    routes: Option<PathBuf>,
+
+    /// Fetch the routing table from the router at --server and talk to shards directly
+    #[clap(long)]
+    direct: bool,
+
+    /// How to print results; `text` is the human-readable default
+    #[clap(long, value_enum, env = "KVS_OUTPUT", default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print JSON on a single line instead of indented
+    #[clap(long)]
+    compact: bool,
+
     #[clap(subcommand)]
     command: Option<Commands>,
 
@@ -25,197 +69,1360 @@ enum Commands {
     /// Set a new key (fails if key exists)
     Set {
         key: String,
-        #[clap(value_parser = parse_json)]
-        value: Value,
+        /// JSON value, `@path` to read it from a file, or `-` to read stdin (`@@` for a literal `@`)
+        value: String,
+        /// Store the input as a JSON string instead of parsing it as JSON
+        #[clap(long, visible_alias = "string")]
+        raw: bool,
     },
     /// Update an existing key (fails if key doesn't exist)
     Update {
         key: String,
-        #[clap(value_parser = parse_json)]
-        value: Value,
+        /// JSON value, `@path` to read it from a file, or `-` to read stdin (`@@` for a literal `@`)
+        value: String,
+        /// Store the input as a JSON string instead of parsing it as JSON
+        #[clap(long, visible_alias = "string")]
+        raw: bool,
     },
     /// Get a key's value
     Get {
         key: String,
+        /// Print only part of the value: a JSON Pointer (`/a/0`) or a jq-like path (`.a[0]`, `.items[].name`)
+        #[clap(short, long)]
+        query: Option<String>,
     },
     /// Delete a key
     Delete {
         key: String,
     },
+    /// Add to a number stored at a key, atomically on the server; prints the new number
+    Incr {
+        key: String,
+        /// Amount to add; may be negative or fractional
+        #[clap(default_value = "1", allow_hyphen_values = true, value_parser = parse_number)]
+        by: Number,
+        #[clap(flatten)]
+        options: IncrOptions,
+    },
+    /// Subtract from a number stored at a key, atomically on the server; prints the new number
+    Decr {
+        key: String,
+        /// Amount to subtract
+        #[clap(default_value = "1", allow_hyphen_values = true, value_parser = parse_number)]
+        by: Number,
+        #[clap(flatten)]
+        options: IncrOptions,
+    },
+    /// Edit a key's value in $VISUAL/$EDITOR; refuses to overwrite concurrent changes
+    Edit {
+        key: String,
+    },
+    /// Copy a key on the server (or every key under a prefix with --prefix)
+    Cp {
+        source: String,
+        destination: String,
+        #[clap(flatten)]
+        options: TransferOptions,
+    },
+    /// Rename a key on the server (or every key under a prefix with --prefix)
+    Mv {
+        source: String,
+        destination: String,
+        #[clap(flatten)]
+        options: TransferOptions,
+    },
+    /// Run a command while holding a lease on a named lock. Exits 4 if the lock stays held and
+    /// 5 if the lease is lost; otherwise exits with the command's own status, whatever it is
+    Lock {
+        name: String,
+        #[clap(flatten)]
+        options: LockOptions,
+        /// The command and its arguments, after `--`
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Dump every key as NDJSON (one {"key": ..., "value": ...} object per line)
+    Export {
+        /// Write the dump to this file instead of stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Load keys from an NDJSON dump produced by `export`
+    Import {
+        /// Dump file, or `-` to read stdin
+        file: String,
+        /// What to do with keys that already exist
+        #[clap(long, value_enum, default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+    },
+    /// Generate load and report throughput, latency percentiles and errors
+    Bench {
+        /// Concurrent workers, each with one request in flight
+        #[clap(short, long, default_value_t = 16)]
+        concurrency: usize,
+        /// Number of distinct keys; they are created before the run if missing
+        #[clap(short, long, default_value_t = 1000)]
+        keys: usize,
+        /// Key prefix; through a router it must match a route
+        #[clap(long, default_value = "bench.")]
+        prefix: String,
+        /// Length in bytes of the string value written
+        #[clap(long, default_value_t = 100)]
+        value_size: usize,
+        /// Fraction of operations that are reads (the rest are updates)
+        #[clap(long, default_value_t = 0.9)]
+        read_ratio: f64,
+        /// Run time in seconds, not counting the initial key creation
+        #[clap(short, long, default_value_t = 10)]
+        duration: u64,
+        #[clap(long, value_enum, default_value_t = Distribution::Uniform)]
+        distribution: Distribution,
+        /// Skew of the zipfian distribution
+        #[clap(long, default_value_t = 0.99)]
+        zipf_exponent: f64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Distribution {
+    /// Every key equally likely
+    Uniform,
+    /// A few hot keys get most of the traffic
+    Zipfian,
+}
+
+#[derive(Clone, Copy, clap::Args)]
+struct TransferOptions {
+    /// Replace destination keys that already exist
+    #[clap(long)]
+    overwrite: bool,
+    /// Treat source and destination as key prefixes and transfer every key under the source
+    #[clap(short = 'r', long)]
+    prefix: bool,
+}
+
+#[derive(Clone, clap::Args)]
+struct IncrOptions {
+    /// Change the number at this JSON Pointer inside the value (e.g. `/stats/hits`)
+    #[clap(short, long)]
+    pointer: Option<String>,
+    /// Create the key (or the pointer's object member) if it is missing, starting from --initial
+    #[clap(long)]
+    create: bool,
+    /// Where a created number starts
+    #[clap(long, default_value = "0", allow_hyphen_values = true, value_parser = parse_number)]
+    initial: Number,
+}
+
+#[derive(Clone, clap::Args)]
+struct LockOptions {
+    /// Lease length in seconds, from 1 to 86400; renewed every third of it while the command runs
+    #[clap(long, default_value = "30", value_parser = parse_seconds)]
+    ttl: Duration,
+    /// Give up if the lock is still held after this many seconds [default: wait until it is free]
+    #[clap(long, value_parser = parse_seconds)]
+    wait: Option<Duration>,
+    /// Shown to anyone who finds the lock held [default: kvs-client pid <pid>]
+    #[clap(long)]
+    owner: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnConflict {
+    /// Keep the existing value
+    Skip,
+    /// Replace the existing value
+    Overwrite,
+    /// Import nothing if any key exists
+    Fail,
+}
+
+impl From<OnConflict> for ConflictPolicy {
+    fn from(on_conflict: OnConflict) -> Self {
+        match on_conflict {
+            OnConflict::Skip => ConflictPolicy::Skip,
+            OnConflict::Overwrite => ConflictPolicy::Overwrite,
+            OnConflict::Fail => ConflictPolicy::Fail,
+        }
+    }
 }
 
 fn parse_json(s: &str) -> Result<Value, serde_json::Error> {
     serde_json::from_str(s)
 }
 
-async fn do_set(client: &Client, base_url: &str, key: &str, value: &Value) -> anyhow::Result<()> {
-    let url = format!("{}/keys/{}", base_url.trim_end_matches('/'), urlencoding::encode(key));
-    let res = client.post(&url).json(value).send().await?;
-    let status = res.status();
-    let body: serde_json::Value = res.json().await?;
+fn parse_number(s: &str) -> Result<Number, String> {
+    serde_json::from_str(s.trim()).map_err(|_| format!("not a number: {}", s))
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.trim()
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("not a number of seconds: {}", s))
+}
 
-    match status {
-        reqwest::StatusCode::CREATED => {
-            println!("OK: {}", body["uri"].as_str().unwrap_or("/unknown"));
+/// `-n`, or `None` for a u64 too large to negate.
+fn negate(n: &Number) -> Option<Number> {
+    match n.as_i64() {
+        Some(i) => i.checked_neg().map(Number::from),
+        None if n.is_f64() => Number::from_f64(-n.as_f64()?),
+        None => None,
+    }
+}
+
+/// Resolves a `set`/`update` value argument: `-` reads stdin, `@path` reads a
+/// file, `@@text` is `@text` itself, anything else is the value itself. With
+/// `raw` the text is stored as a JSON string, less the single trailing newline
+/// that `echo` and editors leave on stdin and files; otherwise it must be JSON.
+fn read_value(input: &str, raw: bool) -> Result<Value, Failure> {
+    let text = if input == "-" {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        strip_newline(buf)
+    } else if let Some(literal) = input.strip_prefix("@@") {
+        format!("@{}", literal)
+    } else if let Some(path) = input.strip_prefix('@') {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Failure::new(format!("failed to read {}: {}", path, e)).with_code(EXIT_USAGE))?;
+        strip_newline(text)
+    } else {
+        input.to_string()
+    };
+
+    if raw {
+        return Ok(Value::String(text));
+    }
+    parse_json(&text).map_err(|e| Failure::new(format!("Invalid JSON: {}", e)).with_code(EXIT_USAGE))
+}
+
+/// `text` without one trailing `\n` or `\r\n`.
+fn strip_newline(mut text: String) -> String {
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
         }
-        reqwest::StatusCode::CONFLICT => {
-            eprintln!("Error: key already exists. Use 'update' to modify.");
-            std::process::exit(1);
+    }
+    text
+}
+
+/// What a successful command produced. One-off mode and the REPL print it the same way.
+enum Outcome {
+    /// A write succeeded; carries the key's URI
+    Written(String),
+    /// A value to show
+    Value(Value),
+    Imported(ImportSummary),
+    /// Keys copied or renamed, as (source, destination)
+    Transferred(Vec<(String, String)>),
+    Bench(BenchReport),
+    /// Nothing was changed; carries a note for the user
+    Unchanged(String),
+    /// Output was already streamed (e.g. `export`)
+    Done,
+}
+
+// Exit codes of one-off commands. Scripts branch on these, so they must not change.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_ALREADY_EXISTS: i32 = 4;
+const EXIT_PRECONDITION_FAILED: i32 = 5;
+const EXIT_TRANSPORT: i32 = 6;
+const EXIT_AUTH: i32 = 7;
+
+/// A failed command: what to tell the user, plus optional JSON details from the server.
+struct Failure {
+    message: String,
+    details: Option<Value>,
+    /// Process exit code in one-off mode
+    code: i32,
+}
+
+impl Failure {
+    fn new(message: impl Into<String>) -> Self {
+        Failure { message: message.into(), details: None, code: EXIT_FAILURE }
+    }
+
+    fn with_code(mut self, code: i32) -> Self {
+        self.code = code;
+        self
+    }
+}
+
+impl From<KvError> for Failure {
+    fn from(e: KvError) -> Self {
+        let code = match &e {
+            KvError::NotFound { .. } => EXIT_NOT_FOUND,
+            KvError::AlreadyExists { .. } => EXIT_ALREADY_EXISTS,
+            // Other 409s mean the server's state moved under the request, e.g. a
+            // source that changed mid-rename; commands that know better map their own
+            KvError::PreconditionFailed { .. } | KvError::Conflict { .. } | KvError::LeaseLost { .. } => {
+                EXIT_PRECONDITION_FAILED
+            }
+            KvError::Transport(_) => EXIT_TRANSPORT,
+            KvError::Status { status, .. }
+                if *status == reqwest::StatusCode::UNAUTHORIZED || *status == reqwest::StatusCode::FORBIDDEN =>
+            {
+                EXIT_AUTH
+            }
+            KvError::InvalidInput(_) => EXIT_USAGE,
+            _ => EXIT_FAILURE,
+        };
+        Failure::new(e.to_string()).with_code(code)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::new(e.to_string())
+    }
+}
+
+type CommandResult = Result<Outcome, Failure>;
+
+async fn do_set(client: &KvClient, key: &str, value: &Value) -> CommandResult {
+    match client.set(key, value).await {
+        Ok(uri) => Ok(Outcome::Written(uri)),
+        Err(KvError::AlreadyExists { .. }) => {
+            Err(Failure::new("key already exists. Use 'update' to modify.").with_code(EXIT_ALREADY_EXISTS))
         }
-        _ => {
-            eprintln!("Error: {} {:?}", status, body);
-            std::process::exit(1);
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn do_update(client: &KvClient, key: &str, value: &Value) -> CommandResult {
+    match client.update(key, value).await {
+        Ok(uri) => Ok(Outcome::Written(uri)),
+        Err(KvError::NotFound { .. }) => {
+            Err(Failure::new("key does not exist. Use 'set' to create.").with_code(EXIT_NOT_FOUND))
         }
+        Err(e) => Err(e.into()),
     }
-    Ok(())
 }
 
-async fn do_update(client: &Client, base_url: &str, key: &str, value: &Value) -> anyhow::Result<()> {
-    let url = format!("{}/keys/{}", base_url.trim_end_matches('/'), urlencoding::encode(key));
-    let res = client.put(&url).json(value).send().await?;
-    let status = res.status();
-    let body: serde_json::Value = res.json().await?;
+async fn do_get(client: &KvClient, key: &str, query: Option<&str>) -> CommandResult {
+    let Some(query) = query else {
+        return match client.get::<Value>(key).await {
+            Ok(value) => Ok(Outcome::Value(value)),
+            Err(KvError::NotFound { .. }) => Err(Failure::new("key not found").with_code(EXIT_NOT_FOUND)),
+            Err(e) => Err(e.into()),
+        };
+    };
+    let query = Query::parse(query).map_err(|e| Failure::new(e).with_code(EXIT_USAGE))?;
+
+    // A single location is resolved by the server; anything else needs the whole value
+    let result = match query.to_pointer() {
+        Some(pointer) => client
+            .get_pointer::<Value>(key, &pointer)
+            .await
+            .map(|value| value.unwrap_or(Value::Null)),
+        None => client.get::<Value>(key).await.map(|value| query.apply(&value)),
+    };
+    match result {
+        Ok(value) => Ok(Outcome::Value(value)),
+        Err(KvError::NotFound { .. }) => Err(Failure::new("key not found").with_code(EXIT_NOT_FOUND)),
+        Err(e) => Err(e.into()),
+    }
+}
 
-    match status {
-        reqwest::StatusCode::OK => {
-            println!("OK: {}", body["uri"].as_str().unwrap_or("/unknown"));
+async fn do_delete(client: &KvClient, key: &str) -> CommandResult {
+    match client.delete(key).await {
+        Ok(uri) => Ok(Outcome::Written(uri)),
+        Err(KvError::NotFound { .. }) => Err(Failure::new("key not found").with_code(EXIT_NOT_FOUND)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn do_incr(client: &KvClient, key: &str, by: Number, options: IncrOptions) -> CommandResult {
+    let mut increment = Increment::by(by);
+    if let Some(pointer) = options.pointer {
+        increment = increment.at(pointer);
+    }
+    if options.create {
+        increment = increment.or_create(options.initial);
+    }
+    match client.incr(key, &increment).await {
+        Ok(incremented) => Ok(Outcome::Value(Value::Number(incremented.value))),
+        Err(KvError::NotFound { .. }) => {
+            Err(Failure::new("key not found. Use '--create' to start it at 0.").with_code(EXIT_NOT_FOUND))
         }
-        reqwest::StatusCode::NOT_FOUND => {
-            eprintln!("Error: key does not exist. Use 'set' to create.");
-            std::process::exit(1);
+        Err(KvError::Status { status, body }) if status == reqwest::StatusCode::NOT_FOUND => Err(Failure {
+            message: "nothing at that pointer. Use '--create' to start it at 0.".to_string(),
+            details: Some(body),
+            code: EXIT_NOT_FOUND,
+        }),
+        Err(KvError::Status { status, body }) if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+            Err(Failure { message: "value is not a number".to_string(), details: Some(body), code: EXIT_FAILURE })
         }
-        _ => {
-            eprintln!("Error: {} {:?}", status, body);
-            std::process::exit(1);
+        Err(KvError::Conflict { body }) => {
+            Err(Failure { message: "result would overflow".to_string(), details: Some(body), code: EXIT_FAILURE })
         }
+        Err(e) => Err(e.into()),
     }
-    Ok(())
 }
 
-async fn do_get(client: &Client, base_url: &str, key: &str) -> anyhow::Result<()> {
-    let url = format!("{}/keys/{}", base_url.trim_end_matches('/'), urlencoding::encode(key));
-    let res = client.get(&url).send().await?;
-    let status = res.status();
+async fn do_bench(client: &KvClient, config: BenchConfig) -> CommandResult {
+    eprintln!(
+        "Benchmarking {} for {}s with {} workers over {} keys...",
+        client.base_url(),
+        config.duration.as_secs(),
+        config.concurrency,
+        config.keys
+    );
+    Ok(Outcome::Bench(bench::run(client, &config).await?))
+}
 
-    if status == reqwest::StatusCode::NOT_FOUND {
-        eprintln!("Error: key not found");
-        std::process::exit(1);
+/// Whether `key` is `prefix` or lies under it, on a `.` boundary: `user`
+/// covers `user` and `user.x` but not `users.x`.
+fn under_prefix(key: &str, prefix: &str) -> bool {
+    match key.strip_prefix(prefix) {
+        Some(rest) => prefix.is_empty() || prefix.ends_with('.') || rest.is_empty() || rest.starts_with('.'),
+        None => false,
     }
+}
 
-    let body: serde_json::Value = res.json().await?;
-    println!("{}", serde_json::to_string_pretty(&body)?);
-    Ok(())
+/// Copies or renames one key, or with `--prefix` every key under `source`. A
+/// prefix transfer checks for existing destinations before touching anything;
+/// each key is then transferred atomically, but the prefix as a whole is not.
+async fn do_transfer(
+    client: &KvClient,
+    rename: bool,
+    source: &str,
+    destination: &str,
+    options: TransferOptions,
+) -> CommandResult {
+    let pairs: Vec<(String, String)> = if options.prefix {
+        let mut keys = client.list(source, None).await?;
+        keys.retain(|key| under_prefix(key, source));
+        if keys.is_empty() {
+            return Err(Failure::new(format!("no keys under {}", source)).with_code(EXIT_NOT_FOUND));
+        }
+        keys.into_iter()
+            .map(|key| {
+                let target = format!("{}{}", destination, &key[source.len()..]);
+                (key, target)
+            })
+            .collect()
+    } else {
+        vec![(source.to_string(), destination.to_string())]
+    };
+
+    if options.prefix && !options.overwrite {
+        let existing = client.list(destination, None).await?;
+        let conflicts: Vec<&str> = pairs
+            .iter()
+            .map(|(_, target)| target.as_str())
+            .filter(|target| existing.iter().any(|key| key == target))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(Failure {
+                message: "destination keys already exist; nothing was changed. Use '--overwrite' to replace them.".to_string(),
+                details: Some(json!({ "conflicts": conflicts })),
+                code: EXIT_ALREADY_EXISTS,
+            });
+        }
+    }
+
+    for (done, (from, to)) in pairs.iter().enumerate() {
+        let result = if rename {
+            client.rename(from, to, options.overwrite).await
+        } else {
+            client.copy(from, to, options.overwrite).await
+        };
+        let failure = match result {
+            Ok(_) => continue,
+            Err(KvError::AlreadyExists { .. }) => {
+                Failure::new(format!("{} already exists. Use '--overwrite' to replace it.", to))
+                    .with_code(EXIT_ALREADY_EXISTS)
+            }
+            Err(KvError::NotFound { .. }) => {
+                Failure::new(format!("key not found: {}", from)).with_code(EXIT_NOT_FOUND)
+            }
+            Err(e) => e.into(),
+        };
+        if pairs.len() == 1 {
+            return Err(failure);
+        }
+        return Err(Failure {
+            message: format!("{} of {} keys transferred; stopped at {}: {}", done, pairs.len(), from, failure.message),
+            ..failure
+        });
+    }
+    Ok(Outcome::Transferred(pairs))
+}
+
+/// Shortest `lock --ttl`; renewals go out every third of it.
+const MIN_LOCK_TTL: Duration = Duration::from_secs(1);
+
+/// Runs `command` while holding a lease on `name`, with the fencing token in
+/// `KVS_LOCK_TOKEN`. If the lease is lost the command is killed, since another
+/// holder may already be running.
+async fn do_lock(client: &KvClient, name: &str, options: LockOptions, command: &[String]) -> CommandResult {
+    if !(MIN_LOCK_TTL..=MAX_TTL).contains(&options.ttl) {
+        let message = format!("--ttl must be between {} and {} seconds", MIN_LOCK_TTL.as_secs(), MAX_TTL.as_secs());
+        return Err(Failure::new(message).with_code(EXIT_USAGE));
+    }
+    let owner = options.owner.unwrap_or_else(|| format!("kvs-client pid {}", std::process::id()));
+    let wait = options.wait.unwrap_or(Duration::MAX);
+    let Some(lease) = client.acquire_lock(name, options.ttl, wait, Some(&owner)).await? else {
+        return Err(Failure::new(format!("{} is held by someone else", name)).with_code(EXIT_ALREADY_EXISTS));
+    };
+
+    let (program, args) = command.split_first().expect("clap requires a command");
+    let spawned = tokio::process::Command::new(program)
+        .args(args)
+        .env("KVS_LOCK_NAME", name)
+        .env("KVS_LOCK_TOKEN", lease.token.to_string())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = client.release_lock(&lease).await;
+            return Err(Failure::new(format!("failed to start {:?}: {}", program, e)));
+        }
+    };
+
+    let mut renewals = tokio::time::interval(options.ttl / 3);
+    renewals.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    renewals.tick().await;
+    let mut renewed = Instant::now();
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            _ = renewals.tick() => match client.renew_lock(&lease).await {
+                Ok(()) => renewed = Instant::now(),
+                Err(e) if matches!(e, KvError::LeaseLost { .. }) || renewed.elapsed() >= options.ttl => {
+                    let _ = child.kill().await;
+                    return Err(Failure::new(format!("lost the lease on {}; the command was stopped", name))
+                        .with_code(EXIT_PRECONDITION_FAILED));
+                }
+                // Keep trying while the lease may still be ours
+                Err(e) => eprintln!("Warning: could not renew the lease on {}: {}", name, e),
+            },
+            // The command got the Ctrl+C too; hold the lease until it exits
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    };
+
+    match client.release_lock(&lease).await {
+        Ok(()) => {}
+        Err(KvError::LeaseLost { .. }) => {
+            eprintln!("Warning: the lease on {} expired before the command finished", name)
+        }
+        Err(e) => eprintln!("Warning: could not release {}; it expires on its own: {}", name, e),
+    }
+    if status.success() {
+        return Ok(Outcome::Done);
+    }
+    Err(Failure::new(format!("command exited with {}", status)).with_code(status.code().unwrap_or(EXIT_FAILURE)))
+}
+
+async fn do_export(client: &KvClient, output: Option<&PathBuf>) -> CommandResult {
+    match output {
+        Some(path) => {
+            let mut file = std::fs::File::create(path)?;
+            client.export_to(&mut file).await?;
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            client.export_to(&mut stdout).await?;
+            stdout.flush()?;
+        }
+    }
+    Ok(Outcome::Done)
 }
 
-async fn do_delete(client: &Client, base_url: &str, key: &str) -> anyhow::Result<()> {
-    let url = format!("{}/keys/{}", base_url.trim_end_matches('/'), urlencoding::encode(key));
-    let res = client.delete(&url).send().await?;
-    let status = res.status();
+async fn do_import(client: &KvClient, file: &str, on_conflict: OnConflict) -> CommandResult {
+    let dump = if file == "-" {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        buf
+    } else {
+        std::fs::read_to_string(file)?
+    };
 
-    if status == reqwest::StatusCode::NOT_FOUND {
-        eprintln!("Error: key not found");
-        std::process::exit(1);
+    match client.import(dump, on_conflict.into()).await {
+        Ok(summary) => Ok(Outcome::Imported(summary)),
+        Err(KvError::Conflict { body }) => Err(Failure {
+            message: "keys already exist. Use '--on-conflict skip' or '--on-conflict overwrite'.".to_string(),
+            details: Some(body),
+            code: EXIT_ALREADY_EXISTS,
+        }),
+        Err(e) => Err(e.into()),
     }
+}
 
-    let body: serde_json::Value = res.json().await?;
-    println!("OK: {}", body["uri"].as_str().unwrap_or("/unknown"));
+fn pretty_json(value: &Value) -> String {
+    let mut text = serde_json::to_string_pretty(value).expect("Value always serializes");
+    text.push('\n');
+    text
+}
+
+/// `$VISUAL`, then `$EDITOR`, then a platform default. May include arguments (`code --wait`).
+fn editor_command() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string())
+}
+
+async fn run_editor(path: &std::path::Path) -> Result<(), Failure> {
+    let editor = editor_command();
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| Failure::new("$EDITOR is empty"))?;
+    let status = tokio::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .await
+        .map_err(|e| Failure::new(format!("failed to start editor {:?}: {}", editor, e)))?;
+    if !status.success() {
+        return Err(Failure::new(format!("editor {:?} exited with {}; nothing was saved", editor, status)));
+    }
     Ok(())
 }
 
-async fn run_repl(server: String) -> anyhow::Result<()> {
-    let client = Client::new();
-    let mut rl = rustyline::DefaultEditor::new()?;
-    println!("kvs-client REPL (server: {})", server);
-    println!("Commands: set <key> <json>, update <key> <json>, get <key>, delete <key>, exit");
-    loop {
-        let readline = rl.readline("> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
-                let parts: Vec<&str> = line.trim().split_whitespace().collect();
-                if parts.is_empty() {
-                    continue;
+fn confirm(prompt: &str) -> io::Result<bool> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(!answer.trim().eq_ignore_ascii_case("n"))
+}
+
+/// Opens the value in an editor and writes it back only if the key is still at
+/// the version that was opened. If someone else changed it in the meantime the
+/// edit is kept in a file and the concurrent change is shown as a diff.
+async fn do_edit(client: &KvClient, key: &str) -> CommandResult {
+    let original = match client.get_versioned::<Value>(key).await {
+        Ok(original) => original,
+        Err(KvError::NotFound { .. }) => return Err(Failure::new("key not found").with_code(EXIT_NOT_FOUND)),
+        Err(e) => return Err(e.into()),
+    };
+    let original_text = pretty_json(&original.value);
+
+    let mut file = tempfile::Builder::new()
+        .prefix("kvs-edit-")
+        .suffix(".json")
+        .tempfile()?;
+    file.write_all(original_text.as_bytes())?;
+    file.flush()?;
+
+    let edited = loop {
+        run_editor(file.path()).await?;
+        let text = std::fs::read_to_string(file.path())?;
+        match serde_json::from_str::<Value>(&text) {
+            Ok(value) => break value,
+            Err(e) => {
+                eprintln!("Invalid JSON: {}", e);
+                if !confirm("Re-open the editor? [Y/n] ")? {
+                    return Err(Failure::new("edit aborted; nothing was saved"));
                 }
+            }
+        }
+    };
+    if edited == original.value {
+        return Ok(Outcome::Unchanged("No changes.".to_string()));
+    }
 
-                match parts[0] {
-                    "exit" | "quit" => break,
-                    "set" if parts.len() >= 3 => {
-                        let key = parts[1];
-                        let json_str = parts[2..].join(" ");
-                        match serde_json::from_str::<Value>(&json_str) {
-                            Ok(value) => {
-                                if let Err(e) = do_set(&client, &server, key, &value).await {
-                                    eprintln!("Error: {}", e);
-                                }
-                            }
-                            Err(e) => eprintln!("Invalid JSON: {}", e),
-                        }
-                    }
-                    "update" if parts.len() >= 3 => {
-                        let key = parts[1];
-                        let json_str = parts[2..].join(" ");
-                        match serde_json::from_str::<Value>(&json_str) {
-                            Ok(value) => {
-                                if let Err(e) = do_update(&client, &server, key, &value).await {
-                                    eprintln!("Error: {}", e);
-                                }
-                            }
-                            Err(e) => eprintln!("Invalid JSON: {}", e),
-                        }
-                    }
-                    "get" if parts.len() == 2 => {
-                        let key = parts[1];
-                        let _ = do_get(&client, &server, key).await;
+    match client.update_if(key, &edited, original.version).await {
+        Ok(uri) => Ok(Outcome::Written(uri)),
+        Err(KvError::PreconditionFailed { body, .. }) => {
+            let (_, kept) = file.keep().map_err(|e| Failure::new(e.to_string()))?;
+            let current_text = pretty_json(&body["value"]);
+            let diff = TextDiff::from_lines(&original_text, &current_text)
+                .unified_diff()
+                .header("when opened", "on server now")
+                .to_string();
+            Err(Failure::new(format!(
+                "{} was changed while you were editing; nothing was saved.\nYour edit is in {}\nChanges made on the server:\n{}",
+                key,
+                kept.display(),
+                diff
+            ))
+            .with_code(EXIT_PRECONDITION_FAILED))
+        }
+        Err(KvError::NotFound { .. }) => {
+            let (_, kept) = file.keep().map_err(|e| Failure::new(e.to_string()))?;
+            Err(Failure::new(format!(
+                "{} was deleted while you were editing; nothing was saved.\nYour edit is in {}",
+                key,
+                kept.display()
+            ))
+            .with_code(EXIT_NOT_FOUND))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn execute(client: &KvClient, command: Commands) -> CommandResult {
+    match command {
+        Commands::Set { key, value, raw } => do_set(client, &key, &read_value(&value, raw)?).await,
+        Commands::Update { key, value, raw } => do_update(client, &key, &read_value(&value, raw)?).await,
+        Commands::Get { key, query } => do_get(client, &key, query.as_deref()).await,
+        Commands::Delete { key } => do_delete(client, &key).await,
+        Commands::Incr { key, by, options } => do_incr(client, &key, by, options).await,
+        Commands::Decr { key, by, options } => {
+            let by = negate(&by).ok_or_else(|| Failure::new("amount is too large").with_code(EXIT_USAGE))?;
+            do_incr(client, &key, by, options).await
+        }
+        Commands::Edit { key } => do_edit(client, &key).await,
+        Commands::Cp { source, destination, options } => {
+            do_transfer(client, false, &source, &destination, options).await
+        }
+        Commands::Mv { source, destination, options } => {
+            do_transfer(client, true, &source, &destination, options).await
+        }
+        Commands::Lock { name, options, command } => do_lock(client, &name, options, &command).await,
+        Commands::Export { output } => do_export(client, output.as_ref()).await,
+        Commands::Import { file, on_conflict } => do_import(client, &file, on_conflict).await,
+        Commands::Bench {
+            concurrency,
+            keys,
+            prefix,
+            value_size,
+            read_ratio,
+            duration,
+            distribution,
+            zipf_exponent,
+        } => {
+            let distribution = match distribution {
+                Distribution::Uniform => KeyDistribution::Uniform,
+                Distribution::Zipfian => KeyDistribution::Zipfian { exponent: zipf_exponent },
+            };
+            let config = BenchConfig {
+                concurrency,
+                keys,
+                prefix,
+                value_size,
+                read_ratio,
+                duration: Duration::from_secs(duration),
+                distribution,
+            };
+            do_bench(client, config).await
+        }
+    }
+}
+
+// ========================
+// Output
+// ========================
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable messages and pretty-printed values
+    Text,
+    /// Results and errors as JSON objects
+    Json,
+    /// Results and errors as YAML
+    Yaml,
+    /// Bare values: strings unquoted, everything else as compact JSON
+    Raw,
+    /// Objects and arrays of objects as aligned columns
+    Table,
+}
+
+/// How results and failures are printed. One-off mode and the REPL share it.
+#[derive(Clone, Copy)]
+struct Output {
+    format: OutputFormat,
+    compact: bool,
+}
+
+impl Output {
+    fn print_outcome(&self, outcome: &Outcome) {
+        if self.format == OutputFormat::Text {
+            match outcome {
+                Outcome::Written(uri) => println!("OK: {}", uri),
+                Outcome::Value(value) => println!("{}", self.json(value)),
+                Outcome::Imported(summary) => {
+                    println!("OK: imported {}, skipped {}", summary.imported, summary.skipped)
+                }
+                Outcome::Unchanged(note) => println!("{}", note),
+                Outcome::Transferred(pairs) => {
+                    for (from, to) in pairs {
+                        println!("OK: {} -> {}", from, to);
                     }
-                    "delete" if parts.len() == 2 => {
-                        let key = parts[1];
-                        let _ = do_delete(&client, &server, key).await;
+                }
+                Outcome::Bench(report) => print_bench(report),
+                Outcome::Done => {}
+            }
+            return;
+        }
+
+        let value = match outcome {
+            Outcome::Written(uri) => json!({ "status": "ok", "uri": uri }),
+            Outcome::Value(value) => value.clone(),
+            Outcome::Imported(summary) => json!({ "imported": summary.imported, "skipped": summary.skipped }),
+            Outcome::Unchanged(note) => json!({ "status": "unchanged", "message": note }),
+            Outcome::Transferred(pairs) => pairs
+                .iter()
+                .map(|(from, to)| json!({ "source": from, "destination": to }))
+                .collect(),
+            Outcome::Bench(report) => serde_json::to_value(report).expect("report always serializes"),
+            Outcome::Done => return,
+        };
+        if self.format == OutputFormat::Raw {
+            // A write has nothing to show but where it went
+            if let Outcome::Written(uri) = outcome {
+                println!("{}", uri);
+                return;
+            }
+        }
+        print!("{}", self.render(&value));
+    }
+
+    /// Text mode writes `Error: ...` to stderr; structured modes write the
+    /// failure in the chosen format, including its exit code.
+    fn print_failure(&self, failure: &Failure) {
+        match self.format {
+            OutputFormat::Json | OutputFormat::Yaml => {
+                let mut error = json!({ "error": failure.message, "code": failure.code });
+                if let Some(details) = &failure.details {
+                    error["details"] = details.clone();
+                }
+                eprint!("{}", self.render(&error));
+            }
+            _ => {
+                eprintln!("Error: {}", failure.message);
+                if let Some(details) = &failure.details {
+                    eprintln!("{}", self.json(details));
+                }
+            }
+        }
+    }
+
+    fn json(&self, value: &Value) -> String {
+        let text = if self.compact {
+            serde_json::to_string(value)
+        } else {
+            serde_json::to_string_pretty(value)
+        };
+        text.expect("Value always serializes")
+    }
+
+    /// `value` in the chosen format, newline-terminated.
+    fn render(&self, value: &Value) -> String {
+        match self.format {
+            OutputFormat::Text | OutputFormat::Json => format!("{}\n", self.json(value)),
+            OutputFormat::Yaml => serde_yaml_ng::to_string(value).expect("Value always serializes"),
+            OutputFormat::Raw => format!("{}\n", cell(value)),
+            OutputFormat::Table => table(value),
+        }
+    }
+}
+
+fn print_bench(report: &BenchReport) {
+    println!(
+        "{} operations in {:.1}s: {:.0} successful ops/s",
+        report.operations, report.duration_secs, report.throughput
+    );
+    println!("{:<8}{:>10}{:>10}{:>10}{:>10}{:>10}", "", "count", "p50", "p99", "p999", "max");
+    for (name, latency) in [("reads", &report.reads), ("writes", &report.writes)] {
+        println!(
+            "{:<8}{:>10}{:>10}{:>10}{:>10}{:>10}",
+            name,
+            latency.count,
+            format_micros(latency.p50_us),
+            format_micros(latency.p99_us),
+            format_micros(latency.p999_us),
+            format_micros(latency.max_us)
+        );
+    }
+    if report.errors.is_empty() {
+        println!("errors: none");
+    } else {
+        let errors: Vec<String> = report.errors.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+        println!("errors: {} ({})", report.error_count(), errors.join(", "));
+    }
+}
+
+fn format_micros(micros: u64) -> String {
+    if micros >= 1000 {
+        format!("{:.2}ms", micros as f64 / 1000.0)
+    } else {
+        format!("{}µs", micros)
+    }
+}
+
+/// A value as a single table cell: strings unquoted, everything else compact JSON.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Objects become KEY/VALUE rows, arrays of objects one row per element with a
+/// column per field, other arrays one row per element. Scalars print as a cell.
+fn table(value: &Value) -> String {
+    let rows: Vec<Vec<String>> = match value {
+        Value::Object(map) => std::iter::once(vec!["KEY".to_string(), "VALUE".to_string()])
+            .chain(map.iter().map(|(k, v)| vec![k.clone(), cell(v)]))
+            .collect(),
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+            let mut columns: Vec<&String> = Vec::new();
+            for item in items {
+                for field in item.as_object().expect("checked above").keys() {
+                    if !columns.contains(&field) {
+                        columns.push(field);
                     }
-                    _ => eprintln!("Unknown command. Use: set|update|get|delete|exit"),
                 }
             }
-            Err(rustyline::error::ReadlineError::Interrupted) |
-            Err(rustyline::error::ReadlineError::Eof) => {
-                break;
+            std::iter::once(columns.iter().map(|c| c.to_uppercase()).collect())
+                .chain(items.iter().map(|item| {
+                    columns.iter().map(|c| item.get(c.as_str()).map(cell).unwrap_or_default()).collect()
+                }))
+                .collect()
+        }
+        Value::Array(items) => items.iter().map(|item| vec![cell(item)]).collect(),
+        scalar => vec![vec![cell(scalar)]],
+    };
+
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        for (i, c) in row.iter().enumerate() {
+            let width = c.chars().count();
+            match widths.get_mut(i) {
+                Some(w) => *w = (*w).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+    let mut text = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (i, c) in row.iter().enumerate() {
+            line.push_str(c);
+            line.extend(std::iter::repeat_n(' ', widths[i] - c.chars().count() + 2));
+        }
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+// ========================
+// REPL
+// ========================
+
+const REPL_USAGE: &str =
+    "Commands: set <key> <json>, update <key> <json>, get <key> [| <query>], delete <key>, incr <key> [n], decr <key> [n], edit <key>, exit";
+
+/// Whether `input` ends inside a JSON string or with unclosed `{`/`[`,
+/// in which case the REPL keeps reading lines before parsing it. Input that
+/// closes more than it opened can never become valid, so it is complete.
+fn json_incomplete(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in input.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' if depth == 0 => return false,
+            '}' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    in_string || depth > 0
+}
+
+/// Splits off the first whitespace-delimited word; the rest is returned verbatim
+/// (minus leading whitespace) so JSON keeps its inner spacing.
+fn next_word(input: &str) -> (&str, &str) {
+    let input = input.trim_start();
+    match input.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (input, ""),
+    }
+}
+
+/// Splits off a key: a JSON string when it starts with `"`, so keys with spaces
+/// can be typed, and otherwise the first word.
+fn next_key(input: &str) -> Result<(String, &str), Failure> {
+    let input = input.trim_start();
+    if !input.starts_with('"') {
+        let (key, rest) = next_word(input);
+        return Ok((key.to_string(), rest));
+    }
+    let mut stream = serde_json::Deserializer::from_str(input).into_iter::<String>();
+    match stream.next() {
+        Some(Ok(key)) => Ok((key, input[stream.byte_offset()..].trim_start())),
+        _ => Err(Failure::new("Quoted keys are JSON strings, e.g. \"my key\"").with_code(EXIT_USAGE)),
+    }
+}
+
+/// Parses one REPL command. `Ok(None)` means the user asked to exit.
+fn parse_repl_command(input: &str) -> Result<Option<Commands>, Failure> {
+    let (command, rest) = next_word(input);
+    let (key, json) = next_key(rest)?;
+    let quoted = rest.starts_with('"');
+    let value = json.to_string();
+
+    match command {
+        "exit" | "quit" => Ok(None),
+        "set" if !key.is_empty() && !json.is_empty() => Ok(Some(Commands::Set { key, value, raw: false })),
+        "update" if !key.is_empty() && !json.is_empty() => Ok(Some(Commands::Update { key, value, raw: false })),
+        "get" if !key.is_empty() => {
+            // `get key | .path`, with or without spaces around the `|`
+            let (key, query) = match (quoted, json.strip_prefix('|'), rest.split_once('|')) {
+                (true, Some(query), _) => (key, Some(query.trim().to_string())),
+                (false, _, Some((key, query))) => (key.trim().to_string(), Some(query.trim().to_string())),
+                _ if json.is_empty() => (key, None),
+                _ => return Err(Failure::new("Usage: get <key> [| <query>]").with_code(EXIT_USAGE)),
+            };
+            if key.is_empty() || (!quoted && key.contains(char::is_whitespace)) {
+                return Err(Failure::new("Usage: get <key> [| <query>]").with_code(EXIT_USAGE));
+            }
+            Ok(Some(Commands::Get { key, query }))
+        }
+        "delete" if !key.is_empty() && json.is_empty() => Ok(Some(Commands::Delete { key })),
+        "incr" | "decr" if !key.is_empty() => {
+            let by = match json {
+                "" => Number::from(1),
+                amount => parse_number(amount).map_err(|e| Failure::new(e).with_code(EXIT_USAGE))?,
+            };
+            let options = IncrOptions { pointer: None, create: false, initial: Number::from(0) };
+            match command {
+                "incr" => Ok(Some(Commands::Incr { key, by, options })),
+                _ => Ok(Some(Commands::Decr { key, by, options })),
+            }
+        }
+        "edit" if !key.is_empty() && json.is_empty() => Ok(Some(Commands::Edit { key })),
+        _ => Err(Failure::new("Unknown command. Use: set|update|get|delete|incr|decr|edit|exit").with_code(EXIT_USAGE)),
+    }
+}
+
+const REPL_COMMANDS: &[&str] = &["set", "update", "get", "delete", "incr", "decr", "edit", "exit", "quit"];
+const KEY_COMMANDS: &[&str] = &["set", "update", "get", "delete", "incr", "decr", "edit"];
+
+/// How long a key listing is reused for completion before asking the server again.
+const COMPLETION_CACHE_TTL: Duration = Duration::from_secs(5);
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(1);
+const COMPLETION_LIMIT: usize = 1000;
+
+/// Completes command names and, after a key command, key names one
+/// `.`-separated segment at a time using the server's key listing.
+struct ReplHelper {
+    client: KvClient,
+    runtime: tokio::runtime::Handle,
+    /// Listings by the prefix they were requested for
+    cache: RefCell<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl ReplHelper {
+    fn new(client: KvClient) -> Self {
+        ReplHelper {
+            client,
+            runtime: tokio::runtime::Handle::current(),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Keys under `prefix`, from the cache or the server. Errors complete nothing.
+    fn list_keys(&self, prefix: &str) -> Vec<String> {
+        if let Some((fetched, keys)) = self.cache.borrow().get(prefix) {
+            if fetched.elapsed() < COMPLETION_CACHE_TTL {
+                return keys.clone();
             }
+        }
+
+        // Completion runs inside rustyline's blocking read on a runtime worker thread
+        let list = self.client.list(prefix, Some(COMPLETION_LIMIT));
+        let keys = tokio::task::block_in_place(|| {
+            self.runtime.block_on(tokio::time::timeout(COMPLETION_TIMEOUT, list))
+        });
+        let keys = match keys {
+            Ok(Ok(keys)) => keys,
+            _ => return Vec::new(),
+        };
+        self.cache_keys(prefix, keys.clone());
+        keys
+    }
+
+    /// Caches a listing, dropping the expired ones so a long session doesn't
+    /// keep every prefix it ever completed.
+    fn cache_keys(&self, prefix: &str, keys: Vec<String>) {
+        let mut cache = self.cache.borrow_mut();
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < COMPLETION_CACHE_TTL);
+        cache.insert(prefix.to_string(), (Instant::now(), keys));
+    }
+
+    /// Candidates for a partially typed key: full keys at the current level and
+    /// `segment.` for deeper levels, so each Tab descends one segment.
+    fn complete_key(&self, partial: &str) -> Vec<String> {
+        let parent = match partial.rfind('.') {
+            Some(i) => &partial[..=i],
+            None => "",
+        };
+        let mut candidates: Vec<String> = self
+            .list_keys(parent)
+            .into_iter()
+            .filter(|key| key.starts_with(partial))
+            .map(|key| match key[parent.len()..].find('.') {
+                Some(i) => key[..parent.len() + i + 1].to_string(),
+                None => key,
+            })
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        let preceding: Vec<&str> = before[..start].split_whitespace().collect();
+
+        let candidates = match preceding.as_slice() {
+            [] => REPL_COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| command.to_string())
+                .collect(),
+            [command] if KEY_COMMANDS.contains(command) => self.complete_key(word),
+            _ => Vec::new(),
+        };
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair { display: candidate.clone(), replacement: candidate })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+type ReplEditor = Editor<ReplHelper, FileHistory>;
+
+/// `<config dir>/kvs-client/history.txt`, creating the directory if needed.
+fn history_path() -> Option<PathBuf> {
+    let dir = dirs::config_dir()?.join("kvs-client");
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("history.txt"))
+}
+
+/// Reads one command, continuing with a `... ` prompt while the JSON is unbalanced.
+/// `Ok(None)` means the input was cancelled with Ctrl+C.
+fn read_command(rl: &mut ReplEditor) -> Result<Option<String>, ReadlineError> {
+    let mut input = rl.readline("> ")?;
+    while json_incomplete(&input) {
+        match rl.readline("... ") {
+            Ok(line) => {
+                input.push('\n');
+                input.push_str(&line);
+            }
+            Err(ReadlineError::Interrupted) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(input))
+}
+
+async fn run_repl(client: KvClient, output: Output) -> anyhow::Result<()> {
+    let config = rustyline::Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = ReplEditor::with_config(config)?;
+    rl.set_helper(Some(ReplHelper::new(client.clone())));
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session
+        let _ = rl.load_history(path);
+    }
+
+    println!("kvs-client REPL (server: {})", client.base_url());
+    println!("{}", REPL_USAGE);
+    loop {
+        let input = match read_command(&mut rl) {
+            Ok(Some(input)) => input,
+            Ok(None) => continue,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
             Err(err) => {
                 eprintln!("REPL error: {}", err);
                 break;
             }
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        rl.add_history_entry(input.as_str())?;
+
+        let result = match parse_repl_command(&input) {
+            Ok(None) => break,
+            Ok(Some(command)) => execute(&client, command).await,
+            Err(failure) => Err(failure),
+        };
+        match result {
+            Ok(outcome) => output.print_outcome(&outcome),
+            Err(failure) => output.print_failure(&failure),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("Warning: could not save history to {:?}: {}", path, e);
         }
     }
     Ok(())
 }
 
+impl Cli {
+    fn kv_client(&self) -> Result<KvClient, KvError> {
+        let mut builder = KvClient::builder().base_url(&self.server);
+        if let Some(secs) = self.timeout {
+            builder = builder.timeout(Duration::from_secs(secs));
+        }
+        if let Some(token) = &self.token {
+            builder = builder.bearer_token(token);
+        }
+        if let Some(path) = &self.routes {
+            builder = builder.routes_file(path);
+        } else if self.direct {
+            builder = builder.routes_from_router(&self.server);
+        }
+        builder.build()
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    let client = cli.kv_client()?;
+    let output = Output { format: cli.output, compact: cli.compact };
 
     if cli.command.is_none() && !cli.repl {
         // Default to REPL if no command
-        run_repl(cli.server).await?;
+        run_repl(client, output).await?;
         return Ok(());
     }
 
-    if let Some(cmd) = cli.command {
-        let client = Client::new();
-        match cmd {
-            Commands::Set { key, value } => {
-                do_set(&client, &cli.server, &key, &value).await?;
-            }
-            Commands::Update { key, value } => {
-                do_update(&client, &cli.server, &key, &value).await?;
-            }
-            Commands::Get { key } => {
-                do_get(&client, &cli.server, &key).await?;
-            }
-            Commands::Delete { key } => {
-                do_delete(&client, &cli.server, &key).await?;
+    if let Some(cmd) = cli.command.take() {
+        // One-off mode: the only place a failed command ends the process
+        match execute(&client, cmd).await {
+            Ok(outcome) => output.print_outcome(&outcome),
+            Err(failure) => {
+                output.print_failure(&failure);
+                std::process::exit(failure.code);
             }
         }
     } else if cli.repl {
-        run_repl(cli.server).await?;
+        run_repl(client, output).await?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_is_incomplete_only_inside_strings_or_unclosed_brackets() {
+        assert!(!json_incomplete(r#"{"a": 1}"#));
+        assert!(json_incomplete(r#"{"a": [1,"#));
+        // Brackets inside strings don't count
+        assert!(!json_incomplete(r#"{"a": "}{]["}"#));
+        assert!(json_incomplete(r#"{"a": "{"#));
+        // An escaped quote doesn't end the string, an escaped backslash does
+        assert!(json_incomplete(r#""say \"hi"#));
+        assert!(!json_incomplete(r#""C:\\""#));
+        // Too many closers can't be fixed by reading more
+        assert!(!json_incomplete("}"));
+        assert!(!json_incomplete("]{"));
+    }
+
+    fn parse(input: &str) -> Commands {
+        match parse_repl_command(input) {
+            Ok(Some(command)) => command,
+            Ok(None) => panic!("{:?} parsed as exit", input),
+            Err(failure) => panic!("{:?} failed: {}", input, failure.message),
+        }
+    }
+
+    fn get_parts(input: &str) -> (String, Option<String>) {
+        match parse(input) {
+            Commands::Get { key, query } => (key, query),
+            _ => panic!("{:?} is not a get", input),
+        }
+    }
+
+    #[test]
+    fn get_takes_an_optional_query_after_a_pipe() {
+        assert_eq!(get_parts("get user"), ("user".to_string(), None));
+        assert_eq!(get_parts("get user | .name"), ("user".to_string(), Some(".name".to_string())));
+        assert_eq!(get_parts("get user|.tags[0]"), ("user".to_string(), Some(".tags[0]".to_string())));
+        assert!(parse_repl_command("get user .name").is_err());
+        assert!(parse_repl_command("get | .name").is_err());
+    }
+
+    #[test]
+    fn quoted_keys_may_hold_spaces_and_pipes() {
+        assert_eq!(get_parts(r#"get "my key""#), ("my key".to_string(), None));
+        assert_eq!(get_parts(r#"get "a|b" | .x"#), ("a|b".to_string(), Some(".x".to_string())));
+        assert_eq!(get_parts(r#"get "say \"hi\"""#), (r#"say "hi""#.to_string(), None));
+        match parse(r#"set "my key" {"a": "b c"}"#) {
+            Commands::Set { key, value, .. } => assert_eq!((key.as_str(), value.as_str()), ("my key", r#"{"a": "b c"}"#)),
+            _ => panic!("not a set"),
+        }
+        assert!(matches!(parse(r#"delete "my key""#), Commands::Delete { key } if key == "my key"));
+        assert!(parse_repl_command(r#"get "unterminated"#).is_err());
+        assert!(parse_repl_command(r#"get "a" extra"#).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expired_completions_are_dropped_from_the_cache() {
+        let client = KvClient::builder().base_url("http://127.0.0.1:1").build().unwrap();
+        let helper = ReplHelper::new(client);
+        let stale = Instant::now().checked_sub(COMPLETION_CACHE_TTL * 2).unwrap();
+        helper.cache.borrow_mut().insert("old.".to_string(), (stale, vec!["old.a".to_string()]));
+        helper.cache_keys("fresh.", vec!["fresh.a".to_string()]);
+        helper.cache_keys("", vec!["fresh.a".to_string()]);
+
+        let mut cached: Vec<String> = helper.cache.borrow().keys().cloned().collect();
+        cached.sort_unstable();
+        assert_eq!(cached, ["", "fresh."]);
+        assert_eq!(helper.list_keys("fresh."), ["fresh.a"]);
+    }
+
+    #[test]
+    fn other_commands_and_mistakes() {
+        assert!(matches!(parse_repl_command("exit"), Ok(None)));
+        assert!(matches!(parse_repl_command("  quit  "), Ok(None)));
+        assert!(matches!(parse("incr hits"), Commands::Incr { key, by, .. } if key == "hits" && by == Number::from(1)));
+        assert!(matches!(parse("decr hits 2.5"), Commands::Decr { by, .. } if by.as_f64() == Some(2.5)));
+
+        for input in ["frobnicate key", "", "set key", "delete a b", "incr hits lots"] {
+            match parse_repl_command(input) {
+                Err(failure) => assert_eq!(failure.code, EXIT_USAGE, "{:?}", input),
+                Ok(_) => panic!("{:?} should not parse", input),
+            }
+        }
+        let unknown = parse_repl_command("frobnicate key").err().unwrap();
+        assert!(unknown.message.starts_with("Unknown command"));
+    }
+}
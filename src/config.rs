@@ -0,0 +1,197 @@
+//! Settings of the `rust-key-store` binary. Each comes from, highest first: a
+//! command-line flag, a `KVS_*` environment variable, the `--config` TOML file,
+//! or a built-in default. [`Config::resolve`] merges the layers, so it can be
+//! exercised without a real command line or environment.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::server::key_server::{parse_size, EvictionPolicy, Limits};
+
+pub const DEFAULT_PORT: u16 = 3000;
+pub const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// One layer of settings: the flags, the environment or the config file. Unset
+// fields fall through to the layer below. (A doc comment here would become the
+// binary's `--help` about text through `#[clap(flatten)]`.)
+#[derive(clap::Args, Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Port to bind to [default: 3000]
+    #[clap(short, long)]
+    pub port: Option<u16>,
+
+    /// Address to bind to [default: 127.0.0.1]
+    #[clap(short, long)]
+    pub bind: Option<IpAddr>,
+
+    /// Listen on a Unix domain socket at this path instead of TCP
+    #[clap(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Path to routes JSON file (enables shard-router mode)
+    #[clap(long)]
+    pub routes: Option<PathBuf>,
+
+    /// Seconds to wait for in-flight requests on shutdown [default: 30]
+    #[clap(long)]
+    pub drain_timeout: Option<u64>,
+
+    /// Write the store to this file on shutdown (key-server mode; `.ndjson`/`.jsonl` selects NDJSON)
+    #[clap(long)]
+    pub snapshot: Option<PathBuf>,
+
+    /// Seed the store from a JSON object or NDJSON dump before listening (key-server mode)
+    #[clap(long)]
+    pub load: Option<PathBuf>,
+
+    /// On shutdown, write the store back to the `--load` file in the format it was read in
+    #[clap(long, num_args = 0..=1, default_missing_value = "true")]
+    pub save_on_exit: Option<bool>,
+
+    /// Approximate limit on stored keys and values, e.g. `512mb` (key-server mode)
+    #[clap(long)]
+    pub max_memory: Option<String>,
+
+    /// Limit on the number of stored keys (key-server mode)
+    #[clap(long)]
+    pub max_keys: Option<u64>,
+
+    /// What to do at a limit: noeviction, lru, lfu or ttl-first [default: noeviction]
+    #[clap(long)]
+    pub eviction_policy: Option<EvictionPolicy>,
+}
+
+impl Settings {
+    /// Parses a config file. Unknown keys are errors, so a typo isn't silently ignored.
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+        Self::from_toml(&content).map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+    }
+
+    /// Reads the `KVS_*` variables through `var`, e.g. `|name| std::env::var(name).ok()`.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        Ok(Settings {
+            port: env_value(&var, "KVS_PORT")?,
+            bind: env_value(&var, "KVS_BIND")?,
+            unix_socket: env_value(&var, "KVS_UNIX_SOCKET")?,
+            routes: env_value(&var, "KVS_ROUTES")?,
+            drain_timeout: env_value(&var, "KVS_DRAIN_TIMEOUT")?,
+            snapshot: env_value(&var, "KVS_SNAPSHOT")?,
+            load: env_value(&var, "KVS_LOAD")?,
+            save_on_exit: env_value(&var, "KVS_SAVE_ON_EXIT")?,
+            max_memory: env_value(&var, "KVS_MAX_MEMORY")?,
+            max_keys: env_value(&var, "KVS_MAX_KEYS")?,
+            eviction_policy: env_value(&var, "KVS_EVICTION_POLICY")?,
+        })
+    }
+
+    /// These settings, with the ones left unset taken from `lower`.
+    pub fn or(self, lower: Settings) -> Settings {
+        Settings {
+            port: self.port.or(lower.port),
+            bind: self.bind.or(lower.bind),
+            unix_socket: self.unix_socket.or(lower.unix_socket),
+            routes: self.routes.or(lower.routes),
+            drain_timeout: self.drain_timeout.or(lower.drain_timeout),
+            snapshot: self.snapshot.or(lower.snapshot),
+            load: self.load.or(lower.load),
+            save_on_exit: self.save_on_exit.or(lower.save_on_exit),
+            max_memory: self.max_memory.or(lower.max_memory),
+            max_keys: self.max_keys.or(lower.max_keys),
+            eviction_policy: self.eviction_policy.or(lower.eviction_policy),
+        }
+    }
+}
+
+fn env_value<T>(var: &impl Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    var(name)
+        .map(|value| value.parse().map_err(|e| format!("{}: invalid value {:?}: {}", name, value, e)))
+        .transpose()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// The effective settings, checked for combinations that make no sense.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub listen: Listen,
+    /// Shard-router mode when set
+    pub routes: Option<PathBuf>,
+    pub drain_timeout: Duration,
+    pub snapshot: Option<PathBuf>,
+    pub load: Option<PathBuf>,
+    pub save_on_exit: bool,
+    pub limits: Limits,
+}
+
+impl Config {
+    /// Merges the layers, highest first, and fills in the defaults.
+    pub fn resolve(flags: Settings, env: Settings, file: Settings) -> Result<Self, String> {
+        let settings = flags.or(env).or(file);
+
+        let listen = match settings.unix_socket {
+            Some(_) if cfg!(not(unix)) => {
+                return Err("Unix domain sockets are not supported on this platform".to_string());
+            }
+            Some(path) => Listen::Unix(path),
+            None => {
+                let ip = settings.bind.unwrap_or(DEFAULT_BIND);
+                let port = settings.port.unwrap_or(DEFAULT_PORT);
+                Listen::Tcp(SocketAddr::new(ip, port))
+            }
+        };
+
+        let drain_timeout = settings.drain_timeout.map_or(DEFAULT_DRAIN_TIMEOUT, Duration::from_secs);
+        let routes = settings.routes;
+        let load = settings.load;
+        let save_on_exit = settings.save_on_exit.unwrap_or(false);
+        if routes.is_some() && load.is_some() {
+            return Err("--load is only supported in key-server mode".to_string());
+        }
+        if save_on_exit && load.is_none() {
+            return Err("--save-on-exit requires --load".to_string());
+        }
+
+        let max_memory = settings
+            .max_memory
+            .map(|size| parse_size(&size).map_err(|e| format!("--max-memory: {}", e)))
+            .transpose()?;
+        let limits = Limits {
+            max_memory,
+            max_keys: settings.max_keys,
+            policy: settings.eviction_policy.unwrap_or_default(),
+        };
+        if routes.is_some() && limits != Limits::default() {
+            return Err(
+                "--max-memory, --max-keys and --eviction-policy are only supported in key-server mode".to_string()
+            );
+        }
+        if limits.max_memory == Some(0) || limits.max_keys == Some(0) {
+            return Err("--max-memory and --max-keys must be greater than 0".to_string());
+        }
+
+        Ok(Config { listen, routes, drain_timeout, snapshot: settings.snapshot, load, save_on_exit, limits })
+    }
+}
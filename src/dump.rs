@@ -0,0 +1,61 @@
+//! The NDJSON dump format shared by `/admin/export`, `/admin/import` and the client.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// One line of an export/import dump: `{"key": "...", "value": ...}`.
+/// Writers may borrow the value (`Record<&Value>`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record<V = Value> {
+    pub key: String,
+    pub value: V,
+}
+
+/// What an import does with a key that already exists.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing value
+    Skip,
+    /// Replace the existing value
+    Overwrite,
+    /// Reject the whole import without writing anything
+    #[default]
+    Fail,
+}
+
+impl ConflictPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Fail => "fail",
+        }
+    }
+}
+
+/// Body of a successful import.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: u64,
+    pub skipped: u64,
+}
+
+/// Parses an NDJSON dump, ignoring blank lines. Errors name the 1-based line number.
+pub fn parse_ndjson(input: &str) -> Result<Vec<Record>, String> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Serializes `record` as one newline-terminated NDJSON line.
+pub fn ndjson_line<V: Serialize>(record: &Record<V>) -> Vec<u8> {
+    let mut line = serde_json::to_vec(record).expect("Failed to serialize record");
+    line.push(b'\n');
+    line
+}
@@ -0,0 +1,749 @@
+//! Async HTTP client for a key server or shard router.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), rust_key_store::KvError> {
+//! use rust_key_store::KvClient;
+//! use std::time::Duration;
+//!
+//! let kv = KvClient::builder()
+//!     .base_url("http://localhost:3000")
+//!     .timeout(Duration::from_secs(5))
+//!     .build()?;
+//!
+//! kv.set("app.config", &serde_json::json!({ "debug": true })).await?;
+//! let config: serde_json::Value = kv.get("app.config").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Given a routing table ([`KvClientBuilder::routes_file`] or
+//! [`KvClientBuilder::routes_from_router`]), the client sends key requests
+//! straight to the owning key server instead of through the router.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use reqwest::{header, redirect, Method, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use crate::{
+    dump::{ConflictPolicy, ImportSummary, NDJSON_CONTENT_TYPE},
+    lease::MAX_WAIT,
+    query::POINTER_HEADER,
+    routing::{build_routes, parse_routes, RouteTable, RoutesConfig},
+};
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+
+/// How much longer than the server's wait a lock long-poll may take to answer.
+const LONG_POLL_SLACK: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum KvError {
+    /// The key does not exist (404)
+    NotFound { key: String },
+    /// A shard-aware client has no route for the key, even after refreshing its table
+    NoRoute { key: String },
+    /// `set` on a key that already exists (409)
+    AlreadyExists { key: String },
+    /// The server refused the request because of existing state, e.g. an
+    /// import that hit existing keys (409). `body` is the server's response.
+    Conflict { body: Value },
+    /// A conditional write found the key at a different version (412). `body`
+    /// holds the server's current `version` and `value`.
+    PreconditionFailed { key: String, body: Value },
+    /// A lease renewal or release found the lease expired or held by someone else (409)
+    LeaseLost { name: String },
+    /// Any other non-success response
+    Status { status: StatusCode, body: Value },
+    /// The request never got a usable HTTP response (connect, timeout, TLS, I/O)
+    Transport(reqwest::Error),
+    /// The response body was not the JSON the caller asked for
+    Decode(String),
+    /// The request could not be built, e.g. a value that does not serialize to JSON
+    InvalidInput(String),
+    /// Writing a streamed response (`export_to`) failed locally
+    Io(std::io::Error),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::NotFound { key } => write!(f, "key not found: {}", key),
+            KvError::NoRoute { key } => write!(f, "no route found for key: {}", key),
+            KvError::AlreadyExists { key } => write!(f, "key already exists: {}", key),
+            KvError::Conflict { body } => write!(f, "conflict: {}", body),
+            KvError::PreconditionFailed { key, .. } => write!(f, "key changed concurrently: {}", key),
+            KvError::LeaseLost { name } => write!(f, "lease lost: {}", name),
+            KvError::Status { status, body } => write!(f, "server returned {}: {}", status, body),
+            KvError::Transport(e) => write!(f, "transport error: {}", e),
+            KvError::Decode(e) => write!(f, "invalid response: {}", e),
+            KvError::InvalidInput(e) => write!(f, "invalid input: {}", e),
+            KvError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KvError::Transport(e) => Some(e),
+            KvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for KvError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            KvError::Decode(e.to_string())
+        } else {
+            KvError::Transport(e)
+        }
+    }
+}
+
+/// A value together with the version the server stamped it with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub version: u64,
+}
+
+/// What [`KvClient::incr`] adds, and where: `Increment::by(-1).at("/stock").or_create(10)`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Increment {
+    delta: Number,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pointer: String,
+    create: bool,
+    initial: Number,
+}
+
+impl Increment {
+    /// Adds `delta` to the number the key holds; use [`Number::from_f64`] for a fractional step.
+    pub fn by(delta: impl Into<Number>) -> Self {
+        Increment { delta: delta.into(), pointer: String::new(), create: false, initial: Number::from(0) }
+    }
+
+    /// Adds to the number at a JSON Pointer inside the value instead.
+    pub fn at(mut self, pointer: impl Into<String>) -> Self {
+        self.pointer = pointer.into();
+        self
+    }
+
+    /// Starts a missing key (or object member) at `initial` instead of failing
+    /// with [`KvError::NotFound`].
+    pub fn or_create(mut self, initial: impl Into<Number>) -> Self {
+        self.create = true;
+        self.initial = initial.into();
+        self
+    }
+}
+
+/// A held lease on a named lock, from [`KvClient::acquire_lock`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lease {
+    pub name: String,
+    /// Fencing token: greater than the token of every earlier lease from the
+    /// same server. Pass it along with writes so stale holders can be refused.
+    pub token: u64,
+    /// How long the lease lasts after acquiring or renewing it
+    pub ttl: Duration,
+}
+
+/// Where a shard-aware client gets its routing table.
+#[derive(Clone, Debug)]
+pub enum RouteSource {
+    /// A routes file, in the format the router's `--routes` takes
+    File(PathBuf),
+    /// A router's `GET /admin/routes`, given the router's base URL
+    Router(String),
+}
+
+/// The routing table of a shard-aware client, reloaded from `source` when a
+/// shard signals (with a redirect or `421 Misdirected Request`) that it is stale.
+#[derive(Debug)]
+struct ShardMap {
+    source: RouteSource,
+    routes: RwLock<Option<Arc<RouteTable>>>,
+}
+
+#[derive(Default)]
+pub struct KvClientBuilder {
+    base_url: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    bearer_token: Option<String>,
+    route_source: Option<RouteSource>,
+}
+
+impl KvClientBuilder {
+    /// Server or router URL, e.g. `http://localhost:3000`. Defaults to [`DEFAULT_BASE_URL`].
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// Limit on each whole request, from connecting until the body is read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends `Authorization: Bearer <token>` with every request.
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Route key requests directly to shards using a routes file.
+    /// The file is read by `build`, so a bad file fails early.
+    pub fn routes_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.route_source = Some(RouteSource::File(path.into()));
+        self
+    }
+
+    /// Route key requests directly to shards using the table served by the
+    /// router at `router_url`, fetched on first use.
+    pub fn routes_from_router(mut self, router_url: impl Into<String>) -> Self {
+        self.route_source = Some(RouteSource::Router(router_url.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<KvClient, KvError> {
+        let mut http = reqwest::Client::builder();
+        if self.route_source.is_some() {
+            // Redirects from a shard mean our routing table is stale; see them instead of following
+            http = http.redirect(redirect::Policy::none());
+        }
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            http = http.connect_timeout(timeout);
+        }
+        if let Some(token) = self.bearer_token {
+            let mut value = header::HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|e| KvError::InvalidInput(format!("invalid bearer token: {}", e)))?;
+            value.set_sensitive(true);
+            let mut headers = header::HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, value);
+            http = http.default_headers(headers);
+        }
+
+        let shards = match self.route_source {
+            Some(source) => {
+                let routes = match &source {
+                    RouteSource::File(path) => Some(Arc::new(RouteTable::new(load_routes_file(path)?))),
+                    RouteSource::Router(_) => None,
+                };
+                Some(Arc::new(ShardMap { source, routes: RwLock::new(routes) }))
+            }
+            None => None,
+        };
+
+        let base_url = self.base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Ok(KvClient {
+            http: http.build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            shards,
+        })
+    }
+}
+
+/// Typed client for the `/keys` and `/admin` APIs. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct KvClient {
+    http: reqwest::Client,
+    base_url: String,
+    shards: Option<Arc<ShardMap>>,
+}
+
+impl KvClient {
+    /// A client for `base_url` with default options.
+    pub fn new(base_url: impl Into<String>) -> Result<Self, KvError> {
+        Self::builder().base_url(base_url).build()
+    }
+
+    pub fn builder() -> KvClientBuilder {
+        KvClientBuilder::default()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Whether key requests bypass the router.
+    pub fn is_shard_aware(&self) -> bool {
+        self.shards.is_some()
+    }
+
+    fn key_url(&self, key: &str) -> String {
+        format!("{}/keys/{}", self.base_url, urlencoding::encode(key))
+    }
+
+    async fn routes(&self, shards: &ShardMap) -> Result<Arc<RouteTable>, KvError> {
+        let cached = shards.routes.read().expect("routes lock poisoned").clone();
+        match cached {
+            Some(routes) => Ok(routes),
+            None => self.refresh_routes(shards).await,
+        }
+    }
+
+    async fn refresh_routes(&self, shards: &ShardMap) -> Result<Arc<RouteTable>, KvError> {
+        let routes = match &shards.source {
+            RouteSource::File(path) => load_routes_file(path)?,
+            RouteSource::Router(router_url) => {
+                let url = format!("{}/admin/routes", router_url.trim_end_matches('/'));
+                let res = self.send(Method::GET, url, None, None).await?;
+                if !res.status().is_success() {
+                    return Err(unexpected(res).await);
+                }
+                let RoutesConfig(entries) = decode(res).await?;
+                build_routes(entries).map_err(KvError::Decode)?
+            }
+        };
+        let routes = Arc::new(RouteTable::new(routes));
+        *shards.routes.write().expect("routes lock poisoned") = Some(routes.clone());
+        Ok(routes)
+    }
+
+    /// Sends a request for `key`, either to the base URL or, for a shard-aware
+    /// client, straight to the owning shard under the key the router would use.
+    /// A redirect, a `421`, or a key missing from the table triggers one refresh
+    /// of the routing table and a retry. `suffix` is appended to the key's URL
+    /// (e.g. a query string).
+    async fn send_key(
+        &self,
+        method: Method,
+        key: &str,
+        suffix: &str,
+        body: Option<Vec<u8>>,
+        if_match: Option<u64>,
+    ) -> Result<Response, KvError> {
+        let Some(shards) = &self.shards else {
+            return self.send(method, self.key_url(key) + suffix, body, if_match).await;
+        };
+
+        let mut routes = self.routes(shards).await?;
+        let mut refreshed = false;
+        loop {
+            let Some(route) = routes.find(key) else {
+                if refreshed {
+                    return Err(KvError::NoRoute { key: key.to_string() });
+                }
+                routes = self.refresh_routes(shards).await?;
+                refreshed = true;
+                continue;
+            };
+
+            let url = format!(
+                "{}/keys/{}{}",
+                route.target.trim_end_matches('/'),
+                urlencoding::encode(route.backend_key),
+                suffix
+            );
+            let res = self.send(method.clone(), url, body.clone(), if_match).await?;
+            let stale = res.status() == StatusCode::MISDIRECTED_REQUEST || res.status().is_redirection();
+            if stale && !refreshed {
+                routes = self.refresh_routes(shards).await?;
+                refreshed = true;
+                continue;
+            }
+            return Ok(res);
+        }
+    }
+
+    /// The key's URI from a write response. Shards answer in their own key
+    /// space, so a shard-aware client reports the router's view instead.
+    async fn uri(&self, key: &str, res: Response) -> Result<String, KvError> {
+        if self.shards.is_some() {
+            return Ok(format!("/keys/{}", urlencoding::encode(key)));
+        }
+        let body: Value = decode(res).await?;
+        body["uri"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| KvError::Decode(format!("response has no uri: {}", body)))
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: String,
+        body: Option<Vec<u8>>,
+        if_match: Option<u64>,
+    ) -> Result<Response, KvError> {
+        let mut request = self.http.request(method, url);
+        if let Some(version) = if_match {
+            request = request.header(header::IF_MATCH, format!("\"{}\"", version));
+        }
+        if let Some(body) = body {
+            request = request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+        Ok(request.send().await?)
+    }
+
+    /// Fetches `key` and deserializes its value into `T`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, KvError> {
+        let (value, _) = self.fetch(key).await?;
+        Ok(value)
+    }
+
+    /// Like [`get`](Self::get), but also returns the version the server holds the
+    /// value at, for use with [`update_if`](Self::update_if) and [`delete_if`](Self::delete_if).
+    pub async fn get_versioned<T: DeserializeOwned>(&self, key: &str) -> Result<Versioned<T>, KvError> {
+        match self.fetch(key).await? {
+            (value, Some(version)) => Ok(Versioned { value, version }),
+            (_, None) => Err(KvError::Decode("response has no version ETag".to_string())),
+        }
+    }
+
+    /// Reads the part of `key`'s value at a JSON Pointer (`/a/0/b`). Returns
+    /// `None` if the key exists but nothing is at `pointer`. Servers that can
+    /// resolve the pointer send only the sub-document; with older servers the
+    /// whole value is fetched and the pointer applied here.
+    pub async fn get_pointer<T: DeserializeOwned>(&self, key: &str, pointer: &str) -> Result<Option<T>, KvError> {
+        let suffix = format!("?pointer={}", urlencoding::encode(pointer));
+        let res = self.send_key(Method::GET, key, &suffix, None, None).await?;
+        match res.status() {
+            StatusCode::OK if res.headers().contains_key(POINTER_HEADER) => Ok(Some(decode(res).await?)),
+            StatusCode::OK => {
+                let value: Value = decode(res).await?;
+                value
+                    .pointer(pointer)
+                    .map(|v| T::deserialize(v).map_err(|e| KvError::Decode(e.to_string())))
+                    .transpose()
+            }
+            StatusCode::NOT_FOUND => {
+                let body = body_json(res).await;
+                if body["error"] == "Pointer not found" {
+                    Ok(None)
+                } else {
+                    Err(KvError::NotFound { key: key.to_string() })
+                }
+            }
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, key: &str) -> Result<(T, Option<u64>), KvError> {
+        let res = self.send_key(Method::GET, key, "", None, None).await?;
+        match res.status() {
+            StatusCode::OK => {
+                let version = etag_version(&res);
+                Ok((decode(res).await?, version))
+            }
+            StatusCode::NOT_FOUND => Err(KvError::NotFound { key: key.to_string() }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Creates `key`. Fails with [`KvError::AlreadyExists`] if it exists.
+    /// Returns the key's URI.
+    pub async fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<String, KvError> {
+        let body = encode(value)?;
+        let res = self.send_key(Method::POST, key, "", Some(body), None).await?;
+        match res.status() {
+            StatusCode::CREATED => self.uri(key, res).await,
+            StatusCode::CONFLICT => Err(KvError::AlreadyExists { key: key.to_string() }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Replaces the value of an existing `key`. Fails with [`KvError::NotFound`]
+    /// if it does not exist. Returns the key's URI.
+    pub async fn update<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<String, KvError> {
+        self.put(key, value, None).await
+    }
+
+    /// Like [`update`](Self::update), but only if `key` is still at `version`;
+    /// otherwise fails with [`KvError::PreconditionFailed`].
+    pub async fn update_if<T: Serialize + ?Sized>(&self, key: &str, value: &T, version: u64) -> Result<String, KvError> {
+        self.put(key, value, Some(version)).await
+    }
+
+    async fn put<T: Serialize + ?Sized>(&self, key: &str, value: &T, if_match: Option<u64>) -> Result<String, KvError> {
+        let body = encode(value)?;
+        let res = self.send_key(Method::PUT, key, "", Some(body), if_match).await?;
+        self.write_result(key, res).await
+    }
+
+    /// Removes `key`. Returns the key's URI.
+    pub async fn delete(&self, key: &str) -> Result<String, KvError> {
+        let res = self.send_key(Method::DELETE, key, "", None, None).await?;
+        self.write_result(key, res).await
+    }
+
+    /// Like [`delete`](Self::delete), but only if `key` is still at `version`;
+    /// otherwise fails with [`KvError::PreconditionFailed`].
+    pub async fn delete_if(&self, key: &str, version: u64) -> Result<String, KvError> {
+        let res = self.send_key(Method::DELETE, key, "", None, Some(version)).await?;
+        self.write_result(key, res).await
+    }
+
+    /// Applies `increment` atomically on the server and returns the new number
+    /// with the key's new version. A value that is not a number fails with a
+    /// 422 [`KvError::Status`], and a sum that does not fit with [`KvError::Conflict`].
+    pub async fn incr(&self, key: &str, increment: &Increment) -> Result<Versioned<Number>, KvError> {
+        #[derive(Deserialize)]
+        struct Incremented {
+            value: Number,
+        }
+
+        let body = encode(increment)?;
+        let res = self.send_key(Method::POST, key, "/incr", Some(body), None).await?;
+        match res.status() {
+            StatusCode::OK | StatusCode::CREATED => {
+                let version = etag_version(&res).ok_or_else(|| KvError::Decode("response has no version ETag".to_string()))?;
+                let Incremented { value } = decode(res).await?;
+                Ok(Versioned { value, version })
+            }
+            StatusCode::NOT_FOUND => {
+                let body = body_json(res).await;
+                if body["error"] == "Key not found" {
+                    Err(KvError::NotFound { key: key.to_string() })
+                } else {
+                    Err(KvError::Status { status: StatusCode::NOT_FOUND, body })
+                }
+            }
+            StatusCode::CONFLICT => Err(KvError::Conflict { body: body_json(res).await }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Copies `source` to `destination` in one server-side step. An existing
+    /// destination is only replaced with `overwrite`; otherwise this fails with
+    /// [`KvError::AlreadyExists`]. Returns the destination's URI.
+    pub async fn copy(&self, source: &str, destination: &str, overwrite: bool) -> Result<String, KvError> {
+        self.transfer("copy", source, destination, overwrite).await
+    }
+
+    /// Like [`copy`](Self::copy), but the source is removed. A key server does
+    /// this atomically; a router moving between shards rolls back on failure.
+    pub async fn rename(&self, source: &str, destination: &str, overwrite: bool) -> Result<String, KvError> {
+        self.transfer("rename", source, destination, overwrite).await
+    }
+
+    /// A shard-aware client sends the transfer straight to the shard when it owns
+    /// both keys; a transfer between shards has to go through the router at the
+    /// base URL.
+    async fn transfer(&self, op: &str, source: &str, destination: &str, overwrite: bool) -> Result<String, KvError> {
+        let mut url = format!("{}/{}", self.key_url(source), op);
+        let mut destination_key = destination.to_string();
+        if let Some(shards) = &self.shards {
+            let routes = self.routes(shards).await?;
+            if let (Some(source), Some(destination)) = (routes.find(source), routes.find(destination)) {
+                if source.target == destination.target {
+                    let target = source.target.trim_end_matches('/');
+                    url = format!("{}/keys/{}/{}", target, urlencoding::encode(source.backend_key), op);
+                    destination_key = destination.backend_key.to_string();
+                }
+            }
+        }
+
+        let body = encode(&serde_json::json!({ "destination": destination_key, "overwrite": overwrite }))?;
+        let res = self.send(Method::POST, url, Some(body), None).await?;
+        match res.status() {
+            StatusCode::OK | StatusCode::CREATED => self.uri(destination, res).await,
+            StatusCode::NOT_FOUND => Err(KvError::NotFound { key: source.to_string() }),
+            StatusCode::CONFLICT => {
+                let body = body_json(res).await;
+                if body["error"] == "Destination exists" {
+                    Err(KvError::AlreadyExists { key: destination.to_string() })
+                } else {
+                    Err(KvError::Conflict { body })
+                }
+            }
+            StatusCode::PRECONDITION_FAILED => Err(KvError::PreconditionFailed {
+                key: source.to_string(),
+                body: body_json(res).await,
+            }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Maps the response of a PUT or DELETE on an existing key.
+    async fn write_result(&self, key: &str, res: Response) -> Result<String, KvError> {
+        match res.status() {
+            StatusCode::OK => self.uri(key, res).await,
+            StatusCode::NOT_FOUND => Err(KvError::NotFound { key: key.to_string() }),
+            StatusCode::PRECONDITION_FAILED => Err(KvError::PreconditionFailed {
+                key: key.to_string(),
+                body: body_json(res).await,
+            }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Names of the keys starting with `prefix`, sorted, at most `limit` of them.
+    /// Always asks the base URL, so a shard-aware client needs it to be a router.
+    pub async fn list(&self, prefix: &str, limit: Option<usize>) -> Result<Vec<String>, KvError> {
+        #[derive(Deserialize)]
+        struct KeyList {
+            keys: Vec<String>,
+        }
+
+        let mut request = self.http
+            .get(format!("{}/keys", self.base_url))
+            .query(&[("prefix", prefix)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        let res = request.send().await?;
+        if !res.status().is_success() {
+            return Err(unexpected(res).await);
+        }
+        let KeyList { keys } = decode(res).await?;
+        Ok(keys)
+    }
+
+    /// Streams the NDJSON dump of every key into `out`. Returns the bytes written.
+    pub async fn export_to<W: Write>(&self, out: &mut W) -> Result<u64, KvError> {
+        let url = format!("{}/admin/export", self.base_url);
+        let mut res = self.send(Method::GET, url, None, None).await?;
+        if !res.status().is_success() {
+            return Err(unexpected(res).await);
+        }
+
+        let mut written = 0;
+        while let Some(chunk) = res.chunk().await.map_err(KvError::Transport)? {
+            out.write_all(&chunk).map_err(KvError::Io)?;
+            written += chunk.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// Loads an NDJSON dump. A `409` (existing keys under [`ConflictPolicy::Fail`])
+    /// maps to [`KvError::Conflict`] carrying the server's list of conflicts.
+    pub async fn import(&self, dump: impl Into<Vec<u8>>, on_conflict: ConflictPolicy) -> Result<ImportSummary, KvError> {
+        let url = format!("{}/admin/import?on_conflict={}", self.base_url, on_conflict.as_str());
+        let res = self.http
+            .post(url)
+            .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(dump.into())
+            .send()
+            .await?;
+        match res.status() {
+            StatusCode::OK => decode(res).await,
+            StatusCode::CONFLICT => Err(KvError::Conflict { body: body_json(res).await }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    /// Acquires the lock `name` for `ttl`, waiting up to `wait` for the holder
+    /// to release it or let it expire (`Duration::MAX` waits forever). `None`
+    /// if it is still held after `wait`. Lock requests always go to the base URL.
+    pub async fn acquire_lock(
+        &self,
+        name: &str,
+        ttl: Duration,
+        wait: Duration,
+        owner: Option<&str>,
+    ) -> Result<Option<Lease>, KvError> {
+        #[derive(Deserialize)]
+        struct Acquired {
+            token: u64,
+        }
+
+        let url = self.lock_url(name, "");
+        let deadline = Instant::now().checked_add(wait);
+        loop {
+            // The server caps each wait, so longer ones take several polls
+            let poll = deadline.map_or(MAX_WAIT, |deadline| deadline.saturating_duration_since(Instant::now()).min(MAX_WAIT));
+            let body = serde_json::json!({ "ttl": ttl.as_secs_f64(), "wait": poll.as_secs_f64(), "owner": owner });
+            let res = self.http.post(&url).json(&body).timeout(poll + LONG_POLL_SLACK).send().await?;
+            match res.status() {
+                StatusCode::CREATED => {
+                    let Acquired { token } = decode(res).await?;
+                    return Ok(Some(Lease { name: name.to_string(), token, ttl }));
+                }
+                StatusCode::CONFLICT if deadline.is_some_and(|deadline| Instant::now() >= deadline) => return Ok(None),
+                StatusCode::CONFLICT => continue,
+                _ => return Err(unexpected(res).await),
+            }
+        }
+    }
+
+    /// Extends `lease` by its TTL. Fails with [`KvError::LeaseLost`] if it
+    /// already expired, in which case someone else may hold the lock.
+    pub async fn renew_lock(&self, lease: &Lease) -> Result<(), KvError> {
+        let body = serde_json::json!({ "token": lease.token, "ttl": lease.ttl.as_secs_f64() });
+        self.lease_call(lease, "/renew", &body).await
+    }
+
+    /// Releases `lease`. Fails with [`KvError::LeaseLost`] if it had expired first.
+    pub async fn release_lock(&self, lease: &Lease) -> Result<(), KvError> {
+        self.lease_call(lease, "/release", &serde_json::json!({ "token": lease.token })).await
+    }
+
+    async fn lease_call(&self, lease: &Lease, suffix: &str, body: &Value) -> Result<(), KvError> {
+        let res = self.send(Method::POST, self.lock_url(&lease.name, suffix), Some(encode(body)?), None).await?;
+        match res.status() {
+            StatusCode::OK => Ok(()),
+            StatusCode::CONFLICT => Err(KvError::LeaseLost { name: lease.name.clone() }),
+            _ => Err(unexpected(res).await),
+        }
+    }
+
+    fn lock_url(&self, name: &str, suffix: &str) -> String {
+        format!("{}/locks/{}{}", self.base_url, urlencoding::encode(name), suffix)
+    }
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, KvError> {
+    serde_json::to_vec(value).map_err(|e| KvError::InvalidInput(format!("value is not JSON: {}", e)))
+}
+
+async fn decode<T: DeserializeOwned>(res: Response) -> Result<T, KvError> {
+    // reqwest reports a body that breaks off as a decode error; it is a transport failure
+    let bytes = res.bytes().await.map_err(KvError::Transport)?;
+    serde_json::from_slice(&bytes).map_err(|e| KvError::Decode(e.to_string()))
+}
+
+/// The version in a `ETag: "<version>"` header.
+fn etag_version(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(header::ETAG)?
+        .to_str()
+        .ok()?
+        .trim_matches('"')
+        .parse()
+        .ok()
+}
+
+fn load_routes_file(path: &Path) -> Result<HashMap<String, String>, KvError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| KvError::InvalidInput(format!("failed to read routes file {:?}: {}", path, e)))?;
+    parse_routes(&content).map_err(KvError::InvalidInput)
+}
+
+/// The response body as JSON, or as a JSON string if it is not JSON.
+async fn body_json(res: Response) -> Value {
+    match res.bytes().await {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(_) => Value::Null,
+    }
+}
+
+async fn unexpected(res: Response) -> KvError {
+    let status = res.status();
+    KvError::Status { status, body: body_json(res).await }
+}
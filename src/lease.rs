@@ -0,0 +1,9 @@
+//! Limits of the lease protocol shared by `/locks/{name}` and the client.
+
+use std::time::Duration;
+
+/// Longest lease a server grants; holders that need longer renew.
+pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest one acquire request waits; clients that want longer ask again.
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
@@ -0,0 +1,15 @@
+//! Library half of rust-key-store: the key-server and shard-router apps
+//! ([`server`]), the typed [`KvClient`] used by `kvs-client`, and the wire
+//! formats shared between the servers and their clients.
+
+pub mod bench;
+pub mod config;
+pub mod dump;
+pub mod kv_client;
+pub mod lease;
+pub mod linearizability;
+pub mod query;
+pub mod routing;
+pub mod server;
+
+pub use kv_client::{Increment, KvClient, KvClientBuilder, KvError, Lease, Versioned};
@@ -0,0 +1,518 @@
+//! Linearizability testing: [`record`] runs concurrent clients against a key
+//! server or router and times every operation, and [`check`] searches the
+//! history for a legal sequential order, one key at a time, against a register
+//! model.
+//!
+//! The search is the Wing & Gong / Lowe algorithm with memoization of
+//! (linearized set, state) pairs, as in Knossos and Porcupine. Linearizability
+//! is local, so checking each key's sub-history on its own is enough.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde_json::Value;
+
+use crate::{KvClient, KvError};
+
+/// An operation on one key. `V` is the value type; the checker works on
+/// interned values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op<V = Value> {
+    Get,
+    /// Create-only write (`POST`)
+    Set(V),
+    /// Write to an existing key (`PUT`)
+    Update(V),
+    Delete,
+    /// `PUT` with `If-Match` carrying the version `expected` was read at. Every
+    /// write stores a distinct value, so the version names exactly that value.
+    CompareAndSet { expected: V, new: V },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<V = Value> {
+    Ok,
+    /// A read's result
+    Value(V),
+    NotFound,
+    /// A `Set` found the key already present
+    Exists,
+    /// A `CompareAndSet` found another version
+    Mismatch,
+    /// The request failed without a definite answer (timeout, connection reset,
+    /// 5xx); the operation may or may not have taken effect.
+    Unknown,
+}
+
+/// One operation of a history, with its invocation and response times since the
+/// start of the run. `ret` is `None` when the outcome is [`Outcome::Unknown`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub client: usize,
+    pub key: String,
+    pub op: Op,
+    pub outcome: Outcome,
+    pub call: Duration,
+    pub ret: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// Concurrent clients, each with at most one request in flight
+    pub clients: usize,
+    /// Operations per client
+    pub operations: usize,
+    /// Number of keys, named `<prefix><n>`; few keys means more contention
+    pub keys: usize,
+    /// Prepended to every key; through a router it must match a route
+    pub prefix: String,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload { clients: 8, operations: 200, keys: 3, prefix: "lin.".to_string() }
+    }
+}
+
+/// Deletes the workload's keys, then runs `workload.clients` clients issuing a
+/// random mix of reads, writes, deletes and read-modify-write cycles. Returns
+/// the history sorted by invocation time.
+pub async fn record(client: &KvClient, workload: &Workload) -> Result<Vec<Event>, KvError> {
+    if workload.keys == 0 || workload.clients == 0 {
+        return Err(KvError::InvalidInput("keys and clients must be at least 1".to_string()));
+    }
+    for i in 0..workload.keys {
+        match client.delete(&format!("{}{}", workload.prefix, i)).await {
+            Ok(_) | Err(KvError::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let started = Instant::now();
+    let tasks: Vec<_> = (0..workload.clients)
+        .map(|id| {
+            let client = client.clone();
+            let workload = workload.clone();
+            tokio::spawn(async move { run_client(&client, id, &workload, started).await })
+        })
+        .collect();
+
+    let mut history = Vec::new();
+    for task in tasks {
+        history.extend(task.await.expect("linearizability client panicked"));
+    }
+    history.sort_by_key(|event| event.call);
+    Ok(history)
+}
+
+async fn run_client(client: &KvClient, id: usize, workload: &Workload, started: Instant) -> Vec<Event> {
+    let mut rng = SmallRng::from_entropy();
+    let mut history = Vec::new();
+    let mut written = 0;
+    // Values are unique across the run, so a read names the write it saw
+    let mut next_value = || {
+        written += 1;
+        Value::String(format!("c{}.{}", id, written))
+    };
+
+    for _ in 0..workload.operations {
+        let key = format!("{}{}", workload.prefix, rng.gen_range(0..workload.keys));
+        let mut call = started.elapsed();
+        let (op, result) = match rng.gen_range(0..100) {
+            0..=29 => (Op::Get, read(client, &key).await.map(|(value, _)| Some(value))),
+            30..=44 => {
+                let value = next_value();
+                (Op::Set(value.clone()), write(client.set(&key, &value).await))
+            }
+            45..=64 => {
+                let value = next_value();
+                (Op::Update(value.clone()), write(client.update(&key, &value).await))
+            }
+            65..=74 => (Op::Delete, write(client.delete(&key).await)),
+            _ => {
+                // Read-modify-write: the read is part of the history too
+                let (expected, version) = match read(client, &key).await {
+                    Ok(read) => read,
+                    Err(outcome) => {
+                        history.push(event(id, &key, Op::Get, Err(outcome), call, started));
+                        continue;
+                    }
+                };
+                history.push(event(id, &key, Op::Get, Ok(Some(expected.clone())), call, started));
+                call = started.elapsed();
+                let new = next_value();
+                let result = write(client.update_if(&key, &new, version).await);
+                (Op::CompareAndSet { expected, new }, result)
+            }
+        };
+        history.push(event(id, &key, op, result, call, started));
+    }
+    history
+}
+
+/// A read's value and version, or the outcome to record when it failed.
+async fn read(client: &KvClient, key: &str) -> Result<(Value, u64), Outcome> {
+    match client.get_versioned::<Value>(key).await {
+        Ok(versioned) => Ok((versioned.value, versioned.version)),
+        Err(KvError::NotFound { .. }) => Err(Outcome::NotFound),
+        Err(_) => Err(Outcome::Unknown),
+    }
+}
+
+/// A write's result as `Ok(None)`, or the outcome to record when it failed.
+fn write(result: Result<String, KvError>) -> Result<Option<Value>, Outcome> {
+    match result {
+        Ok(_) => Ok(None),
+        Err(KvError::NotFound { .. }) => Err(Outcome::NotFound),
+        Err(KvError::AlreadyExists { .. }) => Err(Outcome::Exists),
+        Err(KvError::PreconditionFailed { .. }) => Err(Outcome::Mismatch),
+        Err(_) => Err(Outcome::Unknown),
+    }
+}
+
+/// Completes an event now. `result` is a read's value, `Ok(None)` for a write
+/// that succeeded, or the failure outcome.
+fn event(
+    client: usize,
+    key: &str,
+    op: Op,
+    result: Result<Option<Value>, Outcome>,
+    call: Duration,
+    started: Instant,
+) -> Event {
+    let outcome = match result {
+        Ok(Some(value)) => Outcome::Value(value),
+        Ok(None) => Outcome::Ok,
+        Err(outcome) => outcome,
+    };
+    let ret = (outcome != Outcome::Unknown).then(|| started.elapsed());
+    Event { client, key: key.to_string(), op, outcome, call, ret }
+}
+
+/// A key whose history has no legal sequential order, with a small sub-history
+/// that already has none.
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub key: String,
+    /// Sorted by invocation time
+    pub events: Vec<Event>,
+    /// True if the sub-history starts after the first operation on the key and
+    /// was checked from every possible initial value
+    pub from_any_state: bool,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "history of {:?} is not linearizable; ", self.key)?;
+        if self.from_any_state {
+            writeln!(f, "no order of these operations is legal, whatever the key held before:")?;
+        } else {
+            writeln!(f, "no order of these operations is legal, starting from a missing key:")?;
+        }
+        for event in &self.events {
+            let ret = event.ret.map_or("?".to_string(), |ret| format!("{:.3?}", ret));
+            writeln!(
+                f,
+                "  [{:>10.3?} .. {:>10}] client {}: {} -> {}",
+                event.call, ret, event.client, event.op, event.outcome
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for Op<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Get => write!(f, "get"),
+            Op::Set(value) => write!(f, "set({})", value),
+            Op::Update(value) => write!(f, "update({})", value),
+            Op::Delete => write!(f, "delete"),
+            Op::CompareAndSet { expected, new } => write!(f, "cas({} -> {})", expected, new),
+        }
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for Outcome<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Value(value) => write!(f, "{}", value),
+            Outcome::NotFound => write!(f, "not found"),
+            Outcome::Exists => write!(f, "exists"),
+            Outcome::Mismatch => write!(f, "version mismatch"),
+            Outcome::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Checks every key's sub-history, assuming each key is missing before its
+/// first operation. On failure, the sub-history is shrunk before it is returned.
+pub fn check(history: &[Event]) -> Result<(), Violation> {
+    let mut by_key: BTreeMap<&str, Vec<&Event>> = BTreeMap::new();
+    for event in history {
+        by_key.entry(&event.key).or_default().push(event);
+    }
+    for (key, mut events) in by_key {
+        events.sort_by_key(|event| event.call);
+        let calls = Calls::new(&events);
+        if !calls.linearizable(&calls.all(), Start::Missing) {
+            let (kept, from_any_state) = calls.shrink();
+            return Err(Violation {
+                key: key.to_string(),
+                events: kept.into_iter().map(|i| events[i].clone()).collect(),
+                from_any_state,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Model state: the interned value a key holds, or `None` if it is missing.
+type State = Option<usize>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Start {
+    Missing,
+    /// Every state the key could be in: missing, any value in the history, or
+    /// a value the history never mentions
+    Any,
+}
+
+/// One key's operations with interned values, sorted by invocation time.
+struct Calls {
+    ops: Vec<Op<usize>>,
+    outcomes: Vec<Outcome<usize>>,
+    call: Vec<Duration>,
+    ret: Vec<Option<Duration>>,
+    values: usize,
+}
+
+impl Calls {
+    fn new(events: &[&Event]) -> Self {
+        let mut interned: HashMap<String, usize> = HashMap::new();
+        let mut intern = |value: &Value| {
+            let next = interned.len();
+            *interned.entry(value.to_string()).or_insert(next)
+        };
+        let ops = events
+            .iter()
+            .map(|event| match &event.op {
+                Op::Get => Op::Get,
+                Op::Set(value) => Op::Set(intern(value)),
+                Op::Update(value) => Op::Update(intern(value)),
+                Op::Delete => Op::Delete,
+                Op::CompareAndSet { expected, new } => Op::CompareAndSet { expected: intern(expected), new: intern(new) },
+            })
+            .collect();
+        let outcomes = events
+            .iter()
+            .map(|event| match &event.outcome {
+                Outcome::Ok => Outcome::Ok,
+                Outcome::Value(value) => Outcome::Value(intern(value)),
+                Outcome::NotFound => Outcome::NotFound,
+                Outcome::Exists => Outcome::Exists,
+                Outcome::Mismatch => Outcome::Mismatch,
+                Outcome::Unknown => Outcome::Unknown,
+            })
+            .collect();
+        Calls {
+            ops,
+            outcomes,
+            call: events.iter().map(|event| event.call).collect(),
+            ret: events.iter().map(|event| event.ret).collect(),
+            values: interned.len(),
+        }
+    }
+
+    fn all(&self) -> Vec<usize> {
+        (0..self.ops.len()).collect()
+    }
+
+    /// Whether the operations `subset` (indices in invocation order) have a legal
+    /// sequential order. Unknown operations may be placed anywhere after their
+    /// invocation or left out.
+    fn linearizable(&self, subset: &[usize], start: Start) -> bool {
+        let mut initial = vec![None];
+        if start == Start::Any {
+            initial.extend((0..=self.values).map(Some));
+        }
+        initial.into_iter().any(|state| {
+            let mut search = Search {
+                calls: self,
+                subset,
+                linearized: vec![0; subset.len().div_ceil(64)],
+                seen: HashSet::new(),
+            };
+            let pending = subset.iter().filter(|&&i| self.ret[i].is_some()).count();
+            search.run(state, pending)
+        })
+    }
+
+    /// Shrinks a non-linearizable history to a sub-history that is still not
+    /// linearizable, using only steps that keep it a genuine witness:
+    ///
+    /// - Cut the end at the earliest point where nothing is in flight and the
+    ///   prefix already fails. Operations that returned before such a point come
+    ///   first in every legal order, so the prefix of a linearizable history is
+    ///   linearizable.
+    /// - Cut the start at the latest such point where the rest still fails from
+    ///   every initial state, keeping earlier operations with unknown outcomes.
+    /// - Drop reads and writes that failed without changing anything, one by
+    ///   one, as long as the rest still fails. Removing them never makes a
+    ///   linearizable history illegal.
+    ///
+    /// Dropped operations may have hidden cut points, so this repeats until
+    /// nothing changes. Returns the kept indices and whether the start was cut.
+    fn shrink(&self) -> (Vec<usize>, bool) {
+        let mut kept = self.all();
+        let mut start = Start::Missing;
+        loop {
+            let before = kept.len();
+            (kept, start) = self.cut(&kept, start);
+            kept = self.drop_observations(kept, start);
+            if kept.len() == before {
+                return (kept, start == Start::Any);
+            }
+        }
+    }
+
+    fn cut(&self, subset: &[usize], start: Start) -> (Vec<usize>, Start) {
+        // Positions `k` where every completed operation before `k` returned
+        // before operation `k` was invoked
+        let mut cuts = Vec::new();
+        let mut latest_return = Duration::ZERO;
+        for (k, &i) in subset.iter().enumerate() {
+            if latest_return <= self.call[i] {
+                cuts.push(k);
+            }
+            if let Some(ret) = self.ret[i] {
+                latest_return = latest_return.max(ret);
+            }
+        }
+        cuts.push(subset.len());
+
+        let end = cuts[cuts.partition_point(|&k| self.linearizable(&subset[..k], start))];
+        let window = |from: usize| -> Vec<usize> {
+            (0..end)
+                .filter(|&k| k >= from || self.ret[subset[k]].is_none())
+                .map(|k| subset[k])
+                .collect()
+        };
+        let starts: Vec<usize> = cuts.into_iter().filter(|&k| k > 0 && k < end).collect();
+        match starts.partition_point(|&k| !self.linearizable(&window(k), Start::Any)) {
+            0 => (window(0), start),
+            failing => (window(starts[failing - 1]), Start::Any),
+        }
+    }
+
+    fn drop_observations(&self, mut subset: Vec<usize>, start: Start) -> Vec<usize> {
+        let mut pos = 0;
+        while pos < subset.len() {
+            let i = subset[pos];
+            let observation = matches!(self.ops[i], Op::Get)
+                || matches!(self.outcomes[i], Outcome::NotFound | Outcome::Exists | Outcome::Mismatch);
+            if observation {
+                let mut without = subset.clone();
+                without.remove(pos);
+                if !self.linearizable(&without, start) {
+                    subset = without;
+                    continue;
+                }
+            }
+            pos += 1;
+        }
+        subset
+    }
+}
+
+/// Depth-first search for a legal order, memoizing (linearized set, state)
+/// pairs that are known to lead nowhere.
+struct Search<'a> {
+    calls: &'a Calls,
+    /// Indices into `calls`, in invocation order
+    subset: &'a [usize],
+    /// Bit set over positions in `subset`
+    linearized: Vec<u64>,
+    seen: HashSet<(Vec<u64>, State)>,
+}
+
+impl Search<'_> {
+    /// `pending` counts the completed operations not yet placed.
+    fn run(&mut self, state: State, pending: usize) -> bool {
+        if pending == 0 {
+            return true;
+        }
+        if !self.seen.insert((self.linearized.clone(), state)) {
+            return false;
+        }
+        // Whatever goes next must have been invoked before the earliest
+        // response among the operations still to place
+        let horizon = (0..self.subset.len())
+            .filter(|&pos| !self.is_linearized(pos))
+            .filter_map(|pos| self.calls.ret[self.subset[pos]])
+            .min()
+            .expect("a pending operation");
+        for pos in 0..self.subset.len() {
+            let i = self.subset[pos];
+            if self.calls.call[i] > horizon {
+                break;
+            }
+            if self.is_linearized(pos) {
+                continue;
+            }
+            let Some(next) = step(state, &self.calls.ops[i], &self.calls.outcomes[i]) else {
+                continue;
+            };
+            self.toggle(pos);
+            if self.run(next, pending - usize::from(self.calls.ret[i].is_some())) {
+                return true;
+            }
+            self.toggle(pos);
+        }
+        false
+    }
+
+    fn is_linearized(&self, pos: usize) -> bool {
+        self.linearized[pos / 64] & (1 << (pos % 64)) != 0
+    }
+
+    fn toggle(&mut self, pos: usize) {
+        self.linearized[pos / 64] ^= 1 << (pos % 64);
+    }
+}
+
+/// The register model: the state after applying `op` with the observed
+/// `outcome`, or `None` if the outcome is impossible in `state`.
+fn step(state: State, op: &Op<usize>, outcome: &Outcome<usize>) -> Option<State> {
+    match (op, outcome) {
+        (_, Outcome::Unknown) => Some(apply(state, op)),
+        (Op::Get, Outcome::Value(value)) => (state == Some(*value)).then_some(state),
+        (_, Outcome::NotFound) => state.is_none().then_some(state),
+        (Op::Set(value), Outcome::Ok) => state.is_none().then_some(Some(*value)),
+        (Op::Set(_), Outcome::Exists) => state.is_some().then_some(state),
+        (Op::Update(value), Outcome::Ok) => state.is_some().then_some(Some(*value)),
+        (Op::Delete, Outcome::Ok) => state.is_some().then_some(None),
+        (Op::CompareAndSet { expected, new }, Outcome::Ok) => (state == Some(*expected)).then_some(Some(*new)),
+        (Op::CompareAndSet { expected, .. }, Outcome::Mismatch) => {
+            (state.is_some() && state != Some(*expected)).then_some(state)
+        }
+        _ => None,
+    }
+}
+
+/// The effect of `op` if it took effect, whatever it answered.
+fn apply(state: State, op: &Op<usize>) -> State {
+    match op {
+        Op::Get => state,
+        Op::Set(value) => state.or(Some(*value)),
+        Op::Update(value) => state.map(|_| *value),
+        Op::Delete => None,
+        Op::CompareAndSet { expected, new } if state == Some(*expected) => Some(*new),
+        Op::CompareAndSet { .. } => state,
+    }
+}
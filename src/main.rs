@@ -1,231 +1,116 @@
 // src/main.rs
 
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Json, Response},
-    routing::{delete, get, post, put},
-    Router,
-};
+use axum::Router;
 use clap::Parser;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::{
-    collections::HashMap,
-    net::SocketAddr,
-    path::PathBuf,
-    sync::Arc,
+use rust_key_store::{
+    config::{Config, Listen, Settings},
+    routing::parse_routes,
+    server::{
+        key_server::{self, load_dump, write_dump, Db, DumpFormat, Store},
+        drain, shard_router, wait_for_shutdown, Lifecycle,
+    },
+};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    sync::watch,
 };
-use tokio::{net::TcpListener, sync::RwLock};
-use reqwest::Client;
-use http::Method;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Port to bind to
-    #[clap(short, long, default_value_t = 3000)]
-    port: u16,
-
-    /// Path to routes JSON file (enables shard-router mode)
-    #[clap(long)]
-    routes: Option<PathBuf>,
-}
+    /// Path to a TOML config file; KVS_* environment variables and flags override it
+    #[clap(short, long, env = "KVS_CONFIG")]
+    config: Option<PathBuf>,
 
-#[derive(Serialize)]
-struct UriResponse {
-    uri: String,
+    #[clap(flatten)]
+    settings: Settings,
 }
 
 // ========================
-// Key-Server Mode
+// Configuration
 // ========================
 
-type Store = Arc<RwLock<HashMap<String, Value>>>;
-
-async fn post_key(
-    Path(key): Path<String>,
-    State(store): State<Store>,
-    Json(value): Json<Value>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let mut db = store.write().await;
-    if db.contains_key(&key) {
-        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
-        return Err((StatusCode::CONFLICT, Json(body)));
-    }
-    db.insert(key.clone(), value);
-    let uri = format!("/keys/{}", urlencoding::encode(&key));
-    let res = UriResponse { uri };
-    Ok((StatusCode::CREATED, Json(res)))
+/// Flags over `KVS_*` variables over the config file over defaults.
+fn load_config(args: Args) -> Result<Config, String> {
+    let env = Settings::from_env(|name| std::env::var(name).ok())?;
+    let file = match &args.config {
+        Some(path) => Settings::load(path)?,
+        None => Settings::default(),
+    };
+    Config::resolve(args.settings, env, file)
 }
 
-async fn get_key(
-    Path(key): Path<String>,
-    State(store): State<Store>,
-) -> Result<Json<Value>, (StatusCode, Json<serde_json::Value>)> {
-    let db = store.read().await;
-    match db.get(&key) {
-        Some(value) => Ok(Json(value.clone())),
-        None => {
-            let body = serde_json::json!({ "error": "Key not found" });
-            Err((StatusCode::NOT_FOUND, Json(body)))
+async fn serve(
+    app: Router,
+    listen: &Listen,
+    banner: &str,
+    drain_timeout: Duration,
+    lifecycle: Arc<Lifecycle>,
+) {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        println!("Shutdown signal received, draining in-flight requests...");
+        lifecycle.start_draining();
+        let _ = shutdown_tx.send(true);
+    });
+
+    match listen {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await
+                .unwrap_or_else(|e| panic!("Failed to bind {}: {}", addr, e));
+            println!("{} listening on http://{}", banner, listener.local_addr().unwrap());
+            let server = axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(wait_for_shutdown(shutdown_rx.clone()));
+            drain(server, shutdown_rx, drain_timeout).await.expect("Server error");
         }
-    }
-}
-
-async fn put_key(
-    Path(key): Path<String>,
-    State(store): State<Store>,
-    Json(value): Json<Value>,
-) -> Result<Json<UriResponse>, (StatusCode, Json<serde_json::Value>)> {
-    let mut db = store.write().await;
-    if !db.contains_key(&key) {
-        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
-        return Err((StatusCode::NOT_FOUND, Json(body)));
-    }
-    db.insert(key.clone(), value);
-    let uri = format!("/keys/{}", urlencoding::encode(&key));
-    Ok(Json(UriResponse { uri }))
-}
-
-async fn delete_key(
-    Path(key): Path<String>,
-    State(store): State<Store>,
-) -> Result<Json<UriResponse>, (StatusCode, Json<serde_json::Value>)> {
-    let mut db = store.write().await;
-    if !db.contains_key(&key) {
-        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
-        return Err((StatusCode::NOT_FOUND, Json(body)));
-    }
-    db.remove(&key);
-    let uri = format!("/keys/{}", urlencoding::encode(&key));
-    Ok(Json(UriResponse { uri }))
-}
-
-// ========================
-// Shard-Router Mode
-// ========================
-
-#[derive(Deserialize)]
-struct RouteEntry {
-    prefix: String,
-    target: String,
-}
-
-#[derive(Deserialize)]
-struct RoutesConfig(Vec<RouteEntry>);
-
-struct ShardRouterState {
-    routes: HashMap<String, String>,
-    client: Client,
-}
-
-// Returns (matched_prefix, backend_key_suffix)
-fn find_route(key: &str, routes: &HashMap<String, String>) -> Option<(String, String)> {
-    let segments: Vec<&str> = key.split('.').collect();
-    // Try longest prefix first (most specific match)
-    for i in (1..=segments.len()).rev() {
-        let candidate = segments[..i].join(".");
-        if routes.contains_key(&candidate) {
-            let suffix = if i == segments.len() {
-                ".".to_string()
-            } else {
-                format!(".{}", segments[i..].join("."))
-            };
-            return Some((candidate, suffix));
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket file left behind by a previous run would make bind fail
+            if let Ok(meta) = std::fs::symlink_metadata(path) {
+                if meta.file_type().is_socket() {
+                    std::fs::remove_file(path)
+                        .unwrap_or_else(|e| panic!("Failed to remove stale socket {:?}: {}", path, e));
+                }
+            }
+            let listener = tokio::net::UnixListener::bind(path)
+                .unwrap_or_else(|e| panic!("Failed to bind unix socket {:?}: {}", path, e));
+            println!("{} listening on unix:{}", banner, path.display());
+            let server = axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(wait_for_shutdown(shutdown_rx.clone()));
+            drain(server, shutdown_rx, drain_timeout).await.expect("Server error");
+            let _ = std::fs::remove_file(path);
         }
+        #[cfg(not(unix))]
+        Listen::Unix(_) => unreachable!("rejected in Config::resolve"),
     }
-    None
 }
 
-async fn route_and_proxy(
-    method: Method,
-    full_key: String,
-    routes: &HashMap<String, String>,
-    client: &Client,
-    body: Option<Value>,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    let (prefix, backend_key) = find_route(&full_key, routes)
-        .ok_or_else(|| {
-            let body = serde_json::json!({ "error": "No route found for key" });
-            (StatusCode::NOT_FOUND, Json(body))
-        })?;
-
-    let backend_url = routes.get(&prefix).unwrap(); // safe: `find_route` only returns existing keys
-    let encoded_backend_key = urlencoding::encode(&backend_key);
-    let url = format!("{}/keys/{}", backend_url.trim_end_matches('/'), encoded_backend_key);
-
-    let req_builder = match method {
-        Method::POST => client.post(&url),
-        Method::GET => client.get(&url),
-        Method::PUT => client.put(&url),
-        Method::DELETE => client.delete(&url),
-        _ => unreachable!(),
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
     };
 
-    let request = if let Some(val) = body {
-        req_builder.json(&val)
-    } else {
-        req_builder
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
     };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let res = request.send().await
-        .map_err(|e| {
-            eprintln!("Proxy error to {}: {}", url, e);
-            let body = serde_json::json!({ "error": "Upstream key-server unavailable" });
-            (StatusCode::BAD_GATEWAY, Json(body))
-        })?;
-
-    let status = res.status();
-    let mut json_res: serde_json::Value = res.json().await
-        .map_err(|_| {
-            let body = serde_json::json!({ "error": "Upstream returned invalid JSON" });
-            (StatusCode::BAD_GATEWAY, Json(body))
-        })?;
-
-    // Rewrite URI to reflect the original key space (not backend's view)
-    if json_res.get("uri").is_some() {
-        json_res["uri"] = serde_json::json!(format!("/keys/{}", urlencoding::encode(&full_key)));
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
-
-    let body_bytes = serde_json::to_vec(&json_res).expect("Failed to serialize JSON response");
-    let response = Response::builder()
-        .status(status)
-        .header("content-type", "application/json")
-        .body(axum::body::Body::from(body_bytes))
-        .expect("Failed to build HTTP response");
-
-    Ok(response)
-}
-
-async fn router_post(
-    Path(key): Path<String>,
-    State(state): State<Arc<ShardRouterState>>,
-    Json(value): Json<Value>,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    route_and_proxy(Method::POST, key, &state.routes, &state.client, Some(value)).await
-}
-
-async fn router_get(
-    Path(key): Path<String>,
-    State(state): State<Arc<ShardRouterState>>,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    route_and_proxy(Method::GET, key, &state.routes, &state.client, None).await
-}
-
-async fn router_put(
-    Path(key): Path<String>,
-    State(state): State<Arc<ShardRouterState>>,
-    Json(value): Json<Value>,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    route_and_proxy(Method::PUT, key, &state.routes, &state.client, Some(value)).await
-}
-
-async fn router_delete(
-    Path(key): Path<String>,
-    State(state): State<Arc<ShardRouterState>>,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    route_and_proxy(Method::DELETE, key, &state.routes, &state.client, None).await
 }
 
 // ========================
@@ -234,60 +119,68 @@ async fn router_delete(
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let config = load_config(Args::parse()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
 
-    if let Some(routes_path) = args.routes {
+    if let Some(routes_path) = config.routes {
         // === Shard Router Mode ===
         let routes_content = std::fs::read_to_string(&routes_path)
             .unwrap_or_else(|_| panic!("Failed to read routes file: {:?}", routes_path));
 
-        let RoutesConfig(route_entries) = serde_json::from_str(&routes_content)
-            .expect("Invalid routes.json: expected array of { \"prefix\": \"...\", \"target\": \"...\" }");
+        let routes = parse_routes(&routes_content).unwrap_or_else(|e| panic!("{}", e));
 
-        let mut routes = HashMap::new();
-        for entry in route_entries {
-            if entry.prefix.is_empty() {
-                panic!("Empty prefix is not allowed in routes");
-            }
-            if routes.insert(entry.prefix, entry.target).is_some() {
-                panic!("Duplicate prefix found in routes configuration");
+        let lifecycle = Lifecycle::new("shard-router");
+        let app = shard_router::app(routes, lifecycle.clone());
+
+        serve(app, &config.listen, "🌐 Shard router", config.drain_timeout, lifecycle).await;
+    } else {
+        // === Key-Server Mode ===
+        let mut initial = HashMap::new();
+        let mut load_format = DumpFormat::Object;
+        if let Some(path) = &config.load {
+            match load_dump(path) {
+                Ok((db, format)) => {
+                    println!("Loaded {} keys from {:?}", db.len(), path);
+                    initial = db;
+                    load_format = format;
+                }
+                Err(e) => {
+                    eprintln!("Failed to load {:?}: {}", path, e);
+                    std::process::exit(1);
+                }
             }
         }
-
-        let state = Arc::new(ShardRouterState {
-            routes,
-            client: Client::new(),
+        let db = Db::with_values(initial, config.limits).unwrap_or_else(|_| {
+            eprintln!("The loaded keys do not fit in --max-memory/--max-keys without eviction");
+            std::process::exit(1);
         });
+        let store: Store = Arc::new(db);
+        tokio::spawn(key_server::expire_keys(store.clone()));
+        let lifecycle = Lifecycle::new("key-server");
+        let app = key_server::app(store.clone(), lifecycle.clone());
 
-        let app = Router::new()
-            .route("/keys/{key}", post(router_post))
-            .route("/keys/{key}", get(router_get))
-            .route("/keys/{key}", put(router_put))
-            .route("/keys/{key}", delete(router_delete))
-            .with_state(state);
+        serve(app, &config.listen, "🔑 Key server", config.drain_timeout, lifecycle).await;
 
-        let addr = SocketAddr::from(([127, 0, 0, 1], args.port));
-        println!("🌐 Shard router listening on http://{}", addr);
-        let listener = TcpListener::bind(addr).await.unwrap();
-        axum::serve(listener, app.into_make_service())
-            .await
-            .unwrap();
-    } else {
-        // === Key-Server Mode ===
-        let store: Store = Arc::new(RwLock::new(HashMap::new()));
-
-        let app = Router::new()
-            .route("/keys/{key}", post(post_key))
-            .route("/keys/{key}", get(get_key))
-            .route("/keys/{key}", put(put_key))
-            .route("/keys/{key}", delete(delete_key))
-            .with_state(store);
+        let mut dumps = Vec::new();
+        if let Some(path) = config.snapshot {
+            let format = DumpFormat::for_path(&path);
+            dumps.push((path, format));
+        }
+        if config.save_on_exit {
+            let path = config.load.expect("checked in Config::resolve");
+            dumps.push((path, load_format));
+        }
 
-        let addr = SocketAddr::from(([127, 0, 0, 1], args.port));
-        println!("🔑 Key server listening on http://{}", addr);
-        let listener = TcpListener::bind(addr).await.unwrap();
-        axum::serve(listener, app.into_make_service())
-            .await
-            .unwrap();
+        for (path, format) in dumps {
+            match write_dump(&path, &store, format) {
+                Ok(()) => println!("Saved {} keys to {:?}", store.len(), path),
+                Err(e) => {
+                    eprintln!("Failed to write {:?}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 }
\ No newline at end of file
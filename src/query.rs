@@ -0,0 +1,190 @@
+//! Paths into a stored JSON value: RFC 6901 JSON Pointers (`/a/0/b`) and a
+//! jq-like subset (`.a[0].b`, `.["odd key"]`, `.items[].name`).
+//!
+//! Key servers resolve a JSON Pointer themselves (`GET /keys/{key}?pointer=...`)
+//! and mark the response with [`POINTER_HEADER`]; anything else is applied by
+//! the client to the whole value.
+
+use serde_json::Value;
+
+/// Set on a `GET /keys/{key}?pointer=...` response when the server returned
+/// only the sub-document. Older servers ignore the parameter and omit it.
+pub const POINTER_HEADER: &str = "x-kvs-pointer";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// A JSON Pointer, as written
+    Pointer(String),
+    /// A jq-like path
+    Path(Vec<Step>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `.name` or `["name"]`
+    Field(String),
+    /// `[n]`; negative indices count from the end
+    Index(i64),
+    /// `[]`: every element of an array or value of an object
+    Iterate,
+}
+
+impl Query {
+    /// `""` or anything starting with `/` is a JSON Pointer; anything starting
+    /// with `.` is a jq-like path.
+    pub fn parse(input: &str) -> Result<Query, String> {
+        let input = input.trim();
+        if input.is_empty() || input.starts_with('/') {
+            return Ok(Query::Pointer(input.to_string()));
+        }
+        if !input.starts_with('.') {
+            return Err(format!("query must start with '/' or '.': {}", input));
+        }
+        parse_path(input).map(Query::Path)
+    }
+
+    /// The query as a JSON Pointer, if it selects exactly one location that a
+    /// pointer can express (no `[]`, no negative indices).
+    pub fn to_pointer(&self) -> Option<String> {
+        match self {
+            Query::Pointer(pointer) => Some(pointer.clone()),
+            Query::Path(steps) => steps
+                .iter()
+                .map(|step| match step {
+                    Step::Field(name) => Some(format!("/{}", name.replace('~', "~0").replace('/', "~1"))),
+                    Step::Index(i) if *i >= 0 => Some(format!("/{}", i)),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Applies the query. Missing fields and out-of-range indices give `null`,
+    /// like jq. A path with `[]` collects everything it selects into an array.
+    /// As in a JSON Pointer, `.0` also selects an array element and `[0]` an
+    /// object's `"0"` member, so the result is the same whether the server
+    /// resolves the query (see [`Query::to_pointer`]) or the client does.
+    pub fn apply(&self, value: &Value) -> Value {
+        let steps = match self {
+            Query::Pointer(pointer) => return value.pointer(pointer).cloned().unwrap_or(Value::Null),
+            Query::Path(steps) => steps,
+        };
+
+        let mut selected = vec![value.clone()];
+        for step in steps {
+            selected = selected
+                .into_iter()
+                .flat_map(|value| match step {
+                    Step::Field(name) => vec![field(&value, name)],
+                    Step::Index(i) => vec![index(&value, *i)],
+                    Step::Iterate => match value {
+                        Value::Array(items) => items,
+                        Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+                        _ => Vec::new(),
+                    },
+                })
+                .collect();
+        }
+
+        if steps.contains(&Step::Iterate) {
+            Value::Array(selected)
+        } else {
+            selected.pop().unwrap_or(Value::Null)
+        }
+    }
+}
+
+fn field(value: &Value, name: &str) -> Value {
+    match value {
+        Value::Array(_) => array_index(name).map_or(Value::Null, |i| index(value, i)),
+        _ => value.get(name).cloned().unwrap_or(Value::Null),
+    }
+}
+
+/// `name` as an array index, if a JSON Pointer would read it as one: digits
+/// without a leading zero.
+fn array_index(name: &str) -> Option<i64> {
+    let digits = !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit());
+    if !digits || (name.len() > 1 && name.starts_with('0')) {
+        return None;
+    }
+    name.parse().ok()
+}
+
+fn index(value: &Value, i: i64) -> Value {
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(map) if i >= 0 => return map.get(&i.to_string()).cloned().unwrap_or(Value::Null),
+        _ => return Value::Null,
+    };
+    let i = if i < 0 { items.len() as i64 + i } else { i };
+    usize::try_from(i)
+        .ok()
+        .and_then(|i| items.get(i))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+fn parse_path(input: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let (step, after) = parse_bracket(after)?;
+            steps.push(step);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            if after.starts_with('"') {
+                let (name, after) = parse_string(after)?;
+                steps.push(Step::Field(name));
+                rest = after;
+            } else {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                if end > 0 {
+                    steps.push(Step::Field(after[..end].to_string()));
+                } else if !(after.starts_with('[') || (after.is_empty() && rest == input)) {
+                    return Err(format!("expected a field name at '{}'", after));
+                }
+                rest = &after[end..];
+            }
+        } else {
+            return Err(format!("expected '.' or '[' at '{}'", rest));
+        }
+    }
+    Ok(steps)
+}
+
+/// Parses what follows a `[`: `]`, `n]` or `"name"]`.
+fn parse_bracket(input: &str) -> Result<(Step, &str), String> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix(']') {
+        return Ok((Step::Iterate, rest));
+    }
+    let (step, rest) = if input.starts_with('"') {
+        let (name, rest) = parse_string(input)?;
+        (Step::Field(name), rest)
+    } else {
+        let end = input.find(']').ok_or("unclosed '['")?;
+        let index = input[..end]
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid index '{}'", &input[..end]))?;
+        (Step::Index(index), &input[end..])
+    };
+    let rest = rest.trim_start().strip_prefix(']').ok_or("unclosed '['")?;
+    Ok((step, rest))
+}
+
+/// Parses a JSON string literal at the start of `input`.
+fn parse_string(input: &str) -> Result<(String, &str), String> {
+    let mut stream = serde_json::Deserializer::from_str(input).into_iter::<String>();
+    match stream.next() {
+        Some(Ok(name)) => {
+            let end = stream.byte_offset();
+            Ok((name, &input[end..]))
+        }
+        _ => Err(format!("invalid string in query: {}", input)),
+    }
+}
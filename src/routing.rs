@@ -0,0 +1,180 @@
+//! Prefix routing shared by the shard router and shard-aware clients.
+//!
+//! A route prefix is a sequence of `.`-separated segments; a key is routed by
+//! the longest prefix that matches its leading segments. A `*` segment matches
+//! any one non-empty segment, so `tenant.*.cache` takes `tenant.acme.cache.x`.
+//! Where a literal segment and `*` both match at the same depth, the literal
+//! one wins.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// One entry of a routes file: keys under `prefix` live on the key server at `target`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RouteEntry {
+    pub prefix: String,
+    pub target: String,
+}
+
+/// The routes file format: `[{ "prefix": "...", "target": "..." }, ...]`.
+/// `GET /admin/routes` on a router returns the same shape.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RoutesConfig(pub Vec<RouteEntry>);
+
+/// The segment that matches any one segment of a key.
+pub const WILDCARD: &str = "*";
+
+/// Validates route entries and indexes them by prefix.
+pub fn build_routes(entries: Vec<RouteEntry>) -> Result<HashMap<String, String>, String> {
+    let mut routes = HashMap::new();
+    for entry in entries {
+        if entry.prefix.is_empty() {
+            return Err("Empty prefix is not allowed in routes".to_string());
+        }
+        if entry.prefix.starts_with('.') {
+            return Err(format!("Route prefix {:?} must not start with '.'", entry.prefix));
+        }
+        if entry.prefix.split('.').any(|segment| segment.contains('*') && segment != WILDCARD) {
+            return Err(format!("Route prefix {:?}: '*' must be a whole segment", entry.prefix));
+        }
+        if routes.insert(entry.prefix, entry.target).is_some() {
+            return Err("Duplicate prefix found in routes configuration".to_string());
+        }
+    }
+    Ok(routes)
+}
+
+/// Parses and validates the contents of a routes file.
+pub fn parse_routes(json: &str) -> Result<HashMap<String, String>, String> {
+    let RoutesConfig(entries) = serde_json::from_str(json).map_err(|e| {
+        format!("Invalid routes.json: expected array of {{ \"prefix\": \"...\", \"target\": \"...\" }}: {}", e)
+    })?;
+    build_routes(entries)
+}
+
+/// Whether `prefix` has a `*` segment.
+pub fn is_pattern(prefix: &str) -> bool {
+    prefix.split('.').any(|segment| segment == WILDCARD)
+}
+
+/// Where a key goes: the matching route and the key to use on its backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route<'t, 'k> {
+    /// The route's prefix as configured, possibly with `*` segments
+    pub prefix: &'t str,
+    pub target: &'t str,
+    pub backend_key: &'k str,
+}
+
+/// Validated routes with a segment trie over their prefixes, built once so a
+/// lookup walks the key a segment at a time without allocating.
+#[derive(Clone, Debug, Default)]
+pub struct RouteTable {
+    routes: HashMap<String, String>,
+    root: Node,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    wildcard: Option<Box<Node>>,
+    /// The route ending here: (prefix, target)
+    route: Option<(String, String)>,
+}
+
+/// The deepest route seen so far, and where in the key it ends.
+struct Best<'t> {
+    depth: usize,
+    end: usize,
+    node: &'t Node,
+}
+
+impl RouteTable {
+    /// Builds the table from routes checked by [`build_routes`].
+    pub fn new(routes: HashMap<String, String>) -> Self {
+        let mut root = Node::default();
+        for (prefix, target) in &routes {
+            let mut node = &mut root;
+            for segment in prefix.split('.') {
+                node = match segment {
+                    WILDCARD => node.wildcard.get_or_insert_with(Box::default),
+                    literal => node.children.entry(literal.to_string()).or_default(),
+                };
+            }
+            node.route = Some((prefix.clone(), target.clone()));
+        }
+        RouteTable { routes, root }
+    }
+
+    /// Finds the route for `key`. Keys below a literal prefix are stored on the
+    /// backend under the rest of the key, dot included (`foo.x` -> `.x`). Where
+    /// the rest would be empty or a `.`/`..` path segment, which no URL can
+    /// address, and for every key under a `*` route, the full key is used
+    /// instead. Route prefixes never start with a dot, so the two forms cannot
+    /// collide.
+    pub fn find<'k>(&self, key: &'k str) -> Option<Route<'_, 'k>> {
+        let mut best = None;
+        self.root.search(key, 0, 0, &mut best);
+        let Best { end, node, .. } = best?;
+        let (prefix, target) = node.route.as_ref().expect("only nodes with a route are recorded");
+        let backend_key = match &key[end..] {
+            _ if is_pattern(prefix) => key,
+            "" | "." | ".." => key,
+            rest => rest,
+        };
+        Some(Route { prefix, target, backend_key })
+    }
+
+    /// The target for a route prefix, as configured.
+    pub fn get(&self, prefix: &str) -> Option<&str> {
+        self.routes.get(prefix).map(String::as_str)
+    }
+
+    /// (prefix, target) pairs, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.routes.iter().map(|(prefix, target)| (prefix.as_str(), target.as_str()))
+    }
+
+    /// The table as entries, sorted by prefix.
+    pub fn entries(&self) -> Vec<RouteEntry> {
+        let mut entries: Vec<RouteEntry> = self
+            .iter()
+            .map(|(prefix, target)| RouteEntry { prefix: prefix.to_string(), target: target.to_string() })
+            .collect();
+        entries.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl Node {
+    /// Visits the routes matching `key`, where this node stands for the first
+    /// `depth` segments, ending at byte `end`. Literal children are tried before
+    /// the wildcard and only a deeper match replaces `best`, so literals win ties.
+    fn search<'t>(&'t self, key: &str, end: usize, depth: usize, best: &mut Option<Best<'t>>) {
+        if self.route.is_some() && best.as_ref().is_none_or(|best| depth > best.depth) {
+            *best = Some(Best { depth, end, node: self });
+        }
+        let start = match depth {
+            0 => 0,
+            _ if end == key.len() => return,
+            _ => end + 1,
+        };
+        let segment_end = key[start..].find('.').map_or(key.len(), |i| start + i);
+        let segment = &key[start..segment_end];
+        if let Some(child) = self.children.get(segment) {
+            child.search(key, segment_end, depth + 1, best);
+        }
+        if let Some(child) = self.wildcard.as_deref().filter(|_| !segment.is_empty()) {
+            child.search(key, segment_end, depth + 1, best);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! The HTTP servers behind the `rust-key-store` binary, as [`axum::Router`]s
+//! that can also be served in-process (see `tests/`). This module holds what
+//! both modes share; [`key_server`] and [`shard_router`] build the apps.
+//! [`locks`] holds the key server's leases, and [`fault_proxy`] is the chaos
+//! proxy behind `kvs-chaos`.
+
+use std::{
+    future::IntoFuture,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::watch;
+
+use crate::dump::{ConflictPolicy, NDJSON_CONTENT_TYPE};
+
+pub mod fault_proxy;
+pub mod key_server;
+pub mod locks;
+pub mod shard_router;
+
+#[derive(Serialize)]
+struct UriResponse {
+    uri: String,
+}
+
+/// Process-level facts shared by the health endpoints of both modes.
+pub struct Lifecycle {
+    mode: &'static str,
+    started: Instant,
+    /// Set once shutdown begins, so `/ready` fails while in-flight requests drain.
+    draining: AtomicBool,
+}
+
+impl Lifecycle {
+    pub fn new(mode: &'static str) -> Arc<Self> {
+        Arc::new(Lifecycle {
+            mode,
+            started: Instant::now(),
+            draining: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Marks the process as shutting down; `/ready` fails from now on.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    fn status(&self) -> Value {
+        serde_json::json!({
+            "mode": self.mode,
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime_secs": self.started.elapsed().as_secs(),
+        })
+    }
+}
+
+/// Resolves once `shutdown_rx` reports `true`, e.g. as the future given to
+/// `with_graceful_shutdown`.
+pub async fn wait_for_shutdown(mut shutdown_rx: watch::Receiver<bool>) {
+    let _ = shutdown_rx.wait_for(|stopping| *stopping).await;
+}
+
+/// Runs `server` until it exits on its own, or until shutdown is requested and
+/// in-flight requests have drained. Connections still open after `drain_timeout` are dropped.
+pub async fn drain<F>(server: F, shutdown_rx: watch::Receiver<bool>, drain_timeout: Duration) -> std::io::Result<()>
+where
+    F: IntoFuture<Output = std::io::Result<()>>,
+{
+    let server = server.into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result,
+        _ = wait_for_shutdown(shutdown_rx) => {
+            match tokio::time::timeout(drain_timeout, &mut server).await {
+                Ok(result) => result,
+                Err(_) => {
+                    eprintln!("Drain timeout ({:?}) elapsed, dropping remaining connections", drain_timeout);
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// Liveness: answers as long as the process can serve HTTP at all.
+async fn health() -> Json<Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+fn ready_response(ready: bool, mut body: Value) -> (StatusCode, Json<Value>) {
+    body["ready"] = Value::Bool(ready);
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(body))
+}
+
+/// Set on a `GET /keys/{key}` response when the key has a TTL: the seconds it
+/// has left, so a router moving the key to another shard can keep it.
+pub const TTL_HEADER: &str = "x-kvs-ttl";
+
+/// Imports may carry the whole keyspace, far beyond axum's 2 MB default body limit.
+const IMPORT_BODY_LIMIT: usize = 1 << 30;
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+}
+
+fn ndjson_response(body: Body) -> Response {
+    Response::builder()
+        .header("content-type", NDJSON_CONTENT_TYPE)
+        .body(body)
+        .expect("Failed to build HTTP response")
+}
+
+fn invalid_ndjson(error: String) -> (StatusCode, Json<Value>) {
+    let body = serde_json::json!({ "error": format!("Invalid NDJSON: {}", error) });
+    (StatusCode::BAD_REQUEST, Json(body))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+}
+
+fn etag(version: u64) -> [(HeaderName, String); 1] {
+    [(header::ETAG, format!("\"{}\"", version))]
+}
+
+/// The version an `If-Match` header demands, if any. `*` only demands that the
+/// key exists, which PUT and DELETE require anyway.
+fn if_match(headers: &HeaderMap) -> Result<Option<u64>, (StatusCode, Json<Value>)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or("").trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| {
+            let body = serde_json::json!({ "error": "If-Match must be a version ETag such as \"42\"" });
+            (StatusCode::BAD_REQUEST, Json(body))
+        })
+}
+
+#[derive(Deserialize)]
+struct TransferRequest {
+    destination: String,
+    /// Replace the destination if it exists (409 otherwise)
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Transfer {
+    Copy,
+    Rename,
+}
+
+impl Transfer {
+    fn path(self) -> &'static str {
+        match self {
+            Transfer::Copy => "copy",
+            Transfer::Rename => "rename",
+        }
+    }
+}
+
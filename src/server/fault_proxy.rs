@@ -0,0 +1,261 @@
+//! Chaos mode: an HTTP proxy that sits in front of a key server and injects
+//! latency, connection resets, 5xx responses, truncated bodies and invalid
+//! JSON, for testing how the router and clients cope with a misbehaving shard.
+//!
+//! Every response carries `Connection: close`, so each request arrives on its
+//! own connection and a reset hits exactly one request.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Json, Response},
+    serve::Listener,
+    Router,
+};
+use futures_util::{stream, StreamExt};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+
+/// What to inject. Each fault is a probability from 0.0 to 1.0; `error`,
+/// `truncate` and `invalid_json` exclude each other, so they may add up to at
+/// most 1.0.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    /// Delay before every request is forwarded, in milliseconds
+    pub latency_ms: u64,
+    /// Up to this many milliseconds of extra random delay
+    pub jitter_ms: u64,
+    /// Connections reset (TCP RST) once the request has arrived
+    pub reset: f64,
+    /// Requests answered with `error_status` without reaching the key server
+    pub error: f64,
+    pub error_status: u16,
+    /// Responses cut off halfway through the body; the key server did handle
+    /// the request
+    pub truncate: f64,
+    /// Responses whose body is replaced by its first half: a complete HTTP
+    /// response that is not valid JSON
+    pub invalid_json: f64,
+    /// Makes the random choices reproducible
+    pub seed: Option<u64>,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            latency_ms: 0,
+            jitter_ms: 0,
+            reset: 0.0,
+            error: 0.0,
+            error_status: 503,
+            truncate: 0.0,
+            invalid_json: 0.0,
+            seed: None,
+        }
+    }
+}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, p) in [
+            ("reset", self.reset),
+            ("error", self.error),
+            ("truncate", self.truncate),
+            ("invalid_json", self.invalid_json),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{} must be a probability between 0 and 1, got {}", name, p));
+            }
+        }
+        if self.error + self.truncate + self.invalid_json > 1.0 {
+            return Err("error, truncate and invalid_json must add up to at most 1".to_string());
+        }
+        if !StatusCode::from_u16(self.error_status).is_ok_and(|status| status.is_server_error()) {
+            return Err(format!("error_status must be a 5xx status, got {}", self.error_status));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fault {
+    Error,
+    Truncate,
+    InvalidJson,
+}
+
+struct Faults {
+    config: FaultConfig,
+    rng: Mutex<SmallRng>,
+}
+
+impl Faults {
+    fn chance(&self, p: f64) -> bool {
+        p > 0.0 && self.rng.lock().expect("rng lock poisoned").gen_bool(p)
+    }
+
+    fn delay(&self) -> Duration {
+        let jitter = match self.config.jitter_ms {
+            0 => 0,
+            max => self.rng.lock().expect("rng lock poisoned").gen_range(0..=max),
+        };
+        Duration::from_millis(self.config.latency_ms + jitter)
+    }
+
+    fn pick(&self) -> Option<Fault> {
+        let roll: f64 = self.rng.lock().expect("rng lock poisoned").gen();
+        let FaultConfig { error, truncate, invalid_json, .. } = self.config;
+        if roll < error {
+            Some(Fault::Error)
+        } else if roll < error + truncate {
+            Some(Fault::Truncate)
+        } else if roll < error + truncate + invalid_json {
+            Some(Fault::InvalidJson)
+        } else {
+            None
+        }
+    }
+}
+
+/// Serves the proxy on `listener`, forwarding to the key server at `target`.
+pub async fn serve(listener: TcpListener, target: String, config: FaultConfig) -> io::Result<()> {
+    config.validate().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let rng = match config.seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    };
+    let faults = Arc::new(Faults { config, rng: Mutex::new(rng) });
+    let state = Arc::new(ProxyState { target, client: Client::new(), faults: faults.clone() });
+    let app = Router::new().fallback(proxy).with_state(state);
+    axum::serve(FaultListener { inner: listener, faults }, app).await
+}
+
+/// Accepts connections, resetting some of them instead of handing them to the
+/// HTTP server.
+struct FaultListener {
+    inner: TcpListener,
+    faults: Arc<Faults>,
+}
+
+impl Listener for FaultListener {
+    type Io = TcpStream;
+    type Addr = std::net::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let (stream, addr) = Listener::accept(&mut self.inner).await;
+            if !self.faults.chance(self.faults.config.reset) {
+                return (stream, addr);
+            }
+            tokio::spawn(reset(stream));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// Waits for the request, so the client sees the reset while waiting for the
+/// response rather than while connecting, then closes with an RST.
+async fn reset(mut stream: TcpStream) {
+    let mut buf = [0; 1024];
+    let _ = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+    if let Err(e) = stream.set_zero_linger() {
+        eprintln!("Could not set SO_LINGER for an injected reset: {}", e);
+    }
+}
+
+struct ProxyState {
+    target: String,
+    client: Client,
+    faults: Arc<Faults>,
+}
+
+/// Connection-level headers that are not forwarded in either direction.
+const HOP_BY_HOP: [header::HeaderName; 4] =
+    [header::HOST, header::CONNECTION, header::CONTENT_LENGTH, header::TRANSFER_ENCODING];
+
+async fn proxy(
+    State(state): State<Arc<ProxyState>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    tokio::time::sleep(state.faults.delay()).await;
+    let fault = state.faults.pick();
+    if fault == Some(Fault::Error) {
+        let status = StatusCode::from_u16(state.faults.config.error_status).expect("validated");
+        let body = serde_json::json!({ "error": "Injected fault" });
+        return close((status, Json(body)).into_response());
+    }
+
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let url = format!("{}{}", state.target.trim_end_matches('/'), path);
+    let mut request = state.client.request(method, &url).body(body);
+    for (name, value) in headers.iter().filter(|(name, _)| !HOP_BY_HOP.contains(name)) {
+        request = request.header(name, value);
+    }
+    let upstream = match request.send().await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Fault proxy could not reach {}: {}", url, e);
+            let body = serde_json::json!({ "error": "Fault proxy could not reach the key server" });
+            return close((StatusCode::BAD_GATEWAY, Json(body)).into_response());
+        }
+    };
+
+    let status = upstream.status();
+    let mut response_headers = upstream.headers().clone();
+    for name in &HOP_BY_HOP {
+        response_headers.remove(name);
+    }
+    let bytes = match upstream.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Fault proxy could not read the response from {}: {}", url, e);
+            let body = serde_json::json!({ "error": "Fault proxy could not read the key server's response" });
+            return close((StatusCode::BAD_GATEWAY, Json(body)).into_response());
+        }
+    };
+
+    let body = match fault {
+        Some(Fault::Truncate) => {
+            // Announce the full length, send half, then fail the body so the
+            // connection is dropped mid-response. The pause lets the first half
+            // go out before the failure.
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+            let half = bytes.slice(..bytes.len() / 2);
+            let failure = async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err(io::Error::other("injected truncation"))
+            };
+            Body::from_stream(stream::once(async { Ok(half) }).chain(stream::once(failure)))
+        }
+        Some(Fault::InvalidJson) => Body::from(bytes.slice(..bytes.len() / 2)),
+        _ => Body::from(bytes),
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = response_headers;
+    close(response)
+}
+
+fn close(mut response: Response) -> Response {
+    response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+    response
+}
@@ -0,0 +1,1168 @@
+//! Key-server mode: an in-memory store of JSON values with versioned writes,
+//! optional expiry, and optional memory/key limits with eviction.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    convert::Infallible,
+    fmt,
+    hash::BuildHasher,
+    mem::size_of,
+    path::{Path as FsPath, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, FromRef, Path, Query, State},
+    http::{HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use futures_util::{stream, StreamExt};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::{Number, Value};
+
+use super::{
+    etag, health, if_match, invalid_ndjson,
+    locks::{self, Locks},
+    ndjson_response, ready_response, ImportQuery, Lifecycle, ListQuery, Transfer, TransferRequest, UriResponse,
+    IMPORT_BODY_LIMIT, TTL_HEADER,
+};
+use crate::{
+    dump::{ndjson_line, parse_ndjson, ConflictPolicy, ImportSummary, Record},
+    query::POINTER_HEADER,
+};
+
+pub type Store = Arc<Db>;
+
+/// Values are shared, so a read only clones the `Arc` under the lock and
+/// serializes after releasing it.
+#[derive(Clone)]
+struct Entry {
+    value: Arc<Value>,
+    version: u64,
+}
+
+/// An entry as stored, with what expiry and eviction need.
+struct Slot {
+    entry: Entry,
+    /// Approximate bytes held, see [`entry_size`]
+    size: u64,
+    expires_at: Option<Instant>,
+    /// Milliseconds since the store was created, at the last read or write
+    last_access: AtomicU64,
+    /// Reads and writes since the last write
+    hits: AtomicU32,
+}
+
+impl Slot {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    fn touch(&self, tick: u64) {
+        self.last_access.store(tick, Ordering::Relaxed);
+        let _ = self.hits.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hits| hits.checked_add(1));
+    }
+}
+
+type Shard = HashMap<String, Slot>;
+
+/// Keys are spread over this many independently locked maps.
+const SHARDS: usize = 64;
+
+/// Entries compared per eviction.
+const EVICTION_SAMPLES: usize = 16;
+
+/// How often [`expire_keys`] sweeps the store.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest `?ttl=` a write accepts (ten years), so `now + ttl` never overflows.
+pub const MAX_KEY_TTL: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// Eviction order of an entry under a policy, lowest first.
+type Rank = (u8, u64, u64);
+
+/// What a key server at one of its limits does with a write that needs room.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EvictionPolicy {
+    /// Reject the write with `507 Insufficient Storage`
+    #[default]
+    #[serde(rename = "noeviction")]
+    NoEviction,
+    /// Evict the least recently used keys
+    Lru,
+    /// Evict the least frequently used keys
+    Lfu,
+    /// Evict the keys closest to expiring, then the least recently used ones
+    TtlFirst,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::Lru => "lru",
+            EvictionPolicy::Lfu => "lfu",
+            EvictionPolicy::TtlFirst => "ttl-first",
+        }
+    }
+
+    /// Orders entries for eviction, lowest first. Expired entries always go first.
+    fn rank(self, slot: &Slot, now: Instant) -> Rank {
+        if slot.is_expired(now) {
+            return (0, 0, 0);
+        }
+        let last_access = slot.last_access.load(Ordering::Relaxed);
+        match (self, slot.expires_at) {
+            (EvictionPolicy::Lfu, _) => (1, slot.hits.load(Ordering::Relaxed).into(), last_access),
+            (EvictionPolicy::TtlFirst, Some(at)) => (1, at.duration_since(now).as_millis() as u64, 0),
+            (EvictionPolicy::TtlFirst, None) => (2, last_access, 0),
+            _ => (1, last_access, 0),
+        }
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            "ttl-first" => Ok(EvictionPolicy::TtlFirst),
+            _ => Err(format!("unknown eviction policy {:?}: expected noeviction, lru, lfu or ttl-first", s)),
+        }
+    }
+}
+
+/// Bounds on what a key server holds. Without either limit nothing is evicted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Approximate bytes of keys and values, see [`entry_size`]
+    pub max_memory: Option<u64>,
+    pub max_keys: Option<u64>,
+    pub policy: EvictionPolicy,
+}
+
+impl Limits {
+    fn evicts(&self) -> bool {
+        self.policy != EvictionPolicy::NoEviction && (self.max_memory.is_some() || self.max_keys.is_some())
+    }
+}
+
+/// Parses a byte count with an optional binary suffix: `1048576`, `512kb`, `64mb`, `2gib`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim().to_ascii_lowercase();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let shift = match unit.trim() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => return Err(format!("invalid size {:?}: expected bytes or a k/m/g suffix", s)),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid size {:?}", s))?;
+    number.checked_mul(1 << shift).ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+/// Per-entry cost beyond the key and value: the map slot, the `Arc` and the
+/// bookkeeping above.
+const ENTRY_OVERHEAD: usize = size_of::<(String, Slot)>() + 16;
+
+/// Approximate bytes an entry holds: the key, the value's JSON tree and a fixed
+/// overhead. Strings count their length, not their capacity.
+pub fn entry_size(key: &str, value: &Value) -> u64 {
+    fn value_size(value: &Value) -> usize {
+        size_of::<Value>()
+            + match value {
+                Value::String(s) => s.len(),
+                Value::Array(items) => items.iter().map(value_size).sum(),
+                Value::Object(map) => map.iter().map(|(k, v)| size_of::<String>() + k.len() + value_size(v)).sum(),
+                _ => 0,
+            }
+    }
+    (ENTRY_OVERHEAD + key.len() + value_size(value)) as u64
+}
+
+/// A write that would take a `noeviction` store past its limits, or a value
+/// larger than `max_memory` on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreFull;
+
+impl fmt::Display for StoreFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("store is full")
+    }
+}
+
+impl std::error::Error for StoreFull {}
+
+/// The key server's data. Every write stamps the entry with a fresh version from
+/// a store-wide counter, so a version is never reused, even after delete and re-create.
+///
+/// Each shard has its own lock, so requests for keys on different shards do not
+/// wait for each other. The locks are synchronous and never held across an
+/// `.await`. Operations on several keys lock their shards in index order.
+///
+/// Expired entries read as missing and are removed by [`expire_keys`], by
+/// eviction, or when their key is written again. Until then they still count
+/// towards the limits.
+pub struct Db {
+    shards: Box<[RwLock<Shard>]>,
+    hasher: RandomState,
+    last_version: AtomicU64,
+    limits: Limits,
+    /// When the store was created; access times count from here
+    epoch: Instant,
+    /// Approximate bytes and number of stored entries, expired ones included
+    memory: AtomicU64,
+    keys: AtomicU64,
+    /// Stored entries with a TTL, so sweeps can be skipped when there are none
+    expiring: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Db::new(Limits::default())
+    }
+}
+
+impl Db {
+    pub fn new(limits: Limits) -> Self {
+        Db {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            last_version: AtomicU64::new(0),
+            limits,
+            epoch: Instant::now(),
+            memory: AtomicU64::new(0),
+            keys: AtomicU64::new(0),
+            expiring: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_values(values: HashMap<String, Value>) -> Self {
+        Db::with_values(values, Limits::default()).expect("a store without limits is never full")
+    }
+
+    /// A store under `limits` seeded with `values`, evicting down to the limits
+    /// if the policy allows. Fails if they do not fit under `noeviction`.
+    pub fn with_values(values: HashMap<String, Value>, limits: Limits) -> Result<Self, StoreFull> {
+        let db = Db::new(limits);
+        let mut all = db.write_all();
+        for (key, value) in values {
+            all.insert(key, Arc::new(value), None)?;
+        }
+        drop(all);
+        Ok(db)
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % SHARDS as u64) as usize
+    }
+
+    fn tick(&self, now: Instant) -> u64 {
+        now.duration_since(self.epoch).as_millis() as u64
+    }
+
+    /// The current entry for `key`, counted as an access.
+    /// The live entry for `key` and when it expires.
+    fn get(&self, key: &str) -> Option<(Entry, Option<Instant>)> {
+        let now = Instant::now();
+        let shard = self.shards[self.shard_index(key)].read().expect("store lock poisoned");
+        let slot = shard.get(key).filter(|slot| !slot.is_expired(now))?;
+        slot.touch(self.tick(now));
+        Some((slot.entry.clone(), slot.expires_at))
+    }
+
+    /// Locks the shard holding `key` for writing.
+    fn write(&self, key: &str) -> Locked<'_> {
+        let index = self.shard_index(key);
+        let shard = self.shards[index].write().expect("store lock poisoned");
+        Locked::new(self, vec![(index, shard)])
+    }
+
+    /// Locks the shards holding `keys` for writing.
+    fn write_keys<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> Locked<'_> {
+        self.lock(keys.into_iter().map(|key| self.shard_index(key)))
+    }
+
+    /// Locks every shard for writing.
+    fn write_all(&self) -> Locked<'_> {
+        self.lock(0..SHARDS)
+    }
+
+    fn lock(&self, indices: impl IntoIterator<Item = usize>) -> Locked<'_> {
+        let mut indices: Vec<usize> = indices.into_iter().collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let shards = indices
+            .into_iter()
+            .map(|i| (i, self.shards[i].write().expect("store lock poisoned")))
+            .collect();
+        Locked::new(self, shards)
+    }
+
+    /// Every shard locked for reading, so the result is a consistent view.
+    fn read_all(&self) -> Vec<RwLockReadGuard<'_, Shard>> {
+        self.shards.iter().map(|shard| shard.read().expect("store lock poisoned")).collect()
+    }
+
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Live keys, not counting expired entries that are still stored.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.read_all()
+            .iter()
+            .map(|shard| shard.values().filter(|slot| !slot.is_expired(now)).count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate bytes stored, see [`entry_size`].
+    pub fn memory(&self) -> u64 {
+        self.memory.load(Ordering::Relaxed)
+    }
+
+    /// Every live key and value at one point in time, in no particular order.
+    fn values(&self) -> Vec<(String, Arc<Value>)> {
+        let now = Instant::now();
+        self.read_all()
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .iter()
+                    .filter(|(_, slot)| !slot.is_expired(now))
+                    .map(|(key, slot)| (key.clone(), slot.entry.value.clone()))
+            })
+            .collect()
+    }
+
+    /// Accounts for `slot` replacing `replaced`, if any. Under `noeviction` a
+    /// write that would grow the store past a limit fails instead; an entry
+    /// larger than `max_memory` fails under every policy.
+    fn reserve(&self, slot: &Slot, replaced: Option<&Slot>) -> Result<(), StoreFull> {
+        let Limits { max_memory, max_keys, policy } = self.limits;
+        if max_memory.is_some_and(|max| slot.size > max) {
+            return Err(StoreFull);
+        }
+        let strict = policy == EvictionPolicy::NoEviction;
+        let freed = replaced.map_or(0, |old| old.size);
+        add(&self.memory, slot.size, freed, max_memory.filter(|_| strict))?;
+        if replaced.is_none() {
+            if let Err(full) = add(&self.keys, 1, 0, max_keys.filter(|_| strict)) {
+                add(&self.memory, freed, slot.size, None).expect("no limit");
+                return Err(full);
+            }
+        }
+        if slot.expires_at.is_some() {
+            self.expiring.fetch_add(1, Ordering::Relaxed);
+        }
+        if replaced.is_some_and(|old| old.expires_at.is_some()) {
+            self.expiring.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Accounts for `slot` having been removed.
+    fn release(&self, slot: &Slot) {
+        self.memory.fetch_sub(slot.size, Ordering::Relaxed);
+        self.keys.fetch_sub(1, Ordering::Relaxed);
+        if slot.expires_at.is_some() {
+            self.expiring.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn over_limits(&self) -> bool {
+        self.limits.max_memory.is_some_and(|max| self.memory.load(Ordering::Relaxed) > max)
+            || self.limits.max_keys.is_some_and(|max| self.keys.load(Ordering::Relaxed) > max)
+    }
+
+    /// Evicts entries other than `protected` until the store is within its limits.
+    fn evict(&self, protected: Option<&str>) {
+        let mut rng = rand::thread_rng();
+        while self.over_limits() {
+            let Some((index, key, version)) = self.eviction_candidate(&mut rng, protected) else {
+                return;
+            };
+            let mut shard = self.shards[index].write().expect("store lock poisoned");
+            // Written again since it was sampled: sample again
+            if shard.get(&key).is_none_or(|slot| slot.entry.version != version) {
+                continue;
+            }
+            let slot = shard.remove(&key).expect("checked above");
+            self.release(&slot);
+            let counter = if slot.is_expired(Instant::now()) { &self.expirations } else { &self.evictions };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Samples `EVICTION_SAMPLES` entries and returns the one the policy evicts
+    /// first as (shard, key, version). Samples are runs of consecutive entries
+    /// from a random position, taken from shards in turn starting at a random
+    /// one; map order is hash order, so a run is as good a sample as any. A
+    /// store with no more entries than that is searched exhaustively.
+    fn eviction_candidate(&self, rng: &mut impl Rng, protected: Option<&str>) -> Option<(usize, String, u64)> {
+        let now = Instant::now();
+        let start = rng.gen_range(0..SHARDS);
+        let mut sampled = 0;
+        let mut best: Option<(Rank, usize, String, u64)> = None;
+        for index in (start..SHARDS).chain(0..start) {
+            if sampled == EVICTION_SAMPLES {
+                break;
+            }
+            let shard = self.shards[index].read().expect("store lock poisoned");
+            if shard.is_empty() {
+                continue;
+            }
+            let offset = rng.gen_range(0..shard.len());
+            let run = (EVICTION_SAMPLES - sampled).min(shard.len());
+            sampled += run;
+            for (key, slot) in shard.iter().cycle().skip(offset).take(run) {
+                let rank = self.limits.policy.rank(slot, now);
+                if Some(key.as_str()) != protected && best.as_ref().is_none_or(|(best, ..)| rank < *best) {
+                    best = Some((rank, index, key.clone(), slot.entry.version));
+                }
+            }
+        }
+        best.map(|(_, index, key, version)| (index, key, version))
+    }
+
+    /// Removes expired entries, one shard at a time, and returns how many.
+    pub fn remove_expired(&self) -> u64 {
+        if self.expiring.load(Ordering::Relaxed) == 0 {
+            return 0;
+        }
+        let now = Instant::now();
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            shard.write().expect("store lock poisoned").retain(|_, slot| {
+                if !slot.is_expired(now) {
+                    return true;
+                }
+                self.release(slot);
+                removed += 1;
+                false
+            });
+        }
+        self.expirations.fetch_add(removed, Ordering::Relaxed);
+        removed
+    }
+}
+
+/// Adds `added - removed` to `counter`, failing if that would grow it past `max`.
+fn add(counter: &AtomicU64, added: u64, removed: u64, max: Option<u64>) -> Result<(), StoreFull> {
+    counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            let new = current + added - removed;
+            match max {
+                Some(max) if added > removed && new > max => None,
+                _ => Some(new),
+            }
+        })
+        .map(|_| ())
+        .map_err(|_| StoreFull)
+}
+
+/// Removes expired keys from `store` every second, so keys nobody reads
+/// again do not hold memory. Runs until the task is dropped.
+pub async fn expire_keys(store: Store) {
+    let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        store.remove_expired();
+    }
+}
+
+/// Write locks on the shards holding some keys; only those keys may be used.
+/// When dropped, it releases the locks and then evicts if the writes went past
+/// the store's limits.
+struct Locked<'a> {
+    db: &'a Db,
+    shards: Vec<(usize, RwLockWriteGuard<'a, Shard>)>,
+    now: Instant,
+    /// The last key written, which eviction leaves alone
+    written: Option<String>,
+}
+
+impl<'a> Locked<'a> {
+    fn new(db: &'a Db, shards: Vec<(usize, RwLockWriteGuard<'a, Shard>)>) -> Self {
+        Locked { db, shards, now: Instant::now(), written: None }
+    }
+}
+
+impl Locked<'_> {
+    /// Where the lock on `key`'s shard is in `shards`.
+    fn position(&self, key: &str) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let index = self.db.shard_index(key);
+        self.shards.iter().position(|(i, _)| *i == index).expect("shard for key is locked")
+    }
+
+    fn shard(&self, key: &str) -> &Shard {
+        &self.shards[self.position(key)].1
+    }
+
+    fn shard_mut(&mut self, key: &str) -> &mut Shard {
+        let position = self.position(key);
+        &mut self.shards[position].1
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// The live entry for `key`.
+    fn get(&self, key: &str) -> Option<&Slot> {
+        self.shard(key).get(key).filter(|slot| !slot.is_expired(self.now))
+    }
+
+    /// Stores `value` under `key` and returns its new version.
+    fn insert(&mut self, key: String, value: Arc<Value>, expires_at: Option<Instant>) -> Result<u64, StoreFull> {
+        let tick = self.db.tick(self.now);
+        let slot = Slot {
+            size: entry_size(&key, &value),
+            entry: Entry { value, version: 0 },
+            expires_at,
+            last_access: AtomicU64::new(tick),
+            hits: AtomicU32::new(1),
+        };
+        let replaced = self.shard(&key).get(&key);
+        self.db.reserve(&slot, replaced)?;
+        if replaced.is_some_and(|old| old.is_expired(self.now)) {
+            self.db.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let version = self.db.next_version();
+        let slot = Slot { entry: Entry { version, ..slot.entry }, ..slot };
+        if self.db.limits.evicts() {
+            self.written = Some(key.clone());
+        }
+        self.shard_mut(&key).insert(key, slot);
+        Ok(version)
+    }
+
+    /// Removes `key`'s entry, expired or not.
+    fn take(&mut self, key: &str) -> Option<Slot> {
+        let slot = self.shard_mut(key).remove(key)?;
+        self.db.release(&slot);
+        Some(slot)
+    }
+
+    /// Puts back an entry removed by [`Locked::take`], whatever the limits.
+    fn restore(&mut self, key: String, slot: Slot) {
+        add(&self.db.memory, slot.size, 0, None).expect("no limit");
+        add(&self.db.keys, 1, 0, None).expect("no limit");
+        if slot.expires_at.is_some() {
+            self.db.expiring.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shard_mut(&key).insert(key, slot);
+    }
+}
+
+// Eviction takes shard locks of its own, so it runs once these are released.
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        self.shards.clear();
+        if self.db.limits.evicts() {
+            self.db.evict(self.written.as_deref());
+        }
+    }
+}
+
+/// Fails with 412 if `entry` is not at the `expected` version. The body carries
+/// the current version and value so the caller can show what changed.
+fn check_version(entry: &Entry, expected: Option<u64>) -> Result<(), (StatusCode, Json<Value>)> {
+    match expected {
+        Some(version) if version != entry.version => {
+            let body = serde_json::json!({
+                "error": "Version mismatch",
+                "version": entry.version,
+                "value": entry.value.as_ref(),
+            });
+            Err((StatusCode::PRECONDITION_FAILED, Json(body)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 507 for a write the store has no room for.
+fn store_full(limits: &Limits) -> (StatusCode, Json<Value>) {
+    let body = serde_json::json!({
+        "error": "Store is full",
+        "max_memory": limits.max_memory,
+        "max_keys": limits.max_keys,
+    });
+    (StatusCode::INSUFFICIENT_STORAGE, Json(body))
+}
+
+/// The key server's routes over `store`.
+pub fn app(store: Store, lifecycle: Arc<Lifecycle>) -> Router {
+    let state = KeyServerState { store, locks: Arc::default(), lifecycle };
+    Router::new()
+        .route("/keys/{key}", post(post_key).get(get_key).put(put_key).delete(delete_key))
+        .route("/keys/{key}/copy", post(copy_key))
+        .route("/keys/{key}/rename", post(rename_key))
+        .route("/keys/{key}/incr", post(incr_key))
+        .route("/locks/{name}", post(locks::acquire).get(locks::get_lock))
+        .route("/locks/{name}/renew", post(locks::renew))
+        .route("/locks/{name}/release", post(locks::release))
+        .route("/keys", get(list_keys))
+        .route("/health", get(health))
+        .route("/ready", get(key_server_ready))
+        .route("/status", get(key_server_status))
+        .route("/admin/export", get(export_keys))
+        .route("/admin/import", post(import_keys).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)))
+        .with_state(state)
+}
+
+#[derive(Clone)]
+struct KeyServerState {
+    store: Store,
+    locks: Arc<Locks>,
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl FromRef<KeyServerState> for Store {
+    fn from_ref(state: &KeyServerState) -> Store {
+        state.store.clone()
+    }
+}
+
+impl FromRef<KeyServerState> for Arc<Locks> {
+    fn from_ref(state: &KeyServerState) -> Arc<Locks> {
+        state.locks.clone()
+    }
+}
+
+#[derive(Deserialize)]
+struct WriteQuery {
+    /// Seconds until the key expires; without it the key never does
+    ttl: Option<f64>,
+}
+
+impl WriteQuery {
+    fn expires_at(&self) -> Result<Option<Instant>, (StatusCode, Json<Value>)> {
+        let Some(ttl) = self.ttl else {
+            return Ok(None);
+        };
+        match Duration::try_from_secs_f64(ttl) {
+            Ok(ttl) if !ttl.is_zero() && ttl <= MAX_KEY_TTL => Ok(Some(Instant::now() + ttl)),
+            _ => {
+                let body = serde_json::json!({
+                    "error": format!("ttl must be a positive number of seconds, at most {}", MAX_KEY_TTL.as_secs()),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(body)))
+            }
+        }
+    }
+}
+
+async fn post_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    Query(query): Query<WriteQuery>,
+    Json(value): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let expires_at = query.expires_at()?;
+    let mut db = store.write(&key);
+    if db.contains_key(&key) {
+        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
+        return Err((StatusCode::CONFLICT, Json(body)));
+    }
+    let version = db.insert(key.clone(), Arc::new(value), expires_at).map_err(|_| store_full(&store.limits))?;
+    let uri = format!("/keys/{}", urlencoding::encode(&key));
+    let res = UriResponse { uri };
+    Ok((StatusCode::CREATED, etag(version), Json(res)))
+}
+
+// Lists key names (not values) starting with `prefix`, sorted.
+async fn list_keys(
+    Query(query): Query<ListQuery>,
+    State(store): State<Store>,
+) -> Json<Value> {
+    let now = Instant::now();
+    let mut keys: Vec<String> = store
+        .read_all()
+        .iter()
+        .flat_map(|shard| {
+            shard
+                .iter()
+                .filter(|(key, slot)| key.starts_with(&query.prefix) && !slot.is_expired(now))
+                .map(|(key, _)| key.clone())
+        })
+        .collect();
+    keys.sort_unstable();
+    if let Some(limit) = query.limit {
+        keys.truncate(limit);
+    }
+    Json(serde_json::json!({ "keys": keys }))
+}
+
+#[derive(Deserialize)]
+struct GetQuery {
+    /// JSON Pointer into the value; only that sub-document is returned
+    pointer: Option<String>,
+}
+
+/// The [`TTL_HEADER`] for a key expiring at `expires_at`, rounded up to the
+/// millisecond so a key about to expire never reads as having no time left.
+fn remaining_ttl(expires_at: Option<Instant>) -> Option<[(HeaderName, String); 1]> {
+    let remaining = expires_at?.saturating_duration_since(Instant::now());
+    let millis = remaining.as_nanos().div_ceil(1_000_000).max(1);
+    Some([(HeaderName::from_static(TTL_HEADER), format!("{}.{:03}", millis / 1000, millis % 1000))])
+}
+
+async fn get_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    Query(query): Query<GetQuery>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let Some((entry, expires_at)) = store.get(&key) else {
+        let body = serde_json::json!({ "error": "Key not found" });
+        return Err((StatusCode::NOT_FOUND, Json(body)));
+    };
+    let ttl = remaining_ttl(expires_at);
+    let Some(pointer) = query.pointer else {
+        return Ok((etag(entry.version), ttl, Json(entry.value.as_ref())).into_response());
+    };
+    // The ETag stays the key's version, so a sub-document read can guard a later write
+    match entry.value.pointer(&pointer) {
+        Some(value) => {
+            let marker = [(HeaderName::from_static(POINTER_HEADER), pointer.clone())];
+            Ok((etag(entry.version), ttl, marker, Json(value)).into_response())
+        }
+        None => {
+            let body = serde_json::json!({ "error": "Pointer not found", "pointer": pointer });
+            Err((StatusCode::NOT_FOUND, Json(body)))
+        }
+    }
+}
+
+// With `If-Match: "<version>"`, the update only applies if the key is still at
+// that version (412 otherwise), which lets read-modify-write clients detect races.
+// The key's TTL is replaced by `ttl`, or removed without one.
+async fn put_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    Query(query): Query<WriteQuery>,
+    headers: HeaderMap,
+    Json(value): Json<Value>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let expected = if_match(&headers)?;
+    let expires_at = query.expires_at()?;
+    let mut db = store.write(&key);
+    let Some(slot) = db.get(&key) else {
+        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
+        return Err((StatusCode::NOT_FOUND, Json(body)));
+    };
+    check_version(&slot.entry, expected)?;
+    let version = db.insert(key.clone(), Arc::new(value), expires_at).map_err(|_| store_full(&store.limits))?;
+    let uri = format!("/keys/{}", urlencoding::encode(&key));
+    Ok((etag(version), Json(UriResponse { uri })))
+}
+
+async fn delete_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+) -> Result<Json<UriResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let expected = if_match(&headers)?;
+    let mut db = store.write(&key);
+    let Some(slot) = db.get(&key) else {
+        let body = serde_json::json!({ "uri": format!("/keys/{}", urlencoding::encode(&key)) });
+        return Err((StatusCode::NOT_FOUND, Json(body)));
+    };
+    check_version(&slot.entry, expected)?;
+    db.take(&key);
+    let uri = format!("/keys/{}", urlencoding::encode(&key));
+    Ok(Json(UriResponse { uri }))
+}
+
+async fn copy_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+    Json(request): Json<TransferRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    transfer_key(Transfer::Copy, key, &store, &headers, request).await
+}
+
+async fn rename_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+    Json(request): Json<TransferRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    transfer_key(Transfer::Rename, key, &store, &headers, request).await
+}
+
+// Runs with both keys' shards locked, so no reader ever sees a rename half done.
+// `If-Match` applies to the source, and the destination gets the source's TTL.
+// Answers 201 if the destination was created and 200 if it was replaced, with
+// the destination's URI and new version.
+async fn transfer_key(
+    transfer: Transfer,
+    key: String,
+    store: &Store,
+    headers: &HeaderMap,
+    request: TransferRequest,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let expected = if_match(headers)?;
+    let destination = request.destination;
+    if destination == key {
+        let body = serde_json::json!({ "error": "Source and destination are the same key" });
+        return Err((StatusCode::BAD_REQUEST, Json(body)));
+    }
+
+    let mut db = store.write_keys([key.as_str(), destination.as_str()]);
+    let Some(slot) = db.get(&key) else {
+        let body = serde_json::json!({ "error": "Key not found" });
+        return Err((StatusCode::NOT_FOUND, Json(body)));
+    };
+    check_version(&slot.entry, expected)?;
+    let uri = format!("/keys/{}", urlencoding::encode(&destination));
+    let replaced = db.contains_key(&destination);
+    if replaced && !request.overwrite {
+        let body = serde_json::json!({ "error": "Destination exists", "uri": uri });
+        return Err((StatusCode::CONFLICT, Json(body)));
+    }
+
+    // A rename frees the source first, so it never needs room for both
+    let (value, expires_at) = (slot.entry.value.clone(), slot.expires_at);
+    let source = match transfer {
+        Transfer::Copy => None,
+        Transfer::Rename => db.take(&key),
+    };
+    let version = match db.insert(destination, value, expires_at) {
+        Ok(version) => version,
+        Err(StoreFull) => {
+            if let Some(slot) = source {
+                db.restore(key, slot);
+            }
+            return Err(store_full(&store.limits));
+        }
+    };
+    let status = if replaced { StatusCode::OK } else { StatusCode::CREATED };
+    Ok((status, etag(version), Json(UriResponse { uri })))
+}
+
+/// Body of `POST /keys/{key}/incr`; every field is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct IncrRequest {
+    /// Added to the number; negative to decrement. 1 by default
+    delta: Option<Number>,
+    /// JSON Pointer to the number inside the value; the whole value by default
+    pointer: String,
+    /// Create a missing key or object member at `initial` instead of answering 404
+    create: bool,
+    /// Where a created number starts, before `delta` is added. 0 by default
+    initial: Option<Number>,
+}
+
+// Reads, adds and writes back under the key's shard lock, so concurrent
+// increments never lose an update. Integers stay integers as long as the result
+// fits in an i64 or u64 (409 otherwise); any float makes it a float. Answers
+// the new number, with 201 if the key was created. The key keeps its TTL.
+async fn incr_key(
+    Path(key): Path<String>,
+    State(store): State<Store>,
+    headers: HeaderMap,
+    request: Option<Json<IncrRequest>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let expected = if_match(&headers)?;
+    let delta = request.delta.unwrap_or_else(|| Number::from(1));
+    let initial = request.initial.unwrap_or_else(|| Number::from(0));
+
+    let mut db = store.write(&key);
+    let (mut value, expires_at) = match db.get(&key) {
+        Some(slot) => {
+            check_version(&slot.entry, expected)?;
+            (Value::clone(&slot.entry.value), slot.expires_at)
+        }
+        None if request.create && expected.is_none() => match request.pointer.as_str() {
+            "" => (Value::from(initial.clone()), None),
+            _ => (Value::Object(Default::default()), None),
+        },
+        None => {
+            let body = serde_json::json!({ "error": "Key not found" });
+            return Err((StatusCode::NOT_FOUND, Json(body)));
+        }
+    };
+    let created = !db.contains_key(&key);
+
+    let initial = request.create.then_some(&initial);
+    let target = number_at(&mut value, &request.pointer, initial)?;
+    let Value::Number(current) = &*target else {
+        let body = serde_json::json!({ "error": "Value is not a number", "pointer": request.pointer });
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(body)));
+    };
+    let Some(sum) = add_numbers(current, &delta) else {
+        let body = serde_json::json!({ "error": "Increment would overflow", "value": current, "delta": delta });
+        return Err((StatusCode::CONFLICT, Json(body)));
+    };
+    *target = Value::Number(sum.clone());
+
+    let version = db.insert(key, Arc::new(value), expires_at).map_err(|_| store_full(&store.limits))?;
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, etag(version), Json(serde_json::json!({ "value": sum }))))
+}
+
+/// What `pointer` points at in `value`. With `initial`, missing object members
+/// on the way are created as empty objects, and the last one as `initial`;
+/// array elements must exist.
+fn number_at<'v>(
+    value: &'v mut Value,
+    pointer: &str,
+    initial: Option<&Number>,
+) -> Result<&'v mut Value, (StatusCode, Json<Value>)> {
+    let not_found = || {
+        let body = serde_json::json!({ "error": "Pointer not found", "pointer": pointer });
+        (StatusCode::NOT_FOUND, Json(body))
+    };
+    if pointer.is_empty() {
+        return Ok(value);
+    }
+    let Some(path) = pointer.strip_prefix('/') else {
+        return Err(not_found());
+    };
+
+    let mut tokens = path.split('/').peekable();
+    let mut target = value;
+    while let Some(token) = tokens.next() {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        target = match target {
+            Value::Object(members) => match initial {
+                Some(initial) => members.entry(token).or_insert_with(|| match tokens.peek() {
+                    Some(_) => Value::Object(Default::default()),
+                    None => Value::from(initial.clone()),
+                }),
+                None => members.get_mut(&token).ok_or_else(not_found)?,
+            },
+            Value::Array(items) => token.parse::<usize>().ok().and_then(|i| items.get_mut(i)).ok_or_else(not_found)?,
+            _ => return Err(not_found()),
+        };
+    }
+    Ok(target)
+}
+
+/// `current + delta`: exact if both are integers, in f64 otherwise. `None`
+/// if the sum does not fit an i64 or u64, or is not a finite float.
+fn add_numbers(current: &Number, delta: &Number) -> Option<Number> {
+    let integer = |n: &Number| n.as_i64().map(i128::from).or_else(|| n.as_u64().map(i128::from));
+    match (integer(current), integer(delta)) {
+        (Some(current), Some(delta)) => {
+            let sum = current + delta;
+            i64::try_from(sum).map(Number::from).or_else(|_| u64::try_from(sum).map(Number::from)).ok()
+        }
+        _ => Number::from_f64(current.as_f64()? + delta.as_f64()?),
+    }
+}
+
+// The values are snapshotted under the read locks and serialized afterwards,
+// so a slow reader never blocks writers. TTLs are not exported.
+async fn export_keys(State(store): State<Store>) -> Response {
+    let mut values = store.values();
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let lines = stream::iter(values)
+        .map(|(key, value)| Ok::<_, Infallible>(ndjson_line(&Record { key, value: value.as_ref() })));
+    ndjson_response(Body::from_stream(lines))
+}
+
+// A store at its limits under `noeviction` stops at the first record that does
+// not fit and answers 507 with what was imported up to there.
+async fn import_keys(
+    Query(query): Query<ImportQuery>,
+    State(store): State<Store>,
+    body: String,
+) -> Result<Json<ImportSummary>, (StatusCode, Json<serde_json::Value>)> {
+    let records = parse_ndjson(&body).map_err(invalid_ndjson)?;
+
+    let mut db = store.write_all();
+    if query.on_conflict == ConflictPolicy::Fail {
+        let conflicts: Vec<&str> = records
+            .iter()
+            .filter(|r| db.contains_key(&r.key))
+            .map(|r| r.key.as_str())
+            .collect();
+        if !conflicts.is_empty() {
+            let body = serde_json::json!({ "error": "Keys already exist", "conflicts": conflicts });
+            return Err((StatusCode::CONFLICT, Json(body)));
+        }
+    }
+
+    let (mut imported, mut skipped) = (0u64, 0u64);
+    for Record { key, value } in records {
+        if query.on_conflict == ConflictPolicy::Skip && db.contains_key(&key) {
+            skipped += 1;
+            continue;
+        }
+        if db.insert(key, Arc::new(value), None).is_err() {
+            let (status, Json(mut body)) = store_full(&store.limits);
+            body["imported"] = serde_json::json!(imported);
+            body["skipped"] = serde_json::json!(skipped);
+            return Err((status, Json(body)));
+        }
+        imported += 1;
+    }
+    Ok(Json(ImportSummary { imported, skipped }))
+}
+
+// The store is populated before the listener binds, so a key server is ready
+// as soon as it accepts connections and stops being ready once it starts draining.
+async fn key_server_ready(State(state): State<KeyServerState>) -> (StatusCode, Json<Value>) {
+    ready_response(!state.lifecycle.is_draining(), serde_json::json!({}))
+}
+
+// `memory_bytes` is approximate (see `entry_size`) and includes expired keys
+// not yet removed; `evictions` and `expired` count removals since startup.
+async fn key_server_status(State(state): State<KeyServerState>) -> Json<Value> {
+    let store = &state.store;
+    let limits = store.limits;
+    let mut body = state.lifecycle.status();
+    body["keys"] = serde_json::json!(store.len());
+    body["memory_bytes"] = serde_json::json!(store.memory());
+    body["evictions"] = serde_json::json!(store.evictions.load(Ordering::Relaxed));
+    body["expired"] = serde_json::json!(store.expirations.load(Ordering::Relaxed));
+    body["limits"] = serde_json::json!({
+        "max_memory": limits.max_memory,
+        "max_keys": limits.max_keys,
+        "eviction_policy": limits.policy.as_str(),
+    });
+    Json(body)
+}
+
+/// On-disk layouts accepted by `--load` and written by `--snapshot` / `--save-on-exit`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DumpFormat {
+    /// A single `{"key": value, ...}` object
+    Object,
+    /// One `Record` per line, as produced by `/admin/export`
+    Ndjson,
+}
+
+impl DumpFormat {
+    /// `.ndjson` and `.jsonl` files are NDJSON; anything else is a JSON object.
+    pub fn for_path(path: &FsPath) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ndjson" | "jsonl") => DumpFormat::Ndjson,
+            _ => DumpFormat::Object,
+        }
+    }
+}
+
+/// Reads a dump file in either format and reports which one it was.
+///
+/// Files named `*.ndjson`/`*.jsonl` are always NDJSON. Otherwise the whole file
+/// is tried as one JSON object first, since a one-line NDJSON dump is also a
+/// valid object; if that fails and the first line is JSON on its own, the file
+/// is read as NDJSON.
+pub fn load_dump(path: &FsPath) -> Result<(HashMap<String, Value>, DumpFormat), String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    let format = match DumpFormat::for_path(path) {
+        DumpFormat::Ndjson => DumpFormat::Ndjson,
+        DumpFormat::Object => match serde_json::from_str::<Value>(&content) {
+            Ok(Value::Object(_)) => {
+                let DumpObject(db) = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+                return Ok((db, DumpFormat::Object));
+            }
+            Ok(_) => return Err("expected a JSON object of keys or NDJSON records".to_string()),
+            Err(e) => {
+                let first_line = content.lines().find(|line| !line.trim().is_empty()).unwrap_or("");
+                if serde_json::from_str::<Value>(first_line).is_err() {
+                    return Err(e.to_string());
+                }
+                DumpFormat::Ndjson
+            }
+        },
+    };
+
+    let mut db = HashMap::new();
+    for Record { key, value } in parse_ndjson(&content)? {
+        if db.contains_key(&key) {
+            return Err(format!("duplicate key {:?}", key));
+        }
+        db.insert(key, value);
+    }
+    Ok((db, format))
+}
+
+/// The keys of an object dump. Unlike a `Value`, which keeps the last of a
+/// repeated key, a key given twice is an error, as it is in an NDJSON dump.
+struct DumpObject(HashMap<String, Value>);
+
+impl<'de> Deserialize<'de> for DumpObject {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ObjectVisitor {
+            type Value = DumpObject;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object of keys")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<DumpObject, A::Error> {
+                let mut db = HashMap::new();
+                while let Some((key, value)) = map.next_entry::<String, Value>()? {
+                    if db.contains_key(&key) {
+                        return Err(serde::de::Error::custom(format_args!("duplicate key {:?}", key)));
+                    }
+                    db.insert(key, value);
+                }
+                Ok(DumpObject(db))
+            }
+        }
+
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+/// Writes the whole store to `path` in `format`.
+/// The file is written next to its destination first and renamed into place,
+/// so an interrupted write never leaves a truncated dump behind.
+pub fn write_dump(path: &FsPath, db: &Db, format: DumpFormat) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let values = db.values();
+    let sorted: BTreeMap<&String, &Value> = values.iter().map(|(key, value)| (key, value.as_ref())).collect();
+    let bytes = match format {
+        DumpFormat::Object => serde_json::to_vec_pretty(&sorted)?,
+        DumpFormat::Ndjson => sorted
+            .into_iter()
+            .flat_map(|(key, value)| ndjson_line(&Record { key: key.clone(), value }))
+            .collect(),
+    };
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
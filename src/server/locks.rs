@@ -0,0 +1,249 @@
+//! Leases on named locks, served by key servers under `/locks/{name}`. A lease
+//! is held until it is released, or until its holder stops renewing it and the
+//! TTL runs out. Each acquisition gets a fencing token greater than every
+//! token handed out before it.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use crate::lease::{MAX_TTL, MAX_WAIT};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// Fewest leases at which expired ones are pruned.
+const PRUNE_MIN: usize = 64;
+
+struct Lease {
+    token: u64,
+    owner: Option<String>,
+    ttl: Duration,
+    expires_at: Instant,
+}
+
+impl Lease {
+    fn to_json(&self, now: Instant) -> Value {
+        serde_json::json!({
+            "token": self.token,
+            "owner": self.owner,
+            "ttl": self.ttl.as_secs_f64(),
+            "expires_in": self.expires_at.saturating_duration_since(now).as_secs_f64(),
+        })
+    }
+}
+
+/// Leases by name. Expired leases stay until their name is used again or the
+/// map has doubled since the last prune, so unique names can't grow it forever.
+struct Held {
+    leases: HashMap<String, Lease>,
+    prune_at: usize,
+}
+
+impl Held {
+    fn prune(&mut self, now: Instant) {
+        if self.leases.len() >= self.prune_at {
+            self.leases.retain(|_, lease| lease.expires_at > now);
+            self.prune_at = (self.leases.len() * 2).max(PRUNE_MIN);
+        }
+    }
+}
+
+pub struct Locks {
+    held: Mutex<Held>,
+    next_token: AtomicU64,
+    /// Woken whenever a lease is released, so waiters try again
+    released: Notify,
+}
+
+impl Default for Locks {
+    // Tokens start at the current time in microseconds, so they keep growing
+    // across restarts as long as the clock does.
+    fn default() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Locks {
+            held: Mutex::new(Held { leases: HashMap::new(), prune_at: PRUNE_MIN }),
+            next_token: AtomicU64::new(now.as_micros() as u64),
+            released: Notify::new(),
+        }
+    }
+}
+
+impl Locks {
+    /// The new lease, or the current holder's lease and when it runs out.
+    fn try_acquire(&self, name: &str, owner: Option<&str>, ttl: Duration) -> Result<Value, (Value, Instant)> {
+        let mut held = self.held.lock().unwrap();
+        let now = Instant::now();
+        if let Some(lease) = held.leases.get(name).filter(|lease| lease.expires_at > now) {
+            return Err((lease.to_json(now), lease.expires_at));
+        }
+        held.prune(now);
+        let lease = Lease {
+            token: self.next_token.fetch_add(1, Ordering::SeqCst),
+            owner: owner.map(str::to_string),
+            ttl,
+            expires_at: now + ttl,
+        };
+        let body = lease.to_json(now);
+        held.leases.insert(name.to_string(), lease);
+        Ok(body)
+    }
+
+    /// Extends the lease `token` holds by `ttl`, or by its original TTL.
+    fn renew(&self, name: &str, token: u64, ttl: Option<Duration>) -> Option<Value> {
+        let mut held = self.held.lock().unwrap();
+        let now = Instant::now();
+        let lease = held.leases.get_mut(name).filter(|lease| lease.token == token && lease.expires_at > now)?;
+        lease.ttl = ttl.unwrap_or(lease.ttl);
+        lease.expires_at = now + lease.ttl;
+        Some(lease.to_json(now))
+    }
+
+    /// Whether `token` still held the lease. An expired lease is cleared either way.
+    fn release(&self, name: &str, token: u64) -> bool {
+        let mut held = self.held.lock().unwrap();
+        let Some(lease) = held.leases.get(name).filter(|lease| lease.token == token) else {
+            return false;
+        };
+        let live = lease.expires_at > Instant::now();
+        held.leases.remove(name);
+        drop(held);
+        self.released.notify_waiters();
+        live
+    }
+
+    fn holder(&self, name: &str) -> Option<Value> {
+        let now = Instant::now();
+        let held = self.held.lock().unwrap();
+        held.leases.get(name).filter(|lease| lease.expires_at > now).map(|lease| lease.to_json(now))
+    }
+}
+
+/// `seconds` as a duration; 400 if it is negative or not finite.
+fn duration(field: &str, seconds: f64) -> Result<Duration, (StatusCode, Json<Value>)> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        let body = serde_json::json!({ "error": format!("{} must be a non-negative number of seconds", field) });
+        (StatusCode::BAD_REQUEST, Json(body))
+    })
+}
+
+/// A TTL up to [`MAX_TTL`], so `now + ttl` never overflows under the lock.
+fn lease_ttl(seconds: f64) -> Result<Duration, (StatusCode, Json<Value>)> {
+    match duration("ttl", seconds)? {
+        ttl if ttl.is_zero() || ttl > MAX_TTL => {
+            let body = serde_json::json!({
+                "error": format!("ttl must be a positive number of seconds, at most {}", MAX_TTL.as_secs()),
+            });
+            Err((StatusCode::BAD_REQUEST, Json(body)))
+        }
+        ttl => Ok(ttl),
+    }
+}
+
+fn lease_lost() -> (StatusCode, Json<Value>) {
+    let body = serde_json::json!({ "error": "Lease lost" });
+    (StatusCode::CONFLICT, Json(body))
+}
+
+/// Body of `POST /locks/{name}`; every field is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct AcquireRequest {
+    /// Seconds the lease lasts unless renewed, at most [`MAX_TTL`]; 30 by default
+    ttl: Option<f64>,
+    /// Seconds to wait for a held lock to be released (at most [`MAX_WAIT`]); 0 by default
+    wait: Option<f64>,
+    /// Shown to anyone who finds the lock held
+    owner: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct RenewRequest {
+    token: u64,
+    /// New TTL in seconds; the lease's current TTL by default
+    ttl: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ReleaseRequest {
+    token: u64,
+}
+
+// Answers 201 with the lease and its fencing token. If the lock is held it
+// waits up to `wait` seconds for a release or expiry, then answers 409 with
+// the holder's lease.
+pub(super) async fn acquire(
+    Path(name): Path<String>,
+    State(locks): State<Arc<Locks>>,
+    request: Option<Json<AcquireRequest>>,
+) -> Result<(StatusCode, Json<Value>), (StatusCode, Json<Value>)> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let ttl = request.ttl.map(lease_ttl).transpose()?.unwrap_or(DEFAULT_TTL);
+    let wait = duration("wait", request.wait.unwrap_or(0.0))?.min(MAX_WAIT);
+    let deadline = Instant::now() + wait;
+
+    loop {
+        // Registered before checking, so a release in between still wakes us
+        let released = locks.released.notified();
+        tokio::pin!(released);
+        released.as_mut().enable();
+
+        let (holder, expires_at) = match locks.try_acquire(&name, request.owner.as_deref(), ttl) {
+            Ok(lease) => return Ok((StatusCode::CREATED, Json(lease))),
+            Err(holder) => holder,
+        };
+        if Instant::now() >= deadline {
+            let mut body = serde_json::json!({ "error": "Lock is held" });
+            body["holder"] = holder;
+            return Err((StatusCode::CONFLICT, Json(body)));
+        }
+        let _ = tokio::time::timeout_at(deadline.min(expires_at).into(), released).await;
+    }
+}
+
+pub(super) async fn renew(
+    Path(name): Path<String>,
+    State(locks): State<Arc<Locks>>,
+    Json(request): Json<RenewRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let ttl = request.ttl.map(lease_ttl).transpose()?;
+    locks.renew(&name, request.token, ttl).map(Json).ok_or_else(lease_lost)
+}
+
+// 409 if the lease had already expired or passed to someone else, so the
+// holder learns that its work may have overlapped with another's.
+pub(super) async fn release(
+    Path(name): Path<String>,
+    State(locks): State<Arc<Locks>>,
+    Json(request): Json<ReleaseRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    if !locks.release(&name, request.token) {
+        return Err(lease_lost());
+    }
+    Ok(Json(serde_json::json!({ "released": true })))
+}
+
+pub(super) async fn get_lock(
+    Path(name): Path<String>,
+    State(locks): State<Arc<Locks>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    locks.holder(&name).map(Json).ok_or_else(|| {
+        let body = serde_json::json!({ "error": "Lock not held" });
+        (StatusCode::NOT_FOUND, Json(body))
+    })
+}
@@ -0,0 +1,815 @@
+//! Shard-router mode: forwards each key to the key server that owns its prefix.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, RawQuery, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
+    Router,
+};
+use futures_util::{future::try_join_all, stream, StreamExt};
+use reqwest::Client;
+use serde_json::Value;
+
+use super::{
+    etag, health, if_match, invalid_ndjson, ndjson_response, ready_response, ImportQuery, Lifecycle, ListQuery,
+    Transfer, TransferRequest, UriResponse, IMPORT_BODY_LIMIT, TTL_HEADER,
+};
+use crate::{
+    dump::{ndjson_line, parse_ndjson, ImportSummary, Record, NDJSON_CONTENT_TYPE},
+    query::POINTER_HEADER,
+    routing::{is_pattern, RouteTable, RoutesConfig},
+};
+
+/// The router's routes, forwarding to the key servers in `routes` (prefix → base URL).
+pub fn app(routes: HashMap<String, String>, lifecycle: Arc<Lifecycle>) -> Router {
+    let state = Arc::new(ShardRouterState {
+        routes: RouteTable::new(routes),
+        client: Client::new(),
+        lifecycle,
+    });
+    Router::new()
+        .route("/keys/{key}", post(router_post).get(router_get).put(router_put).delete(router_delete))
+        .route("/keys/{key}/copy", post(router_copy))
+        .route("/keys/{key}/rename", post(router_rename))
+        .route("/keys/{key}/incr", post(router_incr))
+        .route("/locks/{name}", post(router_acquire).get(router_lock))
+        .route("/locks/{name}/renew", post(router_renew))
+        .route("/locks/{name}/release", post(router_release))
+        .route("/keys", get(router_list))
+        .route("/health", get(health))
+        .route("/ready", get(router_ready))
+        .route("/status", get(router_status))
+        .route("/admin/routes", get(router_routes))
+        .route("/admin/export", get(router_export))
+        .route("/admin/import", post(router_import).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)))
+        .with_state(state)
+}
+
+struct ShardRouterState {
+    routes: RouteTable,
+    client: Client,
+    lifecycle: Arc<Lifecycle>,
+}
+
+const BACKEND_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl ShardRouterState {
+    /// Distinct backend URLs, in a stable order.
+    fn targets(&self) -> Vec<&str> {
+        let mut targets: Vec<&str> = self.routes.iter().map(|(_, target)| target).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    /// Each backend URL with the prefixes routed to it, sorted, ordered by the
+    /// backends' first prefixes.
+    fn shards(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut by_target: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (prefix, target) in self.routes.iter() {
+            by_target.entry(target).or_default().push(prefix);
+        }
+        let mut shards: Vec<(&str, Vec<&str>)> = by_target
+            .into_iter()
+            .map(|(target, mut prefixes)| {
+                prefixes.sort_unstable();
+                (target, prefixes)
+            })
+            .collect();
+        shards.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+        shards
+    }
+
+    /// GETs `path` from every backend concurrently. A backend that fails to answer
+    /// with a 2xx JSON body within `BACKEND_PROBE_TIMEOUT` maps to `None`.
+    async fn probe_backends(&self, path: &str) -> Vec<(String, Option<Value>)> {
+        let probes = self.targets().into_iter().map(|target| {
+            let url = format!("{}{}", target.trim_end_matches('/'), path);
+            let request = self.client.get(url).timeout(BACKEND_PROBE_TIMEOUT).send();
+            let target = target.to_string();
+            async move {
+                let body = match request.await {
+                    Ok(res) if res.status().is_success() => res.json::<Value>().await.ok(),
+                    _ => None,
+                };
+                (target, body)
+            }
+        });
+        let handles: Vec<_> = probes.map(tokio::spawn).collect();
+
+        let mut results = Vec::with_capacity(handles.len());
+        for handle in handles {
+            results.push(handle.await.expect("backend probe panicked"));
+        }
+        results
+    }
+}
+
+async fn route_and_proxy(
+    method: Method,
+    full_key: String,
+    routes: &RouteTable,
+    client: &Client,
+    body: Option<Value>,
+    headers: &HeaderMap,
+    query: Option<&str>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let route = routes.find(&full_key).ok_or_else(|| {
+        let body = serde_json::json!({ "error": "No route found for key" });
+        (StatusCode::NOT_FOUND, Json(body))
+    })?;
+
+    let encoded_backend_key = urlencoding::encode(route.backend_key);
+    let mut url = format!("{}/keys/{}", route.target.trim_end_matches('/'), encoded_backend_key);
+    if let Some(query) = query {
+        url = format!("{}?{}", url, query);
+    }
+    let is_write = method != Method::GET;
+
+    let req_builder = match method {
+        Method::POST => client.post(&url),
+        Method::GET => client.get(&url),
+        Method::PUT => client.put(&url),
+        Method::DELETE => client.delete(&url),
+        _ => unreachable!(),
+    };
+
+    let mut request = if let Some(val) = body {
+        req_builder.json(&val)
+    } else {
+        req_builder
+    };
+    // Versions belong to the backend that owns the key, so preconditions pass straight through
+    if let Some(if_match) = headers.get(header::IF_MATCH) {
+        request = request.header(header::IF_MATCH, if_match);
+    }
+
+    let res = request.send().await
+        .map_err(|e| {
+            eprintln!("Proxy error to {}: {}", url, e);
+            let body = serde_json::json!({ "error": "Upstream key-server unavailable" });
+            (StatusCode::BAD_GATEWAY, Json(body))
+        })?;
+
+    let status = res.status();
+    let etag = res.headers().get(header::ETAG).cloned();
+    let pointer = res.headers().get(POINTER_HEADER).cloned();
+    let ttl = res.headers().get(TTL_HEADER).cloned();
+    let mut json_res = upstream_json(res, &url).await?;
+
+    // Rewrite URI to reflect the original key space (not backend's view).
+    // Only write responses carry one; a read returns the stored value as-is.
+    if is_write && json_res.get("uri").is_some() {
+        json_res["uri"] = serde_json::json!(format!("/keys/{}", urlencoding::encode(&full_key)));
+    }
+
+    let body_bytes = serde_json::to_vec(&json_res).expect("Failed to serialize JSON response");
+    let mut builder = Response::builder()
+        .status(status)
+        .header("content-type", "application/json");
+    if let Some(etag) = etag {
+        builder = builder.header(header::ETAG, etag);
+    }
+    if let Some(pointer) = pointer {
+        builder = builder.header(POINTER_HEADER, pointer);
+    }
+    if let Some(ttl) = ttl {
+        builder = builder.header(TTL_HEADER, ttl);
+    }
+    let response = builder
+        .body(axum::body::Body::from(body_bytes))
+        .expect("Failed to build HTTP response");
+
+    Ok(response)
+}
+
+/// Reads a key server's JSON body. A body that breaks off (connection reset,
+/// truncated response) and one that arrives whole but does not parse are both
+/// 502s, with different messages.
+async fn upstream_json(res: reqwest::Response, url: &str) -> Result<Value, (StatusCode, Json<serde_json::Value>)> {
+    let bytes = res.bytes().await.map_err(|e| {
+        eprintln!("Reading response from {} failed: {}", url, e);
+        let body = serde_json::json!({ "error": "Upstream response was incomplete" });
+        (StatusCode::BAD_GATEWAY, Json(body))
+    })?;
+    serde_json::from_slice(&bytes).map_err(|_| {
+        let body = serde_json::json!({ "error": "Upstream returned invalid JSON" });
+        (StatusCode::BAD_GATEWAY, Json(body))
+    })
+}
+
+// The query string (e.g. `?ttl=` or `?pointer=`) is passed through to the key server.
+async fn router_post(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    Json(value): Json<Value>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let query = query.as_deref();
+    route_and_proxy(Method::POST, key, &state.routes, &state.client, Some(value), &headers, query).await
+}
+
+async fn router_get(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    route_and_proxy(Method::GET, key, &state.routes, &state.client, None, &headers, query.as_deref()).await
+}
+
+async fn router_put(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    Json(value): Json<Value>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let query = query.as_deref();
+    route_and_proxy(Method::PUT, key, &state.routes, &state.client, Some(value), &headers, query).await
+}
+
+async fn router_delete(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    route_and_proxy(Method::DELETE, key, &state.routes, &state.client, None, &headers, None).await
+}
+
+async fn router_copy(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    Json(request): Json<TransferRequest>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    state.transfer(Transfer::Copy, key, &headers, request).await
+}
+
+async fn router_rename(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    Json(request): Json<TransferRequest>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    state.transfer(Transfer::Rename, key, &headers, request).await
+}
+
+// The owning key server applies the increment under its own lock.
+async fn router_incr(
+    Path(key): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    headers: HeaderMap,
+    request: Option<Json<Value>>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let Some(route) = state.routes.find(&key) else {
+        let body = serde_json::json!({ "error": "No route found for key" });
+        return Err((StatusCode::NOT_FOUND, Json(body)));
+    };
+    let url = format!("{}/incr", backend_key_url(route.target, route.backend_key));
+    let request = request.map(|Json(request)| request);
+    let reply = state.backend_call(Method::POST, &url, request.as_ref(), if_match(&headers)?).await?;
+    Ok(json_response(reply.status, reply.version, reply.body))
+}
+
+// A lock lives on the key server that owns its name as a key, so leases on
+// different shards are independent. Long-polls are held open through the router.
+async fn router_acquire(
+    Path(name): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    request: Option<Json<Value>>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let request = request.map(|Json(request)| request);
+    state.lock_call(Method::POST, &name, "", request.as_ref()).await
+}
+
+async fn router_lock(
+    Path(name): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    state.lock_call(Method::GET, &name, "", None).await
+}
+
+async fn router_renew(
+    Path(name): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    Json(request): Json<Value>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    state.lock_call(Method::POST, &name, "/renew", Some(&request)).await
+}
+
+async fn router_release(
+    Path(name): Path<String>,
+    State(state): State<Arc<ShardRouterState>>,
+    Json(request): Json<Value>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    state.lock_call(Method::POST, &name, "/release", Some(&request)).await
+}
+
+/// What a key server answered: status, version from the `ETag`, and JSON body.
+struct BackendReply {
+    status: StatusCode,
+    version: Option<u64>,
+    /// Seconds the key has left, from a read of a key with a TTL
+    ttl: Option<f64>,
+    body: Value,
+}
+
+fn backend_key_url(target: &str, backend_key: &str) -> String {
+    format!("{}/keys/{}", target.trim_end_matches('/'), urlencoding::encode(backend_key))
+}
+
+/// `key_url` for a write that gives the key `ttl` seconds to live, or no TTL.
+fn with_ttl(key_url: &str, ttl: Option<f64>) -> String {
+    match ttl {
+        Some(ttl) => format!("{}?ttl={}", key_url, ttl),
+        None => key_url.to_string(),
+    }
+}
+
+fn transfer_response(status: StatusCode, version: Option<u64>, full_key: &str) -> Response {
+    let uri = format!("/keys/{}", urlencoding::encode(full_key));
+    json_response(status, version, serde_json::json!(UriResponse { uri }))
+}
+
+fn json_response(status: StatusCode, version: Option<u64>, body: Value) -> Response {
+    let mut response = (status, Json(body)).into_response();
+    if let Some(version) = version {
+        let [(name, value)] = etag(version);
+        response.headers_mut().insert(name, value.parse().expect("ETag is a valid header value"));
+    }
+    response
+}
+
+impl ShardRouterState {
+    /// One request to a key server. Failures to reach it or to parse its body
+    /// become the same 502s `route_and_proxy` returns.
+    async fn backend_call(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+        if_match: Option<u64>,
+    ) -> Result<BackendReply, (StatusCode, Json<serde_json::Value>)> {
+        let mut request = self.client.request(method, url);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        if let Some(version) = if_match {
+            request = request.header(header::IF_MATCH, format!("\"{}\"", version));
+        }
+        let res = request.send().await.map_err(|e| {
+            eprintln!("Proxy error to {}: {}", url, e);
+            let body = serde_json::json!({ "error": "Upstream key-server unavailable" });
+            (StatusCode::BAD_GATEWAY, Json(body))
+        })?;
+        let status = res.status();
+        let version = res
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .and_then(|etag| etag.trim_matches('"').parse().ok());
+        let ttl = res
+            .headers()
+            .get(TTL_HEADER)
+            .and_then(|ttl| ttl.to_str().ok())
+            .and_then(|ttl| ttl.parse().ok());
+        let body = upstream_json(res, url).await?;
+        Ok(BackendReply { status, version, ttl, body })
+    }
+
+    /// Forwards a `/locks/{name}{suffix}` request to the shard owning `name`.
+    async fn lock_call(
+        &self,
+        method: Method,
+        name: &str,
+        suffix: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+        let Some(route) = self.routes.find(name) else {
+            let body = serde_json::json!({ "error": "No route found for lock" });
+            return Err((StatusCode::NOT_FOUND, Json(body)));
+        };
+        let url = format!(
+            "{}/locks/{}{}",
+            route.target.trim_end_matches('/'),
+            urlencoding::encode(route.backend_key),
+            suffix
+        );
+        let reply = self.backend_call(method, &url, body, None).await?;
+        Ok(json_response(reply.status, None, reply.body))
+    }
+
+    /// Copies or renames `key` to `request.destination`. When one backend owns
+    /// both keys it does the whole transfer under its own lock; otherwise the
+    /// router moves the value itself (see `transfer_across_shards`).
+    async fn transfer(
+        &self,
+        transfer: Transfer,
+        key: String,
+        headers: &HeaderMap,
+        request: TransferRequest,
+    ) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+        let no_route = || {
+            let body = serde_json::json!({ "error": "No route found for key" });
+            (StatusCode::NOT_FOUND, Json(body))
+        };
+        let source = self.routes.find(&key).ok_or_else(no_route)?;
+        let destination = self.routes.find(&request.destination).ok_or_else(no_route)?;
+        let (source_target, source_key) = (source.target, source.backend_key);
+        let (destination_target, destination_key) = (destination.target, destination.backend_key);
+        let expected = if_match(headers)?;
+
+        if source_target == destination_target {
+            let url = format!("{}/{}", backend_key_url(source_target, source_key), transfer.path());
+            let body = serde_json::json!({ "destination": destination_key, "overwrite": request.overwrite });
+            let reply = self.backend_call(Method::POST, &url, Some(&body), expected).await?;
+            if !reply.status.is_success() {
+                let mut body = reply.body;
+                if body.get("uri").is_some() {
+                    body["uri"] = serde_json::json!(format!("/keys/{}", urlencoding::encode(&request.destination)));
+                }
+                return Err((reply.status, Json(body)));
+            }
+            return Ok(transfer_response(reply.status, reply.version, &request.destination));
+        }
+
+        let source_url = backend_key_url(source_target, source_key);
+        let destination_url = backend_key_url(destination_target, destination_key);
+        self.transfer_across_shards(transfer, &source_url, &destination_url, &request, expected).await
+    }
+
+    /// Two-phase transfer between backends. The source is read, then the
+    /// destination written (guarded by its version if it is being replaced)
+    /// with the source's remaining TTL.
+    /// A rename then deletes the source only if it is still at the version that
+    /// was read; if that fails the destination is put back the way it was.
+    /// Readers may briefly see the value under both keys.
+    async fn transfer_across_shards(
+        &self,
+        transfer: Transfer,
+        source_url: &str,
+        destination_url: &str,
+        request: &TransferRequest,
+        expected: Option<u64>,
+    ) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+        let no_version = || {
+            let body = serde_json::json!({ "error": "Upstream returned no version" });
+            (StatusCode::BAD_GATEWAY, Json(body))
+        };
+        let destination_uri = format!("/keys/{}", urlencoding::encode(&request.destination));
+
+        let source = self.backend_call(Method::GET, source_url, None, None).await?;
+        if source.status != StatusCode::OK {
+            return Err((source.status, Json(source.body)));
+        }
+        let source_version = source.version.ok_or_else(no_version)?;
+        if expected.is_some_and(|version| version != source_version) {
+            let body = serde_json::json!({
+                "error": "Version mismatch",
+                "version": source_version,
+                "value": source.body,
+            });
+            return Err((StatusCode::PRECONDITION_FAILED, Json(body)));
+        }
+
+        // Phase 1: write the destination, remembering what it held for a rollback
+        let existing = self.backend_call(Method::GET, destination_url, None, None).await?;
+        let write_url = with_ttl(destination_url, source.ttl);
+        let (written, previous) = match existing.status {
+            StatusCode::NOT_FOUND => {
+                let written = self.backend_call(Method::POST, &write_url, Some(&source.body), None).await?;
+                (written, None)
+            }
+            StatusCode::OK if request.overwrite => {
+                let version = existing.version.ok_or_else(no_version)?;
+                let written = self
+                    .backend_call(Method::PUT, &write_url, Some(&source.body), Some(version))
+                    .await?;
+                (written, Some(existing))
+            }
+            StatusCode::OK => {
+                let body = serde_json::json!({ "error": "Destination exists", "uri": destination_uri });
+                return Err((StatusCode::CONFLICT, Json(body)));
+            }
+            status => return Err((status, Json(existing.body))),
+        };
+        match written.status {
+            status if status.is_success() => {}
+            // Someone else wrote the destination between our read and write
+            StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => {
+                let body = serde_json::json!({ "error": "Destination changed concurrently", "uri": destination_uri });
+                return Err((StatusCode::CONFLICT, Json(body)));
+            }
+            status => return Err((status, Json(written.body))),
+        }
+        let written_version = written.version.ok_or_else(no_version)?;
+        let status = if previous.is_some() { StatusCode::OK } else { StatusCode::CREATED };
+        if transfer == Transfer::Copy {
+            return Ok(transfer_response(status, Some(written_version), &request.destination));
+        }
+
+        // Phase 2: remove the source, unless it changed since it was read
+        let removed = self.backend_call(Method::DELETE, source_url, None, Some(source_version)).await;
+        if removed.as_ref().is_ok_and(|reply| reply.status.is_success()) {
+            return Ok(transfer_response(status, Some(written_version), &request.destination));
+        }
+
+        let rollback = match &previous {
+            None => self.backend_call(Method::DELETE, destination_url, None, Some(written_version)).await,
+            Some(existing) => {
+                let url = with_ttl(destination_url, existing.ttl);
+                self.backend_call(Method::PUT, &url, Some(&existing.body), Some(written_version)).await
+            }
+        };
+        if !rollback.is_ok_and(|reply| reply.status.is_success()) {
+            eprintln!("Rename to {} failed and could not be rolled back", request.destination);
+            let body = serde_json::json!({
+                "error": "Rename failed and could not be rolled back; the value may exist under both keys",
+                "uri": destination_uri,
+            });
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(body)));
+        }
+        match removed {
+            Ok(_) => {
+                let body = serde_json::json!({ "error": "Source changed during rename; nothing was moved" });
+                Err((StatusCode::CONFLICT, Json(body)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Maps a key as stored on the backend for `prefix` back to the router's key space.
+/// Returns `None` for keys the router would never send to `prefix` (for example
+/// keys written to the backend directly), so an export only contains routable keys.
+fn router_key(prefix: &str, backend_key: &str, routes: &RouteTable) -> Option<String> {
+    let full_key = if backend_key.starts_with('.') {
+        format!("{}{}", prefix, backend_key)
+    } else {
+        backend_key.to_string()
+    };
+    match routes.find(&full_key) {
+        Some(route) if route.prefix == prefix && route.backend_key == backend_key => Some(full_key),
+        _ => None,
+    }
+}
+
+impl ShardRouterState {
+    /// Fetches a backend's dump once and rewrites it into the router's key space.
+    /// A record is exported under every one of `prefixes` that routes to it.
+    async fn export_shard(&self, target: &str, prefixes: &[String]) -> std::io::Result<Vec<u8>> {
+        let url = format!("{}/admin/export", target.trim_end_matches('/'));
+        let fetch = async {
+            let res = self.client.get(&url).send().await?.error_for_status()?;
+            res.text().await
+        };
+        let dump = fetch.await.map_err(|e| {
+            eprintln!("Export from {} failed: {}", url, e);
+            std::io::Error::other(format!("export from {} failed", target))
+        })?;
+        let records = parse_ndjson(&dump).map_err(|e| {
+            eprintln!("Export from {} returned invalid NDJSON: {}", url, e);
+            std::io::Error::other(format!("invalid export from {}", target))
+        })?;
+
+        let mut exported: Vec<Record<&Value>> = records
+            .iter()
+            .flat_map(|record| {
+                prefixes.iter().filter_map(|prefix| {
+                    let key = router_key(prefix, &record.key, &self.routes)?;
+                    Some(Record { key, value: &record.value })
+                })
+            })
+            .collect();
+        exported.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(exported.iter().flat_map(ndjson_line).collect())
+    }
+
+    /// Lists the keys under `listed` held by `target` for any of `prefixes`, in
+    /// the router's key space. The backend is asked once per distinct backend
+    /// prefix, leaving out those another listing already covers, and concurrently.
+    async fn list_shard(&self, target: &str, prefixes: &[&str], listed: &str) -> Result<Vec<String>, reqwest::Error> {
+        let mut backend_prefixes: Vec<&str> =
+            prefixes.iter().filter_map(|prefix| backend_list_prefix(prefix, listed)).collect();
+        // Sorted, a prefix comes right before the longer ones it covers
+        backend_prefixes.sort_unstable();
+        let mut covering: Vec<&str> = Vec::new();
+        for backend_prefix in backend_prefixes {
+            if !covering.iter().any(|covered| backend_prefix.starts_with(covered)) {
+                covering.push(backend_prefix);
+            }
+        }
+
+        let url = format!("{}/keys", target.trim_end_matches('/'));
+        let listings = covering.into_iter().map(|backend_prefix| {
+            let request = self.client.get(&url).query(&[("prefix", backend_prefix)]);
+            async move {
+                let body: Value = request.send().await?.error_for_status()?.json().await?;
+                Ok::<_, reqwest::Error>(body)
+            }
+        });
+
+        let mut keys = Vec::new();
+        for body in try_join_all(listings).await? {
+            let backend_keys = body["keys"].as_array().map(Vec::as_slice).unwrap_or_default();
+            for backend_key in backend_keys.iter().filter_map(Value::as_str) {
+                keys.extend(
+                    prefixes
+                        .iter()
+                        .filter_map(|prefix| router_key(prefix, backend_key, &self.routes))
+                        .filter(|key| key.starts_with(listed)),
+                );
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The backend prefix that covers every key under `listed` stored for the route
+/// `route_prefix`, or `None` if that route cannot hold any such key. When keys
+/// stored under their full name may match (see [`RouteTable::find`]), the
+/// whole shard is listed and filtered afterwards; a `*` route stores every key
+/// under its full name.
+fn backend_list_prefix<'a>(route_prefix: &str, listed: &'a str) -> Option<&'a str> {
+    if is_pattern(route_prefix) {
+        return Some(listed);
+    }
+    match listed.strip_prefix(route_prefix) {
+        Some("" | "." | "..") => Some(""),
+        Some(rest) if rest.starts_with('.') => Some(rest),
+        Some(_) => None,
+        None if route_prefix.starts_with(listed) => Some(""),
+        None => None,
+    }
+}
+
+async fn router_list(
+    Query(query): Query<ListQuery>,
+    State(state): State<Arc<ShardRouterState>>,
+) -> Result<Json<Value>, (StatusCode, Json<serde_json::Value>)> {
+    let listings = state.shards().into_iter().map(|(target, prefixes)| {
+        let state = &state;
+        let listed = query.prefix.as_str();
+        async move {
+            state.list_shard(target, &prefixes, listed).await.map_err(|e| {
+                eprintln!("Listing keys on {} failed: {}", target, e);
+                let body = serde_json::json!({ "error": "Upstream key-server unavailable" });
+                (StatusCode::BAD_GATEWAY, Json(body))
+            })
+        }
+    });
+    let mut keys: Vec<String> = try_join_all(listings).await?.into_iter().flatten().collect();
+    keys.sort_unstable();
+    keys.dedup();
+    if let Some(limit) = query.limit {
+        keys.truncate(limit);
+    }
+    Ok(Json(serde_json::json!({ "keys": keys })))
+}
+
+// Each backend is exported once, in the order of its first prefix. If a backend
+// fails midway the response body is aborted, so a truncated dump is never
+// mistaken for a complete one.
+async fn router_export(State(state): State<Arc<ShardRouterState>>) -> Response {
+    let shards: Vec<(String, Vec<String>)> = state
+        .shards()
+        .into_iter()
+        .map(|(target, prefixes)| (target.to_string(), prefixes.into_iter().map(str::to_string).collect()))
+        .collect();
+
+    let exports = stream::iter(shards).then(move |(target, prefixes)| {
+        let state = state.clone();
+        async move { state.export_shard(&target, &prefixes).await }
+    });
+    ndjson_response(Body::from_stream(exports))
+}
+
+// Records are split per route and each batch is imported by its shard with the
+// same conflict policy. `fail` is all-or-nothing per shard, not across shards.
+async fn router_import(
+    Query(query): Query<ImportQuery>,
+    State(state): State<Arc<ShardRouterState>>,
+    body: String,
+) -> Result<Json<ImportSummary>, (StatusCode, Json<serde_json::Value>)> {
+    let records = parse_ndjson(&body).map_err(invalid_ndjson)?;
+
+    let mut batches: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut unrouted = Vec::new();
+    for Record { key, value } in records {
+        match state.routes.find(&key) {
+            Some(route) => batches
+                .entry(route.prefix.to_string())
+                .or_default()
+                .extend(ndjson_line(&Record { key: route.backend_key.to_string(), value })),
+            None => unrouted.push(key),
+        }
+    }
+    if !unrouted.is_empty() {
+        let body = serde_json::json!({ "error": "No route found for keys", "keys": unrouted });
+        return Err((StatusCode::BAD_REQUEST, Json(body)));
+    }
+
+    let mut batches: Vec<(String, Vec<u8>)> = batches.into_iter().collect();
+    batches.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let (mut imported, mut skipped) = (0u64, 0u64);
+    let mut failed = serde_json::Map::new();
+    let mut only_conflicts = true;
+    for (prefix, batch) in batches {
+        let target = state.routes.get(&prefix).expect("batches are keyed by route prefix").trim_end_matches('/');
+        let url = format!("{}/admin/import?on_conflict={}", target, query.on_conflict.as_str());
+        let res = state.client
+            .post(&url)
+            .header("content-type", NDJSON_CONTENT_TYPE)
+            .body(batch)
+            .send()
+            .await;
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("Import to {} failed: {}", url, e);
+                only_conflicts = false;
+                failed.insert(prefix, serde_json::json!({ "error": "Upstream key-server unavailable" }));
+                continue;
+            }
+        };
+
+        let status = res.status();
+        let mut body: Value = res.json().await.unwrap_or(Value::Null);
+        if status.is_success() {
+            imported += body["imported"].as_u64().unwrap_or(0);
+            skipped += body["skipped"].as_u64().unwrap_or(0);
+            continue;
+        }
+        if status == StatusCode::CONFLICT {
+            // Report conflicting keys in the router's key space
+            if let Some(conflicts) = body["conflicts"].as_array_mut() {
+                for key in conflicts.iter_mut() {
+                    if let Some(full_key) = key.as_str().and_then(|k| router_key(&prefix, k, &state.routes)) {
+                        *key = Value::String(full_key);
+                    }
+                }
+            }
+        } else {
+            only_conflicts = false;
+        }
+        failed.insert(prefix, serde_json::json!({ "status": status.as_u16(), "body": body }));
+    }
+
+    if failed.is_empty() {
+        return Ok(Json(ImportSummary { imported, skipped }));
+    }
+    let status = if only_conflicts { StatusCode::CONFLICT } else { StatusCode::BAD_GATEWAY };
+    let body = serde_json::json!({
+        "error": "Import failed on some shards",
+        "imported": imported,
+        "skipped": skipped,
+        "failed": failed,
+    });
+    Err((status, Json(body)))
+}
+
+// Routes are validated at startup, so the router is ready when it is not
+// draining and at least one backend answers its own `/health`.
+async fn router_ready(State(state): State<Arc<ShardRouterState>>) -> (StatusCode, Json<Value>) {
+    let probes = state.probe_backends("/health").await;
+    let reachable = probes.iter().any(|(_, body)| body.is_some());
+
+    let backends: serde_json::Map<String, Value> = probes
+        .into_iter()
+        .map(|(target, body)| (target, serde_json::json!(if body.is_some() { "up" } else { "down" })))
+        .collect();
+    let ready = reachable && !state.lifecycle.is_draining();
+    ready_response(ready, serde_json::json!({ "backends": backends }))
+}
+
+// `keys` sums the backends that answered; unreachable ones are reported as `null`.
+async fn router_status(State(state): State<Arc<ShardRouterState>>) -> Json<Value> {
+    let routes = state.routes.entries();
+
+    let mut keys = 0;
+    let mut backends = serde_json::Map::new();
+    for (target, body) in state.probe_backends("/status").await {
+        let count = body.as_ref().and_then(|b| b["keys"].as_u64());
+        keys += count.unwrap_or(0);
+        backends.insert(target, serde_json::json!({ "up": body.is_some(), "keys": count }));
+    }
+
+    let mut body = state.lifecycle.status();
+    body["keys"] = serde_json::json!(keys);
+    body["routes"] = serde_json::json!(routes);
+    body["backends"] = Value::Object(backends);
+    Json(body)
+}
+
+// Lets shard-aware clients route requests themselves; same format as the routes file.
+async fn router_routes(State(state): State<Arc<ShardRouterState>>) -> Json<RoutesConfig> {
+    Json(RoutesConfig(state.routes.entries()))
+}
+
@@ -0,0 +1,103 @@
+//! `kvs-client` end to end: the binary against an in-process key server.
+
+mod common;
+
+use std::process::{Output, Stdio};
+
+use axum::{http::StatusCode, response::Json, routing::post as route_post, Router};
+use common::{get, key_server, key_url, post, spawn};
+use serde_json::json;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// Runs `kvs-client --server <base> <args>`, feeding it `stdin`.
+async fn kvs_client(base: &str, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kvs-client"))
+        .arg("--server")
+        .arg(base)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start kvs-client");
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.as_bytes()).await.unwrap();
+    drop(input);
+    child.wait_with_output().await.unwrap()
+}
+
+#[tokio::test]
+async fn prefix_transfers_stop_at_segment_boundaries() {
+    let (base, _) = key_server().await;
+    for key in ["user", "user.a", "user.b.c", "users.x", "username"] {
+        post(&key_url(&base, key), &json!(key)).await;
+    }
+
+    let moved = kvs_client(&base, &["mv", "--prefix", "user", "people"], "").await;
+    assert!(moved.status.success(), "{}", String::from_utf8_lossy(&moved.stderr));
+
+    let keys = get(&format!("{}/keys", base)).await.body["keys"].clone();
+    assert_eq!(keys, json!(["people", "people.a", "people.b.c", "username", "users.x"]));
+    assert_eq!(get(&key_url(&base, "people.b.c")).await.body, "user.b.c");
+}
+
+#[tokio::test]
+async fn raw_values_drop_one_trailing_newline_and_escape_at_signs() {
+    let (base, _) = key_server().await;
+    let value = |key: &str| {
+        let url = key_url(&base, key);
+        async move { get(&url).await.body }
+    };
+
+    assert!(kvs_client(&base, &["set", "motd", "--raw", "-"], "Hello, world\n").await.status.success());
+    assert_eq!(value("motd").await, "Hello, world");
+    assert!(kvs_client(&base, &["update", "motd", "--raw", "-"], "two\r\nlines\n\n").await.status.success());
+    assert_eq!(value("motd").await, "two\r\nlines\n");
+
+    let file = std::env::temp_dir().join(format!("kvs-cli-{}.txt", std::process::id()));
+    std::fs::write(&file, "from a file\n").unwrap();
+    let from_file = format!("@{}", file.display());
+    assert!(kvs_client(&base, &["set", "note", "--raw", &from_file], "").await.status.success());
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(value("note").await, "from a file");
+
+    assert!(kvs_client(&base, &["set", "handle", "--raw", "@@home"], "").await.status.success());
+    assert_eq!(value("handle").await, "@home");
+    // Without --raw the escaped text still has to be JSON
+    let invalid = kvs_client(&base, &["set", "json", "@@home"], "").await;
+    assert_eq!(invalid.status.code(), Some(2));
+}
+
+// A router answers 409 when a cross-shard rename loses a race with a writer;
+// that is a concurrent change, not an existing key.
+#[tokio::test]
+async fn conflicts_other_than_existing_keys_exit_as_precondition_failures() {
+    let raced = Router::new().route(
+        "/keys/{key}/rename",
+        route_post(|| async {
+            let body = json!({ "error": "Source changed during rename; nothing was moved" });
+            (StatusCode::CONFLICT, Json(body))
+        }),
+    );
+    let base = spawn(raced).await;
+
+    let moved = kvs_client(&base, &["mv", "a", "b"], "").await;
+    assert_eq!(moved.status.code(), Some(5), "{}", String::from_utf8_lossy(&moved.stderr));
+    assert!(String::from_utf8_lossy(&moved.stderr).contains("Source changed during rename"));
+
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "a"), &json!(1)).await;
+    post(&key_url(&base, "b"), &json!(2)).await;
+    let exists = kvs_client(&base, &["mv", "a", "b"], "").await;
+    assert_eq!(exists.status.code(), Some(4));
+}
+
+#[tokio::test]
+async fn yaml_output_renders_values() {
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "user"), &json!({ "name": "Ada", "tags": ["a", "b"], "age": 36 })).await;
+
+    let shown = kvs_client(&base, &["--output", "yaml", "get", "user"], "").await;
+    assert!(shown.status.success());
+    assert_eq!(String::from_utf8_lossy(&shown.stdout), "age: 36\nname: Ada\ntags:\n- a\n- b\n");
+}
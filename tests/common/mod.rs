@@ -0,0 +1,133 @@
+//! In-process key servers and routers on ephemeral ports.
+
+#![allow(dead_code)] // each test binary uses a different subset
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use axum::{middleware, Router};
+use rust_key_store::server::{
+    fault_proxy::{self, FaultConfig},
+    key_server::{self, Db, Limits, Store},
+    shard_router, Lifecycle,
+};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+/// Serves `app` on 127.0.0.1 with an OS-assigned port and returns its base URL.
+/// The server runs until the test's runtime shuts down.
+pub async fn spawn(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind ephemeral port");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("test server failed");
+    });
+    format!("http://{}", addr)
+}
+
+/// An empty key server. The store is returned so tests can look behind the API.
+pub async fn key_server() -> (String, Store) {
+    let store: Store = Arc::new(Db::default());
+    let url = spawn(key_server::app(store.clone(), Lifecycle::new("key-server"))).await;
+    (url, store)
+}
+
+/// An empty key server under `limits`.
+pub async fn key_server_with_limits(limits: Limits) -> (String, Store) {
+    let store: Store = Arc::new(Db::new(limits));
+    let url = spawn(key_server::app(store.clone(), Lifecycle::new("key-server"))).await;
+    (url, store)
+}
+
+/// An empty key server that counts the requests it answers on `path`.
+pub async fn counted_key_server(path: &'static str) -> (String, Store, Arc<AtomicUsize>) {
+    let store: Store = Arc::new(Db::default());
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let app = key_server::app(store.clone(), Lifecycle::new("key-server")).layer(middleware::from_fn(
+        move |request: axum::extract::Request, next: middleware::Next| {
+            if request.uri().path() == path {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            next.run(request)
+        },
+    ));
+    (spawn(app).await, store, hits)
+}
+
+/// A router over `routes`, given as (prefix, backend URL) pairs.
+pub async fn router(routes: &[(&str, &str)]) -> String {
+    let routes: HashMap<String, String> = routes
+        .iter()
+        .map(|(prefix, target)| (prefix.to_string(), target.to_string()))
+        .collect();
+    spawn(shard_router::app(routes, Lifecycle::new("shard-router"))).await
+}
+
+/// A fault-injecting proxy in front of `target`.
+pub async fn fault_proxy(target: &str, faults: FaultConfig) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind ephemeral port");
+    let addr = listener.local_addr().expect("local addr");
+    let target = target.to_string();
+    tokio::spawn(async move {
+        fault_proxy::serve(listener, target, faults).await.expect("fault proxy failed");
+    });
+    format!("http://{}", addr)
+}
+
+/// A URL nothing listens on: the port was just bound and released.
+pub async fn dead_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind ephemeral port");
+    let addr = listener.local_addr().expect("local addr");
+    drop(listener);
+    format!("http://{}", addr)
+}
+
+/// One shared HTTP client; building a client per request is slow.
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+pub fn key_url(base: &str, key: &str) -> String {
+    format!("{}/keys/{}", base, urlencoding::encode(key))
+}
+
+/// Status, `ETag` and JSON body of a response.
+pub struct Reply {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub body: Value,
+}
+
+pub async fn send(request: reqwest::RequestBuilder) -> Reply {
+    let res = request.send().await.expect("request failed");
+    let status = res.status().as_u16();
+    let etag = res
+        .headers()
+        .get("etag")
+        .map(|etag| etag.to_str().expect("ASCII ETag").to_string());
+    let body = res.json().await.unwrap_or(Value::Null);
+    Reply { status, etag, body }
+}
+
+pub async fn post(url: &str, value: &Value) -> Reply {
+    send(client().post(url).json(value)).await
+}
+
+pub async fn get(url: &str) -> Reply {
+    send(client().get(url)).await
+}
+
+pub async fn put(url: &str, value: &Value) -> Reply {
+    send(client().put(url).json(value)).await
+}
+
+pub async fn delete(url: &str) -> Reply {
+    send(client().delete(url)).await
+}
@@ -0,0 +1,94 @@
+//! `rust-key-store` settings: flags over `KVS_*` variables over the config
+//! file over defaults, resolved without touching the real environment.
+
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, time::Duration};
+
+use rust_key_store::{
+    config::{Config, Listen, Settings, DEFAULT_DRAIN_TIMEOUT},
+    server::key_server::EvictionPolicy,
+};
+
+/// The environment layer as if only `vars` were set.
+fn env(vars: &[(&str, &str)]) -> Result<Settings, String> {
+    let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    Settings::from_env(|name| vars.get(name).cloned())
+}
+
+fn tcp(addr: &str) -> Listen {
+    Listen::Tcp(addr.parse::<SocketAddr>().unwrap())
+}
+
+#[test]
+fn defaults_apply_when_no_layer_sets_anything() {
+    let config = Config::resolve(Settings::default(), env(&[]).unwrap(), Settings::default()).unwrap();
+    assert_eq!(config.listen, tcp("127.0.0.1:3000"));
+    assert_eq!(config.drain_timeout, DEFAULT_DRAIN_TIMEOUT);
+    assert_eq!(config.drain_timeout, Duration::from_secs(30));
+    assert_eq!(config.routes, None);
+    assert!(!config.save_on_exit);
+    assert_eq!(config.limits.policy, EvictionPolicy::NoEviction);
+}
+
+#[test]
+fn flags_override_env_which_overrides_the_file() {
+    let file = Settings::from_toml("port = 4000\nbind = \"0.0.0.0\"\ndrain_timeout = 5\nmax_keys = 10\n").unwrap();
+
+    // The file alone
+    let config = Config::resolve(Settings::default(), env(&[]).unwrap(), file.clone()).unwrap();
+    assert_eq!(config.listen, tcp("0.0.0.0:4000"));
+    assert_eq!(config.drain_timeout, Duration::from_secs(5));
+    assert_eq!(config.limits.max_keys, Some(10));
+
+    // The environment over the file, field by field
+    let vars = env(&[("KVS_PORT", "5000"), ("KVS_DRAIN_TIMEOUT", "7")]).unwrap();
+    let config = Config::resolve(Settings::default(), vars.clone(), file.clone()).unwrap();
+    assert_eq!(config.listen, tcp("0.0.0.0:5000"));
+    assert_eq!(config.drain_timeout, Duration::from_secs(7));
+    assert_eq!(config.limits.max_keys, Some(10));
+
+    // Flags over both
+    let flags = Settings { port: Some(6000), drain_timeout: Some(0), ..Settings::default() };
+    let config = Config::resolve(flags, vars, file).unwrap();
+    assert_eq!(config.listen, tcp("0.0.0.0:6000"));
+    assert_eq!(config.drain_timeout, Duration::ZERO);
+}
+
+#[test]
+fn a_unix_socket_replaces_the_tcp_listener() {
+    let flags = Settings { port: Some(6000), ..Settings::default() };
+    let vars = env(&[("KVS_UNIX_SOCKET", "/tmp/kvs.sock")]).unwrap();
+    let config = Config::resolve(flags, vars, Settings::default()).unwrap();
+    assert_eq!(config.listen, Listen::Unix(PathBuf::from("/tmp/kvs.sock")));
+}
+
+#[test]
+fn unknown_config_file_keys_are_rejected() {
+    let err = Settings::from_toml("prot = 4000\n").unwrap_err();
+    assert!(err.contains("prot"), "{}", err);
+    assert!(Settings::from_toml("port = \"high\"\n").is_err());
+}
+
+#[test]
+fn invalid_environment_values_name_the_variable() {
+    let err = env(&[("KVS_PORT", "http")]).unwrap_err();
+    assert!(err.starts_with("KVS_PORT: invalid value \"http\""), "{}", err);
+    assert!(env(&[("KVS_EVICTION_POLICY", "random")]).unwrap_err().starts_with("KVS_EVICTION_POLICY"));
+    assert_eq!(env(&[("KVS_SAVE_ON_EXIT", "true")]).unwrap().save_on_exit, Some(true));
+}
+
+#[test]
+fn meaningless_combinations_are_errors() {
+    let resolve = |flags: Settings| Config::resolve(flags, Settings::default(), Settings::default());
+
+    let save = Settings { save_on_exit: Some(true), ..Settings::default() };
+    assert_eq!(resolve(save).unwrap_err(), "--save-on-exit requires --load");
+
+    let routed_load =
+        Settings { routes: Some("routes.json".into()), load: Some("dump.json".into()), ..Settings::default() };
+    assert_eq!(resolve(routed_load).unwrap_err(), "--load is only supported in key-server mode");
+
+    let routed_limits = Settings { routes: Some("routes.json".into()), max_keys: Some(5), ..Settings::default() };
+    assert!(resolve(routed_limits).is_err());
+    assert!(resolve(Settings { max_memory: Some("0".into()), ..Settings::default() }).is_err());
+    assert!(resolve(Settings { max_memory: Some("lots".into()), ..Settings::default() }).unwrap_err().starts_with("--max-memory"));
+}
@@ -0,0 +1,83 @@
+//! Graceful shutdown: `server::drain` waits for in-flight requests, but no
+//! longer than the drain timeout.
+
+use std::time::{Duration, Instant};
+
+use axum::{routing::get, Router};
+use rust_key_store::server::{drain, wait_for_shutdown};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+
+/// A server being drained, with its `/slow` URL and a channel that yields once
+/// per request the handler has started on.
+struct SlowServer {
+    url: String,
+    started: mpsc::UnboundedReceiver<()>,
+    drained: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+/// Serves a `/slow` route that answers after `delay`, draining on `shutdown_rx`.
+async fn slow_server(delay: Duration, shutdown_rx: watch::Receiver<bool>, drain_timeout: Duration) -> SlowServer {
+    let (started_tx, started) = mpsc::unbounded_channel();
+    let app = Router::new().route(
+        "/slow",
+        get(move || async move {
+            let _ = started_tx.send(());
+            tokio::time::sleep(delay).await;
+            "done"
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/slow", listener.local_addr().unwrap());
+    let server = axum::serve(listener, app).with_graceful_shutdown(wait_for_shutdown(shutdown_rx.clone()));
+    SlowServer { url, started, drained: tokio::spawn(drain(server, shutdown_rx, drain_timeout)) }
+}
+
+#[tokio::test]
+async fn in_flight_requests_finish_within_the_timeout() {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut server = slow_server(Duration::from_millis(300), shutdown_rx, Duration::from_secs(10)).await;
+
+    let url = server.url.clone();
+    let request = tokio::spawn(async move { reqwest::get(url).await.unwrap().text().await.unwrap() });
+    server.started.recv().await.unwrap();
+    let started = Instant::now();
+    shutdown_tx.send(true).unwrap();
+
+    assert_eq!(request.await.unwrap(), "done");
+    server.drained.await.unwrap().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5), "drain waited {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn requests_still_open_at_the_timeout_are_dropped() {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut server = slow_server(Duration::from_secs(60), shutdown_rx, Duration::from_millis(200)).await;
+
+    let request = tokio::spawn(reqwest::get(server.url.clone()));
+    server.started.recv().await.unwrap();
+    let started = Instant::now();
+    shutdown_tx.send(true).unwrap();
+
+    server.drained.await.unwrap().unwrap();
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_millis(200) && waited < Duration::from_secs(5), "drain waited {:?}", waited);
+    drop(request);
+}
+
+#[tokio::test]
+async fn a_server_that_exits_on_its_own_is_not_waited_on() {
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    let failed = async { Err(std::io::Error::other("listener closed")) };
+    let err = drain(failed, shutdown_rx, Duration::from_secs(60)).await.unwrap_err();
+    assert_eq!(err.to_string(), "listener closed");
+
+    // With nothing in flight the timeout never comes into play
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    shutdown_tx.send(true).unwrap();
+    let started = Instant::now();
+    drain(async { Ok(()) }, shutdown_rx, Duration::from_secs(60)).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
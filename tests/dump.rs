@@ -0,0 +1,185 @@
+//! `--load`, `--snapshot` and `--save-on-exit` dump files: format detection,
+//! rejected input, and round trips through `write_dump`.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rust_key_store::server::key_server::{load_dump, write_dump, Db, DumpFormat};
+use serde_json::{json, Value};
+
+/// A file in the temp dir, removed (with any `.tmp` sibling) when dropped.
+struct DumpFile(PathBuf);
+
+impl DumpFile {
+    fn new(name: &str) -> Self {
+        DumpFile(std::env::temp_dir().join(format!("kvs-dump-{}-{}", std::process::id(), name)))
+    }
+
+    fn with(name: &str, content: &str) -> Self {
+        let file = DumpFile::new(name);
+        std::fs::write(&file.0, content).unwrap();
+        file
+    }
+
+    fn load(&self) -> Result<(HashMap<String, Value>, DumpFormat), String> {
+        load_dump(&self.0)
+    }
+
+    fn tmp(&self) -> PathBuf {
+        let mut tmp = self.0.as_os_str().to_owned();
+        tmp.push(".tmp");
+        PathBuf::from(tmp)
+    }
+}
+
+impl Drop for DumpFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+        let _ = std::fs::remove_file(self.tmp());
+    }
+}
+
+fn values(pairs: &[(&str, Value)]) -> HashMap<String, Value> {
+    pairs.iter().map(|(key, value)| (key.to_string(), value.clone())).collect()
+}
+
+#[test]
+fn the_format_follows_the_extension() {
+    assert_eq!(DumpFormat::for_path(Path::new("data.ndjson")), DumpFormat::Ndjson);
+    assert_eq!(DumpFormat::for_path(Path::new("data.jsonl")), DumpFormat::Ndjson);
+    assert_eq!(DumpFormat::for_path(Path::new("data.json")), DumpFormat::Object);
+    assert_eq!(DumpFormat::for_path(Path::new("data")), DumpFormat::Object);
+}
+
+#[test]
+fn object_dumps_load_as_objects() {
+    let file = DumpFile::with("object.json", "{\n  \"a\": 1,\n  \"b.c\": {\"d\": [true]}\n}\n");
+    let (db, format) = file.load().unwrap();
+    assert_eq!(format, DumpFormat::Object);
+    assert_eq!(db, values(&[("a", json!(1)), ("b.c", json!({ "d": [true] }))]));
+}
+
+#[test]
+fn ndjson_dumps_are_detected_without_the_extension() {
+    let file = DumpFile::with("multi.json", "{\"key\":\"a\",\"value\":1}\n\n{\"key\":\"b\",\"value\":[2]}\n");
+    let (db, format) = file.load().unwrap();
+    assert_eq!(format, DumpFormat::Ndjson);
+    assert_eq!(db, values(&[("a", json!(1)), ("b", json!([2]))]));
+}
+
+#[test]
+fn single_line_ndjson_needs_the_extension() {
+    let line = "{\"key\":\"a\",\"value\":1}\n";
+
+    // Also a valid object, which is how it is read
+    let (db, format) = DumpFile::with("single.json", line).load().unwrap();
+    assert_eq!(format, DumpFormat::Object);
+    assert_eq!(db, values(&[("key", json!("a")), ("value", json!(1))]));
+
+    let (db, format) = DumpFile::with("single.ndjson", line).load().unwrap();
+    assert_eq!(format, DumpFormat::Ndjson);
+    assert_eq!(db, values(&[("a", json!(1))]));
+}
+
+#[test]
+fn keys_given_twice_are_rejected() {
+    let object = DumpFile::with("twice.json", "{\"a\": 1, \"a\": 2}");
+    assert!(object.load().unwrap_err().contains("duplicate key \"a\""));
+
+    let ndjson = DumpFile::with("twice.ndjson", "{\"key\":\"a\",\"value\":1}\n{\"key\":\"a\",\"value\":2}\n");
+    assert_eq!(ndjson.load().unwrap_err(), "duplicate key \"a\"");
+}
+
+#[test]
+fn malformed_dumps_are_rejected() {
+    // Truncated object
+    assert!(DumpFile::with("truncated.json", "{\n  \"a\": 1,\n").load().is_err());
+    // JSON, but not an object of keys
+    let array = DumpFile::with("array.json", "[1, 2]");
+    assert_eq!(array.load().unwrap_err(), "expected a JSON object of keys or NDJSON records");
+    // A bad record after a good one
+    assert!(DumpFile::with("bad-line.json", "{\"key\":\"a\",\"value\":1}\nnot json\n").load().is_err());
+    // Lines that are JSON but not records
+    assert!(DumpFile::with("not-records.ndjson", "[1]\n").load().is_err());
+    assert!(DumpFile::new("missing.json").load().is_err());
+}
+
+#[test]
+fn written_dumps_load_back_in_the_same_format() {
+    let original = values(&[("b", json!({ "x": [1, 2] })), ("a", json!("text")), ("c.d", json!(null))]);
+    let db = Db::from_values(original.clone());
+
+    for (name, format) in [("round-trip.json", DumpFormat::Object), ("round-trip.ndjson", DumpFormat::Ndjson)] {
+        let file = DumpFile::new(name);
+        write_dump(&file.0, &db, format).unwrap();
+        // Written to the side, then renamed into place
+        assert!(!file.tmp().exists());
+
+        let (loaded, loaded_format) = file.load().unwrap();
+        assert_eq!(loaded_format, format);
+        assert_eq!(loaded, original);
+    }
+}
+
+#[test]
+fn saving_replaces_the_loaded_file_in_its_own_format() {
+    // What --save-on-exit does: load an NDJSON dump from a .json file, change
+    // the store, and write it back over the original
+    let file = DumpFile::with("save-on-exit.json", "{\"key\":\"a\",\"value\":1}\n{\"key\":\"b\",\"value\":2}\n");
+    let (loaded, format) = file.load().unwrap();
+    assert_eq!(format, DumpFormat::Ndjson);
+
+    let mut changed = loaded.clone();
+    changed.remove("a");
+    changed.insert("c".to_string(), json!(3));
+    write_dump(&file.0, &Db::from_values(changed.clone()), format).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&file.0).unwrap(),
+        "{\"key\":\"b\",\"value\":2}\n{\"key\":\"c\",\"value\":3}\n"
+    );
+    assert_eq!(file.load().unwrap(), (changed, DumpFormat::Ndjson));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn the_server_saves_on_sigterm() {
+    use std::process::Stdio;
+
+    use common::{get, key_url, post};
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        process::Command,
+    };
+
+    let file = DumpFile::with("sigterm.json", "{\"a\": 1}");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_rust-key-store"))
+        .args(["--port", "0", "--save-on-exit", "--load"])
+        .arg(&file.0)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("start rust-key-store");
+
+    let mut stdout = BufReader::new(server.stdout.take().unwrap()).lines();
+    let base = loop {
+        let line = stdout.next_line().await.unwrap().expect("server exited before listening");
+        if let Some((_, url)) = line.split_once("listening on ") {
+            break url.to_string();
+        }
+    };
+    assert_eq!(get(&key_url(&base, "a")).await.body, 1);
+    post(&key_url(&base, "b"), &json!({ "saved": true })).await;
+
+    let pid = server.id().unwrap().to_string();
+    assert!(Command::new("kill").args(["-TERM", &pid]).status().await.unwrap().success());
+    assert!(server.wait().await.unwrap().success());
+
+    let (saved, format) = file.load().unwrap();
+    assert_eq!(format, DumpFormat::Object);
+    assert_eq!(saved, values(&[("a", json!(1)), ("b", json!({ "saved": true }))]));
+}
@@ -0,0 +1,186 @@
+//! Memory and key limits, eviction policies and TTLs on a key server.
+
+mod common;
+
+use std::time::Duration;
+
+use common::{client, delete, get, key_server, key_server_with_limits, key_url, post, put, router, send};
+use rust_key_store::server::key_server::{entry_size, parse_size, EvictionPolicy, Limits, MAX_KEY_TTL};
+use serde_json::{json, Value};
+
+async fn status(base: &str) -> Value {
+    get(&format!("{}/status", base)).await.body
+}
+
+/// Distinct access times for LRU; they have millisecond resolution.
+async fn tick() {
+    tokio::time::sleep(Duration::from_millis(5)).await;
+}
+
+fn max_keys(n: u64, policy: EvictionPolicy) -> Limits {
+    Limits { max_keys: Some(n), policy, ..Limits::default() }
+}
+
+#[tokio::test]
+async fn noeviction_rejects_writes_past_the_limit() {
+    let (base, store) = key_server_with_limits(max_keys(2, EvictionPolicy::NoEviction)).await;
+    assert_eq!(post(&key_url(&base, "a"), &json!(1)).await.status, 201);
+    assert_eq!(post(&key_url(&base, "b"), &json!(2)).await.status, 201);
+
+    let full = post(&key_url(&base, "c"), &json!(3)).await;
+    assert_eq!(full.status, 507);
+    assert_eq!(full.body["error"], "Store is full");
+    assert_eq!(full.body["max_keys"], 2);
+
+    // Updates and renames do not add keys
+    assert_eq!(put(&key_url(&base, "a"), &json!({ "bigger": [1, 2, 3] })).await.status, 200);
+    let renamed = send(client().post(format!("{}/rename", key_url(&base, "b"))).json(&json!({ "destination": "c" })));
+    assert_eq!(renamed.await.status, 201);
+    let copied = send(client().post(format!("{}/copy", key_url(&base, "c"))).json(&json!({ "destination": "d" })));
+    assert_eq!(copied.await.status, 507);
+    assert_eq!(get(&key_url(&base, "c")).await.body, 2);
+
+    assert_eq!(delete(&key_url(&base, "a")).await.status, 200);
+    assert_eq!(post(&key_url(&base, "d"), &json!(4)).await.status, 201);
+    assert_eq!(store.len(), 2);
+    assert_eq!(status(&base).await["evictions"], 0);
+}
+
+#[tokio::test]
+async fn max_memory_counts_approximate_entry_sizes() {
+    let value = json!({ "name": "Alice", "tags": ["a", "b"] });
+    let size = entry_size("user.1", &value);
+    let limits = Limits { max_memory: Some(2 * size), ..Limits::default() };
+    let (base, store) = key_server_with_limits(limits).await;
+
+    assert_eq!(post(&key_url(&base, "user.1"), &value).await.status, 201);
+    assert_eq!(post(&key_url(&base, "user.2"), &value).await.status, 201);
+    assert_eq!(store.memory(), 2 * size);
+    assert_eq!(post(&key_url(&base, "user.3"), &value).await.status, 507);
+
+    let body = status(&base).await;
+    assert_eq!(body["memory_bytes"], 2 * size);
+    assert_eq!(body["limits"], json!({ "max_memory": 2 * size, "max_keys": null, "eviction_policy": "noeviction" }));
+
+    delete(&key_url(&base, "user.1")).await;
+    delete(&key_url(&base, "user.2")).await;
+    assert_eq!(store.memory(), 0);
+}
+
+#[tokio::test]
+async fn values_larger_than_max_memory_are_rejected_under_every_policy() {
+    let limits = Limits { max_memory: Some(1_024), policy: EvictionPolicy::Lru, ..Limits::default() };
+    let (base, _) = key_server_with_limits(limits).await;
+    post(&key_url(&base, "small"), &json!(1)).await;
+
+    let reply = post(&key_url(&base, "big"), &json!("x".repeat(2_048))).await;
+    assert_eq!(reply.status, 507);
+    assert_eq!(get(&key_url(&base, "small")).await.body, 1);
+}
+
+#[tokio::test]
+async fn lru_evicts_the_least_recently_used_key() {
+    let (base, store) = key_server_with_limits(max_keys(3, EvictionPolicy::Lru)).await;
+    for key in ["a", "b", "c"] {
+        post(&key_url(&base, key), &json!(key)).await;
+        tick().await;
+    }
+    get(&key_url(&base, "a")).await;
+    tick().await;
+
+    assert_eq!(post(&key_url(&base, "d"), &json!("d")).await.status, 201);
+    assert_eq!(get(&key_url(&base, "b")).await.status, 404);
+    assert_eq!(store.len(), 3);
+    assert_eq!(status(&base).await["evictions"], 1);
+}
+
+#[tokio::test]
+async fn lfu_evicts_the_least_frequently_used_key() {
+    let (base, _) = key_server_with_limits(max_keys(3, EvictionPolicy::Lfu)).await;
+    for key in ["a", "b", "c"] {
+        post(&key_url(&base, key), &json!(key)).await;
+    }
+    for key in ["a", "a", "c", "c", "b"] {
+        get(&key_url(&base, key)).await;
+    }
+    tick().await;
+
+    // The key just written is never the one evicted, even with the fewest uses
+    assert_eq!(post(&key_url(&base, "d"), &json!("d")).await.status, 201);
+    assert_eq!(get(&key_url(&base, "b")).await.status, 404);
+    assert_eq!(get(&key_url(&base, "d")).await.status, 200);
+}
+
+#[tokio::test]
+async fn ttl_first_evicts_keys_closest_to_expiring() {
+    let (base, _) = key_server_with_limits(max_keys(3, EvictionPolicy::TtlFirst)).await;
+    post(&key_url(&base, "forever"), &json!(1)).await;
+    post(&format!("{}?ttl=100", key_url(&base, "later")), &json!(2)).await;
+    post(&format!("{}?ttl=50", key_url(&base, "soon")), &json!(3)).await;
+
+    post(&key_url(&base, "new"), &json!(4)).await;
+    assert_eq!(get(&key_url(&base, "soon")).await.status, 404);
+    post(&key_url(&base, "newer"), &json!(5)).await;
+    assert_eq!(get(&key_url(&base, "later")).await.status, 404);
+
+    // Without keys that expire it falls back to least recently used
+    tick().await;
+    get(&key_url(&base, "new")).await;
+    get(&key_url(&base, "newer")).await;
+    tick().await;
+    post(&key_url(&base, "newest"), &json!(6)).await;
+    assert_eq!(get(&key_url(&base, "forever")).await.status, 404);
+}
+
+#[tokio::test]
+async fn keys_with_a_ttl_expire() {
+    let (base, store) = key_server().await;
+    let url = key_url(&base, "session");
+    assert_eq!(post(&format!("{}?ttl=0.2", url), &json!("token")).await.status, 201);
+    assert_eq!(post(&format!("{}?ttl=0", key_url(&base, "bad")), &json!(1)).await.status, 400);
+    // Beyond MAX_KEY_TTL, or not a duration at all
+    let max = MAX_KEY_TTL.as_secs();
+    for ttl in [format!("{}", max + 1), "1e300".to_string(), "inf".to_string(), "NaN".to_string(), "-1".to_string()] {
+        let rejected = post(&format!("{}?ttl={}", key_url(&base, "bad"), ttl), &json!(1)).await;
+        assert_eq!(rejected.status, 400, "ttl={}", ttl);
+        assert!(rejected.body["error"].as_str().unwrap().ends_with(&format!("at most {}", max)));
+    }
+    assert_eq!(get(&url).await.body, "token");
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(get(&url).await.status, 404);
+    assert_eq!(put(&url, &json!("token")).await.status, 404);
+    assert_eq!(get(&format!("{}/keys", base)).await.body["keys"], json!([]));
+    assert_eq!(store.remove_expired(), 1);
+    assert_eq!(store.memory(), 0);
+    assert_eq!(status(&base).await["expired"], 1);
+
+    // A write without `ttl` removes the TTL
+    assert_eq!(post(&format!("{}?ttl=0.2", url), &json!("token")).await.status, 201);
+    assert_eq!(put(&url, &json!("kept")).await.status, 200);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(get(&url).await.body, "kept");
+    assert_eq!(post(&format!("{}?ttl={}", key_url(&base, "decade"), max), &json!(1)).await.status, 201);
+}
+
+#[tokio::test]
+async fn ttls_pass_through_the_router() {
+    let (backend, _) = key_server().await;
+    let router = router(&[("cache", &backend)]).await;
+    let url = key_url(&router, "cache.page");
+
+    assert_eq!(post(&format!("{}?ttl=0.2", url), &json!("<html>")).await.status, 201);
+    assert_eq!(get(&url).await.status, 200);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(get(&url).await.status, 404);
+}
+
+#[test]
+fn sizes_take_binary_suffixes() {
+    assert_eq!(parse_size("1048576"), Ok(1 << 20));
+    assert_eq!(parse_size("512kb"), Ok(512 << 10));
+    assert_eq!(parse_size("64MB"), Ok(64 << 20));
+    assert_eq!(parse_size("2 GiB"), Ok(2 << 30));
+    assert!(parse_size("lots").is_err());
+    assert!(parse_size("1.5mb").is_err());
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{client, fault_proxy, get, key_server, key_url, post, router, send};
+use rust_key_store::{
+    linearizability::{check, record, Outcome, Workload},
+    server::fault_proxy::FaultConfig,
+    KvClient, KvError,
+};
+use serde_json::json;
+
+/// A router with a healthy `ok` shard and a `bad` shard behind a fault proxy.
+async fn router_with_faulty_shard(faults: FaultConfig) -> (String, common::Reply) {
+    let (ok, _) = key_server().await;
+    let (bad, _) = key_server().await;
+    let proxy = fault_proxy(&bad, faults).await;
+    let router = router(&[("ok", &ok), ("bad", &proxy)]).await;
+    let reply = post(&key_url(&router, "bad.key"), &json!({ "n": 1 })).await;
+    (router, reply)
+}
+
+#[tokio::test]
+async fn invalid_json_from_a_shard_is_502() {
+    let (bad, store) = key_server().await;
+    let proxy = fault_proxy(&bad, FaultConfig { invalid_json: 1.0, ..FaultConfig::default() }).await;
+    let router = router(&[("bad", &proxy)]).await;
+
+    let reply = post(&key_url(&router, "bad.key"), &json!({ "n": 1 })).await;
+    assert_eq!(reply.status, 502);
+    assert_eq!(reply.body["error"], "Upstream returned invalid JSON");
+    // The key server did the write; only its answer was mangled
+    assert_eq!(store.len(), 1);
+
+    let read = get(&key_url(&router, "bad.key")).await;
+    assert_eq!(read.status, 502);
+    assert_eq!(read.body["error"], "Upstream returned invalid JSON");
+}
+
+#[tokio::test]
+async fn truncated_response_is_502() {
+    let (_, reply) = router_with_faulty_shard(FaultConfig { truncate: 1.0, ..FaultConfig::default() }).await;
+    assert_eq!(reply.status, 502);
+    assert_eq!(reply.body["error"], "Upstream response was incomplete");
+}
+
+#[tokio::test]
+async fn connection_reset_is_502() {
+    let (bad, store) = key_server().await;
+    let proxy = fault_proxy(&bad, FaultConfig { reset: 1.0, ..FaultConfig::default() }).await;
+    let router = router(&[("bad", &proxy)]).await;
+
+    let reply = post(&key_url(&router, "bad.key"), &json!(1)).await;
+    assert_eq!(reply.status, 502);
+    assert_eq!(reply.body["error"], "Upstream key-server unavailable");
+    assert_eq!(store.len(), 0);
+}
+
+#[tokio::test]
+async fn shard_errors_pass_through() {
+    let faults = FaultConfig { error: 1.0, error_status: 500, ..FaultConfig::default() };
+    let (_, reply) = router_with_faulty_shard(faults).await;
+    assert_eq!(reply.status, 500);
+    assert_eq!(reply.body, json!({ "error": "Injected fault" }));
+}
+
+#[tokio::test]
+async fn slow_shards_still_answer() {
+    let started = Instant::now();
+    let (router, reply) = router_with_faulty_shard(FaultConfig { latency_ms: 100, ..FaultConfig::default() }).await;
+    assert_eq!(reply.status, 201);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(get(&key_url(&router, "bad.key")).await.body, json!({ "n": 1 }));
+}
+
+#[tokio::test]
+async fn other_shards_keep_working() {
+    let (router, _) = router_with_faulty_shard(FaultConfig { reset: 1.0, ..FaultConfig::default() }).await;
+
+    assert_eq!(post(&key_url(&router, "ok.key"), &json!(1)).await.status, 201);
+    assert_eq!(get(&key_url(&router, "ok.key")).await.body, 1);
+    assert_eq!(get(&format!("{}/ready", router)).await.status, 200);
+    let status = get(&format!("{}/status", router)).await.body;
+    let down: Vec<_> = status["backends"]
+        .as_object()
+        .unwrap()
+        .values()
+        .filter(|backend| backend["up"] == false)
+        .collect();
+    assert_eq!(down.len(), 1);
+}
+
+#[tokio::test]
+async fn failed_cross_shard_rename_leaves_the_source() {
+    let (ok, _) = key_server().await;
+    let (bad, _) = key_server().await;
+    let proxy = fault_proxy(&bad, FaultConfig { error: 1.0, ..FaultConfig::default() }).await;
+    let router = router(&[("ok", &ok), ("bad", &proxy)]).await;
+    post(&key_url(&router, "ok.key"), &json!(1)).await;
+
+    let renamed = send(
+        client()
+            .post(format!("{}/rename", key_url(&router, "ok.key")))
+            .json(&json!({ "destination": "bad.key" })),
+    )
+    .await;
+    assert_eq!(renamed.status, 503);
+    assert_eq!(get(&key_url(&router, "ok.key")).await.body, 1);
+}
+
+#[tokio::test]
+async fn client_errors_for_each_fault() {
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "k"), &json!({ "n": 1 })).await;
+    let base = &base;
+    let kv = |faults| async move { KvClient::new(fault_proxy(base, faults).await).unwrap() };
+
+    let invalid = kv(FaultConfig { invalid_json: 1.0, ..FaultConfig::default() }).await;
+    assert!(matches!(invalid.get::<serde_json::Value>("k").await, Err(KvError::Decode(_))));
+
+    let truncated = kv(FaultConfig { truncate: 1.0, ..FaultConfig::default() }).await;
+    assert!(matches!(truncated.get::<serde_json::Value>("k").await, Err(KvError::Transport(_))));
+
+    let reset = kv(FaultConfig { reset: 1.0, ..FaultConfig::default() }).await;
+    assert!(matches!(reset.update("k", &1).await, Err(KvError::Transport(_))));
+
+    let failing = kv(FaultConfig { error: 1.0, ..FaultConfig::default() }).await;
+    let status = failing.update("k", &1).await.unwrap_err();
+    assert!(matches!(status, KvError::Status { status, .. } if status == 503), "{:?}", status);
+}
+
+/// Requests that fail under faults have unknown outcomes; whatever did take
+/// effect must still be linearizable.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn router_stays_linearizable_under_faults() {
+    let (shard, _) = key_server().await;
+    let faults = FaultConfig {
+        jitter_ms: 5,
+        reset: 0.02,
+        error: 0.02,
+        truncate: 0.02,
+        invalid_json: 0.02,
+        ..FaultConfig::default()
+    };
+    let proxy = fault_proxy(&shard, faults).await;
+    let router = router(&[("lin", &proxy)]).await;
+
+    let kv = KvClient::new(&router).unwrap();
+    let workload = Workload { operations: 100, ..Workload::default() };
+    let history = loop {
+        // Clearing the keys goes through the proxy too; retry until it gets through
+        if let Ok(history) = record(&kv, &workload).await {
+            break history;
+        }
+    };
+    assert!(history.iter().any(|event| event.outcome == Outcome::Unknown));
+    if let Err(violation) = check(&history) {
+        panic!("{}", violation);
+    }
+}
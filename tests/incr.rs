@@ -0,0 +1,139 @@
+//! `POST /keys/{key}/incr` on a key server and through a router.
+
+mod common;
+
+use common::{client, get, key_server, key_url, post, router, send, Reply};
+use rust_key_store::{Increment, KvClient, KvError};
+use serde_json::{json, Value};
+
+async fn incr(base: &str, key: &str, request: Value) -> Reply {
+    send(client().post(format!("{}/incr", key_url(base, key))).json(&request)).await
+}
+
+#[tokio::test]
+async fn increments_numbers_in_place() {
+    let (base, _) = key_server().await;
+    let url = key_url(&base, "hits");
+
+    let missing = send(client().post(format!("{}/incr", url))).await;
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.body["error"], "Key not found");
+
+    let created = incr(&base, "hits", json!({ "create": true, "initial": 10 })).await;
+    assert_eq!(created.status, 201);
+    assert_eq!(created.body, json!({ "value": 11 }));
+
+    // Without a body the delta is 1
+    let bumped = send(client().post(format!("{}/incr", url))).await;
+    assert_eq!(bumped.status, 200);
+    assert_eq!(bumped.body["value"], 12);
+    assert_eq!(get(&url).await.etag, bumped.etag);
+
+    assert_eq!(incr(&base, "hits", json!({ "delta": -20 })).await.body["value"], -8);
+    assert_eq!(incr(&base, "hits", json!({ "delta": 0.5 })).await.body["value"], -7.5);
+    assert_eq!(get(&url).await.body, -7.5);
+    assert_eq!(incr(&base, "hits", json!({ "step": 1 })).await.status, 422);
+}
+
+#[tokio::test]
+async fn pointers_reach_into_documents() {
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "page"), &json!({ "title": "Home", "stats": { "views": 1 }, "votes": [3, 4] })).await;
+
+    assert_eq!(incr(&base, "page", json!({ "pointer": "/stats/views" })).await.body["value"], 2);
+    assert_eq!(incr(&base, "page", json!({ "pointer": "/votes/1", "delta": 2 })).await.body["value"], 6);
+
+    let missing = incr(&base, "page", json!({ "pointer": "/stats/likes" })).await;
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.body["error"], "Pointer not found");
+    let created = incr(&base, "page", json!({ "pointer": "/stats/by~1day/mon", "create": true })).await;
+    assert_eq!(created.status, 200);
+    assert_eq!(incr(&base, "page", json!({ "pointer": "/votes/2", "create": true })).await.status, 404);
+
+    let text = incr(&base, "page", json!({ "pointer": "/title" })).await;
+    assert_eq!(text.status, 422);
+    assert_eq!(text.body["error"], "Value is not a number");
+
+    assert_eq!(
+        get(&key_url(&base, "page")).await.body,
+        json!({ "title": "Home", "stats": { "views": 2, "by/day": { "mon": 1 } }, "votes": [3, 6] })
+    );
+
+    // A new key is built around the pointer
+    let fresh = incr(&base, "counts", json!({ "pointer": "/a/b", "create": true, "delta": 5 })).await;
+    assert_eq!(fresh.status, 201);
+    assert_eq!(get(&key_url(&base, "counts")).await.body, json!({ "a": { "b": 5 } }));
+}
+
+#[tokio::test]
+async fn results_must_fit_a_64_bit_integer() {
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "big"), &json!(i64::MAX)).await;
+
+    // Past i64::MAX it carries on as a u64
+    assert_eq!(incr(&base, "big", json!({})).await.body["value"], json!(i64::MAX as u64 + 1));
+    post(&key_url(&base, "max"), &json!(u64::MAX)).await;
+    let overflow = incr(&base, "max", json!({})).await;
+    assert_eq!(overflow.status, 409);
+    assert_eq!(overflow.body["error"], "Increment would overflow");
+    assert_eq!(get(&key_url(&base, "max")).await.body, json!(u64::MAX));
+
+    post(&key_url(&base, "min"), &json!(i64::MIN)).await;
+    assert_eq!(incr(&base, "min", json!({ "delta": -1 })).await.status, 409);
+    post(&key_url(&base, "float"), &json!(f64::MAX)).await;
+    assert_eq!(incr(&base, "float", json!({ "delta": f64::MAX })).await.status, 409);
+}
+
+#[tokio::test]
+async fn if_match_guards_increments() {
+    let (base, _) = key_server().await;
+    let url = key_url(&base, "n");
+    let version = post(&url, &json!(1)).await.etag.unwrap();
+    incr(&base, "n", json!({})).await;
+
+    let stale = send(client().post(format!("{}/incr", url)).header("if-match", &version).json(&json!({}))).await;
+    assert_eq!(stale.status, 412);
+    assert_eq!(stale.body["value"], 2);
+    let current = get(&url).await.etag.unwrap();
+    let fresh = send(client().post(format!("{}/incr", url)).header("if-match", &current).json(&json!({}))).await;
+    assert_eq!(fresh.body["value"], 3);
+}
+
+#[tokio::test]
+async fn concurrent_increments_are_not_lost() {
+    let (base, _) = key_server().await;
+    let tasks: Vec<_> = (0..100)
+        .map(|_| {
+            let base = base.clone();
+            tokio::spawn(async move { incr(&base, "counter", json!({ "create": true })).await.status })
+        })
+        .collect();
+    for task in tasks {
+        assert!([200, 201].contains(&task.await.unwrap()));
+    }
+    assert_eq!(get(&key_url(&base, "counter")).await.body, 100);
+}
+
+#[tokio::test]
+async fn clients_increment_through_a_router() {
+    let (backend, _) = key_server().await;
+    let router = router(&[("stats", &backend)]).await;
+    let kv = KvClient::new(router.clone()).unwrap();
+
+    let first = kv.incr("stats.hits", &Increment::by(5).or_create(0)).await.unwrap();
+    assert_eq!(first.value, 5.into());
+    let second = kv.incr("stats.hits", &Increment::by(-2)).await.unwrap();
+    assert_eq!(second.value, 3.into());
+    assert!(second.version > first.version);
+    assert_eq!(get(&key_url(&backend, ".hits")).await.body, 3);
+
+    assert!(matches!(kv.incr("stats.misses", &Increment::by(1)).await, Err(KvError::NotFound { .. })));
+    kv.set("stats.page", &json!({ "views": 1 })).await.unwrap();
+    let views = kv.incr("stats.page", &Increment::by(1).at("/views")).await.unwrap();
+    assert_eq!(views.value, 2.into());
+
+    let direct = KvClient::builder().base_url(&router).routes_from_router(&router).build().unwrap();
+    let hits = direct.incr("stats.hits", &Increment::by(1)).await.unwrap();
+    assert_eq!(hits.value, 4.into());
+    assert!(matches!(direct.incr("stats.page", &Increment::by(1)).await, Err(KvError::Status { .. })));
+}
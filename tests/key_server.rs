@@ -0,0 +1,219 @@
+mod common;
+
+use common::{client, delete, get, key_server, key_url, post, put, send};
+use serde_json::json;
+
+#[tokio::test]
+async fn set_get_update_delete_contract() {
+    let (base, store) = key_server().await;
+    let url = key_url(&base, "user.profile");
+
+    let created = post(&url, &json!({ "name": "Alice" })).await;
+    assert_eq!(created.status, 201);
+    assert_eq!(created.body, json!({ "uri": "/keys/user.profile" }));
+    assert!(created.etag.is_some());
+
+    let again = post(&url, &json!({ "name": "Bob" })).await;
+    assert_eq!(again.status, 409);
+    assert_eq!(again.body["uri"], "/keys/user.profile");
+
+    let read = get(&url).await;
+    assert_eq!(read.status, 200);
+    assert_eq!(read.body, json!({ "name": "Alice" }));
+    assert_eq!(read.etag, created.etag);
+
+    let updated = put(&url, &json!({ "name": "Alice", "age": 31 })).await;
+    assert_eq!(updated.status, 200);
+    assert_eq!(updated.body, json!({ "uri": "/keys/user.profile" }));
+    assert_ne!(updated.etag, created.etag);
+    assert_eq!(get(&url).await.body, json!({ "name": "Alice", "age": 31 }));
+
+    assert_eq!(delete(&url).await.status, 200);
+    assert_eq!(get(&url).await.status, 404);
+    assert_eq!(store.len(), 0);
+}
+
+#[tokio::test]
+async fn missing_keys_are_404() {
+    let (base, _) = key_server().await;
+    let url = key_url(&base, "nope");
+
+    assert_eq!(get(&url).await.status, 404);
+    assert_eq!(put(&url, &json!(1)).await.status, 404);
+    assert_eq!(delete(&url).await.status, 404);
+}
+
+#[tokio::test]
+async fn keys_with_reserved_characters_round_trip() {
+    let (base, _) = key_server().await;
+    for key in ["a b", "a/b", "100%", "ключ.ü", "q?x=1#frag"] {
+        let url = key_url(&base, key);
+        let created = post(&url, &json!(key)).await;
+        assert_eq!(created.status, 201, "{}", key);
+        assert_eq!(created.body["uri"], format!("/keys/{}", urlencoding::encode(key)));
+        assert_eq!(get(&url).await.body, json!(key), "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn if_match_guards_writes() {
+    let (base, _) = key_server().await;
+    let url = key_url(&base, "counter");
+    let created = post(&url, &json!(1)).await;
+    let version = created.etag.unwrap();
+    put(&url, &json!(2)).await;
+
+    let client = client();
+    let stale = send(client.put(&url).header("if-match", &version).json(&json!(3))).await;
+    assert_eq!(stale.status, 412);
+    assert_eq!(stale.body["value"], 2);
+
+    let current = get(&url).await.etag.unwrap();
+    let fresh = send(client.put(&url).header("if-match", &current).json(&json!(3))).await;
+    assert_eq!(fresh.status, 200);
+
+    let stale_delete = send(client.delete(&url).header("if-match", &current)).await;
+    assert_eq!(stale_delete.status, 412);
+    let bad = send(client.delete(&url).header("if-match", "not-a-version")).await;
+    assert_eq!(bad.status, 400);
+    let any = send(client.delete(&url).header("if-match", "*")).await;
+    assert_eq!(any.status, 200);
+}
+
+#[tokio::test]
+async fn lists_keys_by_prefix() {
+    let (base, _) = key_server().await;
+    for key in ["app.b", "app.a", "user.x", "app.c"] {
+        post(&key_url(&base, key), &json!(null)).await;
+    }
+
+    let listed = get(&format!("{}/keys?prefix=app.", base)).await;
+    assert_eq!(listed.body, json!({ "keys": ["app.a", "app.b", "app.c"] }));
+    let limited = get(&format!("{}/keys?prefix=app.&limit=2", base)).await;
+    assert_eq!(limited.body, json!({ "keys": ["app.a", "app.b"] }));
+}
+
+#[tokio::test]
+async fn pointer_reads_return_sub_documents() {
+    let (base, _) = key_server().await;
+    let url = key_url(&base, "doc");
+    post(&url, &json!({ "items": [{ "name": "a" }] })).await;
+
+    let part = get(&format!("{}?pointer=/items/0/name", url)).await;
+    assert_eq!(part.status, 200);
+    assert_eq!(part.body, "a");
+    let missing = get(&format!("{}?pointer=/items/5", url)).await;
+    assert_eq!(missing.status, 404);
+    assert_eq!(missing.body["error"], "Pointer not found");
+}
+
+#[tokio::test]
+async fn copy_and_rename() {
+    let (base, _) = key_server().await;
+    let client = client();
+    post(&key_url(&base, "user.profile"), &json!({ "n": 1 })).await;
+    post(&key_url(&base, "taken"), &json!("old")).await;
+
+    let copy = |destination: &str, overwrite: bool| {
+        client
+            .post(format!("{}/copy", key_url(&base, "user.profile")))
+            .json(&json!({ "destination": destination, "overwrite": overwrite }))
+    };
+    let copied = send(copy("backup", false)).await;
+    assert_eq!(copied.status, 201);
+    assert_eq!(copied.body["uri"], "/keys/backup");
+    assert_eq!(send(copy("taken", false)).await.status, 409);
+    assert_eq!(send(copy("taken", true)).await.status, 200);
+    assert_eq!(get(&key_url(&base, "taken")).await.body, json!({ "n": 1 }));
+    assert_eq!(send(copy("user.profile", true)).await.status, 400);
+
+    let renamed = send(
+        client
+            .post(format!("{}/rename", key_url(&base, "user.profile")))
+            .json(&json!({ "destination": "users.profile" })),
+    )
+    .await;
+    assert_eq!(renamed.status, 201);
+    assert_eq!(get(&key_url(&base, "user.profile")).await.status, 404);
+    assert_eq!(get(&key_url(&base, "users.profile")).await.body, json!({ "n": 1 }));
+
+    let gone = send(
+        client
+            .post(format!("{}/rename", key_url(&base, "user.profile")))
+            .json(&json!({ "destination": "elsewhere" })),
+    )
+    .await;
+    assert_eq!(gone.status, 404);
+}
+
+// Renames in opposite directions lock the same two shards; taking them in a
+// fixed order keeps them from deadlocking.
+#[tokio::test]
+async fn concurrent_renames_between_shards_complete() {
+    let (base, store) = key_server().await;
+    for i in 0..16 {
+        post(&key_url(&base, &format!("a{}", i)), &json!(i)).await;
+    }
+
+    let renames = (0..16).flat_map(|i| {
+        let (a, b) = (format!("a{}", i), format!("b{}", i));
+        [(a.clone(), b.clone()), (b, a)].into_iter().cycle().take(20)
+    });
+    let tasks: Vec<_> = renames
+        .map(|(from, to)| {
+            let url = format!("{}/rename", key_url(&base, &from));
+            tokio::spawn(async move {
+                let body = json!({ "destination": to, "overwrite": true });
+                send(client().post(url).json(&body)).await.status
+            })
+        })
+        .collect();
+    let run = async {
+        for task in tasks {
+            let status = task.await.unwrap();
+            assert!([200, 201, 404].contains(&status), "{}", status);
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(30), run).await.expect("renames deadlocked");
+
+    assert_eq!(store.len(), 16);
+}
+
+#[tokio::test]
+async fn export_then_import() {
+    let (source, _) = key_server().await;
+    post(&key_url(&source, "a"), &json!(1)).await;
+    post(&key_url(&source, "b"), &json!({ "x": true })).await;
+    let dump = client().get(format!("{}/admin/export", source)).send().await.unwrap().text().await.unwrap();
+    assert_eq!(dump, "{\"key\":\"a\",\"value\":1}\n{\"key\":\"b\",\"value\":{\"x\":true}}\n");
+
+    let (target, store) = key_server().await;
+    post(&key_url(&target, "a"), &json!(0)).await;
+    let client = client();
+    let import = |policy: &str| client.post(format!("{}/admin/import?on_conflict={}", target, policy)).body(dump.clone());
+
+    let failed = send(import("fail")).await;
+    assert_eq!(failed.status, 409);
+    assert_eq!(failed.body["conflicts"], json!(["a"]));
+    assert_eq!(store.len(), 1);
+
+    let skipped = send(import("skip")).await;
+    assert_eq!(skipped.body, json!({ "imported": 1, "skipped": 1 }));
+    assert_eq!(get(&key_url(&target, "a")).await.body, 0);
+
+    let overwritten = send(import("overwrite")).await;
+    assert_eq!(overwritten.body, json!({ "imported": 2, "skipped": 0 }));
+    assert_eq!(get(&key_url(&target, "a")).await.body, 1);
+}
+
+#[tokio::test]
+async fn health_ready_and_status() {
+    let (base, _) = key_server().await;
+    post(&key_url(&base, "k"), &json!(1)).await;
+
+    assert_eq!(get(&format!("{}/health", base)).await.status, 200);
+    assert_eq!(get(&format!("{}/ready", base)).await.body["ready"], true);
+    let status = get(&format!("{}/status", base)).await.body;
+    assert_eq!(status["mode"], "key-server");
+    assert_eq!(status["keys"], 1);
+}
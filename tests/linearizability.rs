@@ -0,0 +1,125 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{key_server, router};
+use rust_key_store::{
+    linearizability::{check, record, Event, Op, Outcome, Workload},
+    KvClient,
+};
+use serde_json::{json, Value};
+
+async fn run(base: &str, workload: &Workload) {
+    let client = KvClient::new(base).unwrap();
+    let history = record(&client, workload).await.unwrap();
+    assert_eq!(
+        history.iter().filter(|event| event.outcome == Outcome::Unknown).count(),
+        0,
+        "requests failed against a healthy server"
+    );
+    if let Err(violation) = check(&history) {
+        panic!("{}", violation);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn key_server_is_linearizable() {
+    let (base, _) = key_server().await;
+    run(&base, &Workload::default()).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn router_is_linearizable() {
+    let (first, _) = key_server().await;
+    let (second, _) = key_server().await;
+    let base = router(&[("lin", &first), ("lin.1", &second)]).await;
+    run(&base, &Workload::default()).await;
+}
+
+/// `KVS_LINEARIZABILITY_URL=http://... cargo test --test linearizability -- --ignored`
+/// runs the workload against a running server. The keys (`lin.0`, `lin.1`, ...
+/// unless `KVS_LINEARIZABILITY_PREFIX` says otherwise) are deleted first.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore = "needs KVS_LINEARIZABILITY_URL"]
+async fn external_server_is_linearizable() {
+    let base = std::env::var("KVS_LINEARIZABILITY_URL").expect("KVS_LINEARIZABILITY_URL is not set");
+    let mut workload = Workload { clients: 16, operations: 500, ..Workload::default() };
+    if let Ok(prefix) = std::env::var("KVS_LINEARIZABILITY_PREFIX") {
+        workload.prefix = prefix;
+    }
+    run(&base, &workload).await;
+}
+
+fn event(client: usize, op: Op, outcome: Outcome, call: u64, ret: Option<u64>) -> Event {
+    Event {
+        client,
+        key: "k".to_string(),
+        op,
+        outcome,
+        call: Duration::from_millis(call),
+        ret: ret.map(Duration::from_millis),
+    }
+}
+
+fn v(name: &str) -> Value {
+    json!(name)
+}
+
+#[test]
+fn accepts_concurrent_and_unknown_operations() {
+    let history = vec![
+        event(0, Op::Set(v("a")), Outcome::Ok, 0, Some(10)),
+        // Overlaps the set, so it may observe it
+        event(1, Op::Get, Outcome::Value(v("a")), 1, Some(2)),
+        // Never answered, but a later read shows it took effect
+        event(2, Op::Update(v("b")), Outcome::Unknown, 11, None),
+        event(1, Op::Get, Outcome::Value(v("b")), 12, Some(13)),
+        // Never answered and never observed
+        event(3, Op::Delete, Outcome::Unknown, 14, None),
+        event(1, Op::CompareAndSet { expected: v("b"), new: v("c") }, Outcome::Ok, 15, Some(16)),
+        event(0, Op::Set(v("d")), Outcome::Exists, 17, Some(18)),
+    ];
+    assert!(check(&history).is_ok());
+}
+
+#[test]
+fn detects_a_lost_update_and_shrinks_it() {
+    let history = vec![
+        event(0, Op::Set(v("a")), Outcome::Ok, 0, Some(1)),
+        event(1, Op::Get, Outcome::Value(v("a")), 2, Some(3)),
+        event(2, Op::Get, Outcome::Value(v("a")), 2, Some(3)),
+        // Both read-modify-write cycles succeed from the same version
+        event(1, Op::CompareAndSet { expected: v("a"), new: v("b") }, Outcome::Ok, 4, Some(6)),
+        event(2, Op::CompareAndSet { expected: v("a"), new: v("c") }, Outcome::Ok, 5, Some(7)),
+        event(0, Op::Get, Outcome::Value(v("c")), 8, Some(9)),
+        event(0, Op::Delete, Outcome::Ok, 10, Some(11)),
+    ];
+    let violation = check(&history).unwrap_err();
+    assert_eq!(violation.key, "k");
+    assert_eq!(violation.events, history[3..5]);
+    assert!(violation.from_any_state);
+    assert!(violation.to_string().contains("cas(\"a\" -> \"b\") -> ok"), "{}", violation);
+}
+
+#[test]
+fn detects_a_stale_read() {
+    let history = vec![
+        event(0, Op::Set(v("a")), Outcome::Ok, 0, Some(1)),
+        event(0, Op::Update(v("b")), Outcome::Ok, 2, Some(3)),
+        event(1, Op::Get, Outcome::Value(v("a")), 4, Some(5)),
+        event(1, Op::Get, Outcome::Value(v("b")), 6, Some(7)),
+    ];
+    let violation = check(&history).unwrap_err();
+    assert_eq!(violation.events, history[1..3]);
+}
+
+#[test]
+fn detects_a_read_of_a_deleted_key_from_the_start() {
+    let history = vec![
+        event(0, Op::Get, Outcome::Value(v("ghost")), 0, Some(1)),
+        event(0, Op::Set(v("a")), Outcome::Ok, 2, Some(3)),
+    ];
+    let violation = check(&history).unwrap_err();
+    assert_eq!(violation.events, history[..1]);
+    assert!(!violation.from_any_state);
+}
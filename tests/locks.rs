@@ -0,0 +1,152 @@
+//! Leases on `/locks/{name}`: fencing tokens, renewal, expiry and waiters.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{client, get, key_server, router, send, Reply};
+use rust_key_store::{KvClient, KvError};
+use serde_json::{json, Value};
+
+fn lock_url(base: &str, name: &str) -> String {
+    format!("{}/locks/{}", base, urlencoding::encode(name))
+}
+
+async fn acquire(base: &str, name: &str, request: Value) -> Reply {
+    send(client().post(lock_url(base, name)).json(&request)).await
+}
+
+async fn lease_call(base: &str, name: &str, op: &str, request: Value) -> Reply {
+    send(client().post(format!("{}/{}", lock_url(base, name), op)).json(&request)).await
+}
+
+#[tokio::test]
+async fn leases_are_exclusive_until_released() {
+    let (base, _) = key_server().await;
+
+    let first = acquire(&base, "deploy", json!({ "ttl": 10, "owner": "ci-1" })).await;
+    assert_eq!(first.status, 201);
+    assert_eq!(first.body["owner"], "ci-1");
+    assert_eq!(first.body["ttl"], 10.0);
+    let token = first.body["token"].as_u64().unwrap();
+
+    let held = acquire(&base, "deploy", json!({})).await;
+    assert_eq!(held.status, 409);
+    assert_eq!(held.body["error"], "Lock is held");
+    assert_eq!(held.body["holder"]["owner"], "ci-1");
+    assert_eq!(get(&lock_url(&base, "deploy")).await.body["token"], token);
+    // Other names are independent
+    assert_eq!(acquire(&base, "backup", json!({})).await.status, 201);
+
+    let renewed = lease_call(&base, "deploy", "renew", json!({ "token": token, "ttl": 20 })).await;
+    assert_eq!(renewed.status, 200);
+    assert_eq!(renewed.body["ttl"], 20.0);
+    assert_eq!(lease_call(&base, "deploy", "release", json!({ "token": token + 1 })).await.status, 409);
+    assert_eq!(lease_call(&base, "deploy", "release", json!({ "token": token })).await.status, 200);
+    assert_eq!(get(&lock_url(&base, "deploy")).await.status, 404);
+
+    let second = acquire(&base, "deploy", json!({})).await;
+    assert_eq!(second.status, 201);
+    assert!(second.body["token"].as_u64().unwrap() > token);
+    assert_eq!(second.body["ttl"], 30.0);
+}
+
+#[tokio::test]
+async fn expired_leases_are_lost() {
+    let (base, _) = key_server().await;
+    let token = acquire(&base, "job", json!({ "ttl": 0.2 })).await.body["token"].clone();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(get(&lock_url(&base, "job")).await.status, 404);
+    let renewed = lease_call(&base, "job", "renew", json!({ "token": token })).await;
+    assert_eq!(renewed.status, 409);
+    assert_eq!(renewed.body["error"], "Lease lost");
+
+    let next = acquire(&base, "job", json!({})).await;
+    assert_eq!(next.status, 201);
+    assert!(next.body["token"].as_u64() > token.as_u64());
+    assert_eq!(lease_call(&base, "job", "release", json!({ "token": token })).await.status, 409);
+}
+
+#[tokio::test]
+async fn waiters_get_the_lock_on_release_or_expiry() {
+    let (base, _) = key_server().await;
+    let token = acquire(&base, "job", json!({ "ttl": 30 })).await.body["token"].clone();
+
+    let waiter = tokio::spawn({
+        let base = base.clone();
+        async move { acquire(&base, "job", json!({ "wait": 10 })).await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let released = Instant::now();
+    lease_call(&base, "job", "release", json!({ "token": token })).await;
+    let acquired = waiter.await.unwrap();
+    assert_eq!(acquired.status, 201);
+    assert!(released.elapsed() < Duration::from_secs(5));
+
+    // A holder that stops renewing is waited out
+    let (base, _) = key_server().await;
+    acquire(&base, "job", json!({ "ttl": 0.3 })).await;
+    let started = Instant::now();
+    assert_eq!(acquire(&base, "job", json!({ "wait": 0.1 })).await.status, 409);
+    assert_eq!(acquire(&base, "job", json!({ "wait": 10 })).await.status, 201);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn lease_durations_are_validated() {
+    let (base, _) = key_server().await;
+    assert_eq!(acquire(&base, "job", json!({ "ttl": 0 })).await.status, 400);
+    assert_eq!(acquire(&base, "job", json!({ "wait": -1 })).await.status, 400);
+    assert_eq!(acquire(&base, "job", json!({ "timeout": 5 })).await.status, 422);
+    let token = send(client().post(lock_url(&base, "job"))).await.body["token"].clone();
+
+    // Far-future TTLs are refused rather than overflowing the clock
+    assert_eq!(acquire(&base, "huge", json!({ "ttl": 1.8e19 })).await.status, 400);
+    assert_eq!(acquire(&base, "huge", json!({ "ttl": 86_401 })).await.status, 400);
+    assert_eq!(lease_call(&base, "job", "renew", json!({ "token": token, "ttl": 1.8e19 })).await.status, 400);
+    assert_eq!(acquire(&base, "huge", json!({ "ttl": 86_400 })).await.status, 201);
+    assert_eq!(get(&lock_url(&base, "job")).await.status, 200);
+    assert_eq!(lease_call(&base, "job", "release", json!({ "token": token })).await.status, 200);
+}
+
+// Each task does a read-modify-write that would lose updates without the lock.
+#[tokio::test]
+async fn clients_take_turns_through_a_router() {
+    let (backend, _) = key_server().await;
+    let router = router(&[("jobs", &backend)]).await;
+    let kv = KvClient::new(router.clone()).unwrap();
+    kv.set("jobs.count", &0).await.unwrap();
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let kv = kv.clone();
+            tokio::spawn(async move {
+                let ttl = Duration::from_secs(10);
+                let lease = kv.acquire_lock("jobs.count", ttl, Duration::MAX, None).await.unwrap().unwrap();
+                let count: u64 = kv.get("jobs.count").await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                kv.update("jobs.count", &(count + 1)).await.unwrap();
+                kv.renew_lock(&lease).await.unwrap();
+                kv.release_lock(&lease).await.unwrap();
+                lease.token
+            })
+        })
+        .collect();
+    let mut tokens = Vec::new();
+    for task in tasks {
+        tokens.push(task.await.unwrap());
+    }
+    assert_eq!(kv.get::<u64>("jobs.count").await.unwrap(), 8);
+    tokens.sort_unstable();
+    tokens.dedup();
+    assert_eq!(tokens.len(), 8);
+
+    // The lock lives on the shard, under the backend name
+    let lease = kv.acquire_lock("jobs.nightly", Duration::from_millis(200), Duration::ZERO, Some("cron")).await.unwrap();
+    let lease = lease.unwrap();
+    assert_eq!(get(&lock_url(&backend, ".nightly")).await.body["owner"], "cron");
+    assert_eq!(kv.acquire_lock("jobs.nightly", Duration::from_secs(1), Duration::ZERO, None).await.unwrap(), None);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(matches!(kv.renew_lock(&lease).await, Err(KvError::LeaseLost { .. })));
+}
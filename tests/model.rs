@@ -0,0 +1,159 @@
+//! Model-based tests: random sequences of set/update/get/delete/list are run
+//! against a server and checked, step by step, against a `BTreeMap`.
+
+mod common;
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use common::{delete, get, key_server, key_url, post, put, router};
+use proptest::prelude::*;
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Set(usize, Value),
+    Update(usize, Value),
+    Get(usize),
+    Delete(usize),
+    /// Lists keys starting with the first `n` characters of a key
+    List(usize, usize),
+}
+
+/// Servers outlive a single case's `block_on`, so every case shares one runtime.
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("tokio runtime"))
+}
+
+fn value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        Just(Value::Null),
+        any::<i64>().prop_map(Value::from),
+        "[a-z ü]{0,4}".prop_map(Value::from),
+        // Looks like a handler response; the router must not rewrite it
+        "[a-z/]{0,4}".prop_map(|uri| json!({ "uri": uri })),
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (any::<usize>(), value()).prop_map(|(key, value)| Op::Set(key, value)),
+        (any::<usize>(), value()).prop_map(|(key, value)| Op::Update(key, value)),
+        any::<usize>().prop_map(Op::Get),
+        any::<usize>().prop_map(Op::Delete),
+        (any::<usize>(), 0..8usize).prop_map(|(key, len)| Op::List(key, len)),
+    ]
+}
+
+/// Any key a client can address. `.` and `..` are excluded: URL normalization
+/// removes them as path segments before a request is sent.
+fn direct_key() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9./%ü€ ?#&+-]{1,6}".prop_filter("dot path segment", |key| key != "." && key != "..")
+}
+
+/// A key under one of the routes in [`through_router`], including the route
+/// prefixes themselves and keys ending in dots.
+fn routed_key() -> impl Strategy<Value = String> {
+    let prefix = prop::sample::select(vec!["a", "a.b", "ü"]);
+    let tail = prop::collection::vec("[a-z/%ü ?#.]{0,3}", 0..3);
+    (prefix, tail).prop_map(|(prefix, segments)| {
+        segments.iter().fold(prefix.to_string(), |key, segment| format!("{}.{}", key, segment))
+    })
+}
+
+fn uri(key: &str) -> Value {
+    json!({ "uri": format!("/keys/{}", urlencoding::encode(key)) })
+}
+
+/// Runs `ops` against the server at `base`, indexing into `keys`, and compares
+/// every response with the model.
+async fn check(base: &str, keys: &[String], ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut model: BTreeMap<String, Value> = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Set(i, value) => {
+                let key = &keys[i % keys.len()];
+                let reply = post(&key_url(base, key), value).await;
+                if model.contains_key(key) {
+                    prop_assert_eq!(reply.status, 409, "set {:?}", key);
+                    prop_assert_eq!(&reply.body["uri"], &uri(key)["uri"]);
+                } else {
+                    prop_assert_eq!(reply.status, 201, "set {:?}", key);
+                    prop_assert_eq!(reply.body, uri(key));
+                    model.insert(key.clone(), value.clone());
+                }
+            }
+            Op::Update(i, value) => {
+                let key = &keys[i % keys.len()];
+                let reply = put(&key_url(base, key), value).await;
+                if let Some(stored) = model.get_mut(key) {
+                    prop_assert_eq!(reply.status, 200, "update {:?}", key);
+                    prop_assert_eq!(reply.body, uri(key));
+                    *stored = value.clone();
+                } else {
+                    prop_assert_eq!(reply.status, 404, "update {:?}", key);
+                }
+            }
+            Op::Get(i) => {
+                let key = &keys[i % keys.len()];
+                let reply = get(&key_url(base, key)).await;
+                match model.get(key) {
+                    Some(value) => {
+                        prop_assert_eq!(reply.status, 200, "get {:?}", key);
+                        prop_assert_eq!(&reply.body, value, "get {:?}", key);
+                    }
+                    None => prop_assert_eq!(reply.status, 404, "get {:?}", key),
+                }
+            }
+            Op::Delete(i) => {
+                let key = &keys[i % keys.len()];
+                let reply = delete(&key_url(base, key)).await;
+                if model.remove(key).is_some() {
+                    prop_assert_eq!(reply.status, 200, "delete {:?}", key);
+                    prop_assert_eq!(reply.body, uri(key));
+                } else {
+                    prop_assert_eq!(reply.status, 404, "delete {:?}", key);
+                }
+            }
+            Op::List(i, len) => {
+                let prefix: String = keys[i % keys.len()].chars().take(*len).collect();
+                let url = format!("{}/keys?prefix={}", base, urlencoding::encode(&prefix));
+                let reply = get(&url).await;
+                let expected: Vec<&String> = model.keys().filter(|key| key.starts_with(&prefix)).collect();
+                prop_assert_eq!(reply.status, 200);
+                prop_assert_eq!(reply.body, json!({ "keys": expected }), "list {:?}", prefix);
+            }
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn key_server_matches_model(
+        keys in prop::collection::vec(direct_key(), 1..6),
+        ops in prop::collection::vec(op(), 1..40),
+    ) {
+        runtime().block_on(async {
+            let (base, _) = key_server().await;
+            check(&base, &keys, &ops).await
+        })?;
+    }
+
+    #[test]
+    fn through_router(
+        keys in prop::collection::vec(routed_key(), 1..6),
+        ops in prop::collection::vec(op(), 1..40),
+    ) {
+        runtime().block_on(async {
+            let (a, _) = key_server().await;
+            let (a_b, _) = key_server().await;
+            let (u, _) = key_server().await;
+            let base = router(&[("a", &a), ("a.b", &a_b), ("ü", &u)]).await;
+            check(&base, &keys, &ops).await
+        })?;
+    }
+}
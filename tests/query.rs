@@ -0,0 +1,86 @@
+//! `Query`: parsing, client-side application, and agreement with the server's
+//! JSON Pointer resolution for every query that is pushed down.
+
+use rust_key_store::query::{Query, Step};
+use serde_json::{json, Value};
+
+fn path(input: &str) -> Vec<Step> {
+    match Query::parse(input).unwrap() {
+        Query::Path(steps) => steps,
+        pointer => panic!("{} parsed as {:?}", input, pointer),
+    }
+}
+
+fn apply(input: &str, value: &Value) -> Value {
+    Query::parse(input).unwrap().apply(value)
+}
+
+#[test]
+fn parses_pointers_and_paths() {
+    assert_eq!(Query::parse("/a/0").unwrap(), Query::Pointer("/a/0".to_string()));
+    assert_eq!(Query::parse("").unwrap(), Query::Pointer(String::new()));
+    assert_eq!(path("."), vec![]);
+    assert_eq!(
+        path(".items[].name"),
+        vec![Step::Field("items".to_string()), Step::Iterate, Step::Field("name".to_string())]
+    );
+    assert_eq!(path(".a[-1][ 2 ]"), vec![Step::Field("a".to_string()), Step::Index(-1), Step::Index(2)]);
+    assert_eq!(
+        path(r#"."odd key"["a/b~"]"#),
+        vec![Step::Field("odd key".to_string()), Step::Field("a/b~".to_string())]
+    );
+
+    for bad in ["a.b", ".a[", ".a[x]", ".a.", r#".["open]"#, ".a-b"] {
+        assert!(Query::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn applies_paths_like_jq() {
+    let value = json!({ "items": [{ "name": "a" }, { "name": "b" }, { "id": 3 }], "n": 1 });
+
+    assert_eq!(apply(".", &value), value);
+    assert_eq!(apply(".items[1].name", &value), "b");
+    assert_eq!(apply(".items[-1]", &value), json!({ "id": 3 }));
+    assert_eq!(apply(".items[].name", &value), json!(["a", "b", null]));
+    assert_eq!(apply(".items[9]", &value), Value::Null);
+    assert_eq!(apply(".missing.deeper", &value), Value::Null);
+    assert_eq!(apply(".n[]", &value), json!([]));
+    assert_eq!(apply("/items/0/name", &value), "a");
+}
+
+#[test]
+fn to_pointer_escapes_and_skips_what_a_pointer_cannot_express() {
+    let pointer = |input: &str| Query::parse(input).unwrap().to_pointer();
+
+    assert_eq!(pointer(".a[0].b"), Some("/a/0/b".to_string()));
+    assert_eq!(pointer(r#".["a/b~c"]"#), Some("/a~1b~0c".to_string()));
+    assert_eq!(pointer("."), Some(String::new()));
+    assert_eq!(pointer("/x/y"), Some("/x/y".to_string()));
+    assert_eq!(pointer(".a[-1]"), None);
+    assert_eq!(pointer(".a[].b"), None);
+}
+
+// A pushed-down query is resolved by the server with `Value::pointer`; a
+// missing location comes back as null. The client must give the same answer.
+#[test]
+fn pushed_down_queries_match_client_side_application() {
+    let values = [
+        json!([10, 20]),
+        json!({ "0": "zero", "a": { "0": "x", "1": [5, 6] }, "01": "padded" }),
+        json!({ "a": [{ "b": 1 }, { "b": 2 }], "a/b": 3, "~": 4 }),
+        json!("scalar"),
+    ];
+    let queries = [
+        ".0", ".1", ".2", ".01", ".a", ".a[0]", ".a[1]", ".a[1][0]", ".a.0", ".a[0].b", ".[0]", ".[1]",
+        r#".["a/b"]"#, r#".["~"]"#, r#".["0"]"#, ".a.1.1",
+    ];
+    for value in &values {
+        for input in queries {
+            let query = Query::parse(input).unwrap();
+            let pointer = query.to_pointer().expect("single-location query");
+            let server = value.pointer(&pointer).cloned().unwrap_or(Value::Null);
+            assert_eq!(query.apply(value), server, "{} on {}", input, value);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Route lookup: `RouteTable::find` against a straightforward reference that
+//! checks every route, and `*` routes through a router.
+
+mod common;
+
+use std::collections::HashMap;
+
+use common::{client, get, key_server, key_url, post, router};
+use proptest::prelude::*;
+use rust_key_store::routing::{build_routes, is_pattern, RouteEntry, RouteTable, WILDCARD};
+use serde_json::json;
+
+/// Every route whose segments match the key's leading ones; the deepest wins,
+/// and between equally deep ones the first to have a literal segment where
+/// the other has `*`. Returns (prefix, backend key).
+fn reference(key: &str, routes: &HashMap<String, String>) -> Option<(String, String)> {
+    let segments: Vec<&str> = key.split('.').collect();
+    let rank = |prefix: &str| -> Option<(usize, Vec<bool>)> {
+        let pattern: Vec<&str> = prefix.split('.').collect();
+        if pattern.len() > segments.len() {
+            return None;
+        }
+        let mut literals = Vec::new();
+        for (p, s) in pattern.iter().zip(&segments) {
+            match *p {
+                WILDCARD if !s.is_empty() => literals.push(false),
+                literal if literal == *s => literals.push(true),
+                _ => return None,
+            }
+        }
+        Some((pattern.len(), literals))
+    };
+    let prefix = routes.keys().filter_map(|prefix| Some((rank(prefix)?, prefix))).max()?.1;
+    let depth = prefix.split('.').count();
+    let end = segments[..depth].iter().map(|s| s.len()).sum::<usize>() + depth - 1;
+    let backend_key = match &key[end..] {
+        _ if is_pattern(prefix) => key,
+        "" | "." | ".." => key,
+        rest => rest,
+    };
+    Some((prefix.clone(), backend_key.to_string()))
+}
+
+fn segment() -> impl Strategy<Value = &'static str> {
+    prop::sample::select(vec!["a", "b", "ab", "", "ü"])
+}
+
+fn prefix() -> impl Strategy<Value = String> {
+    let first = prop::sample::select(vec!["a", "b", "*"]);
+    let rest = prop::collection::vec(prop_oneof![segment(), Just(WILDCARD)], 0..4);
+    (first, rest).prop_map(|(first, rest)| [first].into_iter().chain(rest).collect::<Vec<_>>().join("."))
+}
+
+fn key() -> impl Strategy<Value = String> {
+    prop::collection::vec(segment(), 1..7).prop_map(|segments| segments.join("."))
+}
+
+proptest! {
+    #[test]
+    fn find_matches_the_reference(
+        prefixes in prop::collection::hash_set(prefix(), 1..12),
+        keys in prop::collection::vec(key(), 1..20),
+    ) {
+        let routes: HashMap<String, String> =
+            prefixes.into_iter().enumerate().map(|(i, prefix)| (prefix, format!("http://shard{}", i))).collect();
+        let table = RouteTable::new(routes.clone());
+        for key in &keys {
+            let found = table.find(key);
+            let expected = reference(key, &routes);
+            prop_assert_eq!(
+                found.map(|route| (route.prefix.to_string(), route.backend_key.to_string())),
+                expected.clone(),
+                "key {:?}", key
+            );
+            if let (Some(route), Some((prefix, _))) = (found, expected) {
+                prop_assert_eq!(route.target, &routes[&prefix]);
+            }
+        }
+    }
+}
+
+fn table(prefixes: &[&str]) -> RouteTable {
+    let entries = prefixes
+        .iter()
+        .map(|prefix| RouteEntry { prefix: prefix.to_string(), target: format!("http://{}", prefix) })
+        .collect();
+    RouteTable::new(build_routes(entries).unwrap())
+}
+
+fn found<'k>(table: &RouteTable, key: &'k str) -> Option<(String, &'k str)> {
+    table.find(key).map(|route| (route.prefix.to_string(), route.backend_key))
+}
+
+#[test]
+fn literal_segments_beat_wildcards_at_the_same_depth() {
+    let table = table(&["tenant", "tenant.*.cache", "tenant.acme.cache", "tenant.*.*"]);
+
+    assert_eq!(found(&table, "tenant.acme.cache.x"), Some(("tenant.acme.cache".to_string(), ".x")));
+    assert_eq!(
+        found(&table, "tenant.globex.cache.x"),
+        Some(("tenant.*.cache".to_string(), "tenant.globex.cache.x"))
+    );
+    // `tenant.*.*` is as deep as `tenant.*.cache` but has no literal third segment
+    assert_eq!(found(&table, "tenant.globex.logs.x"), Some(("tenant.*.*".to_string(), "tenant.globex.logs.x")));
+    assert_eq!(found(&table, "tenant.globex"), Some(("tenant".to_string(), ".globex")));
+}
+
+#[test]
+fn wildcards_do_not_match_empty_segments() {
+    let table = table(&["tenant.*.cache"]);
+
+    assert_eq!(found(&table, "tenant..cache.x"), None);
+    assert_eq!(found(&table, "tenant.a.cache"), Some(("tenant.*.cache".to_string(), "tenant.a.cache")));
+}
+
+#[test]
+fn wildcards_must_be_whole_segments() {
+    for prefix in ["tenant.a*", "tenant.**", "*x"] {
+        let entries = vec![RouteEntry { prefix: prefix.to_string(), target: "http://shard".to_string() }];
+        assert!(build_routes(entries).is_err(), "{}", prefix);
+    }
+}
+
+#[tokio::test]
+async fn wildcard_routes_store_full_keys() {
+    let (tenants, _) = key_server().await;
+    let (caches, _) = key_server().await;
+    let router = router(&[("tenant", &tenants), ("tenant.*.cache", &caches)]).await;
+
+    assert_eq!(post(&key_url(&router, "tenant.acme.cache.session"), &json!(1)).await.status, 201);
+    assert_eq!(post(&key_url(&router, "tenant.acme.name"), &json!("Acme")).await.status, 201);
+
+    assert_eq!(get(&key_url(&caches, "tenant.acme.cache.session")).await.body, 1);
+    assert_eq!(get(&key_url(&tenants, ".acme.name")).await.body, "Acme");
+    assert_eq!(get(&key_url(&router, "tenant.acme.cache.session")).await.body, 1);
+
+    let listed = get(&format!("{}/keys?prefix=tenant.acme", router)).await;
+    assert_eq!(listed.body["keys"], json!(["tenant.acme.cache.session", "tenant.acme.name"]));
+    let listed = get(&format!("{}/keys?prefix=tenant.acme.c", router)).await;
+    assert_eq!(listed.body["keys"], json!(["tenant.acme.cache.session"]));
+
+    // Shards are exported in prefix order
+    let dump = client().get(format!("{}/admin/export", router)).send().await.unwrap().text().await.unwrap();
+    let keys: Vec<String> = dump
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["key"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(keys, ["tenant.acme.name", "tenant.acme.cache.session"]);
+
+    let routes = get(&format!("{}/admin/routes", router)).await;
+    assert_eq!(routes.body[1]["prefix"], "tenant.*.cache");
+}
@@ -0,0 +1,93 @@
+//! Shard-aware `KvClient`: keys go straight to the owning key server, and a
+//! stale routing table is reloaded when a shard turns the request away.
+
+mod common;
+
+use std::path::PathBuf;
+
+use axum::{
+    http::{header, StatusCode},
+    Router,
+};
+use common::{dead_url, get, key_server, key_url, spawn};
+use rust_key_store::{KvClient, KvError};
+use serde_json::{json, Value};
+
+/// A routes file in the temp dir, removed when dropped.
+struct RoutesFile(PathBuf);
+
+impl RoutesFile {
+    fn new(name: &str) -> Self {
+        RoutesFile(std::env::temp_dir().join(format!("kvs-routes-{}-{}.json", name, std::process::id())))
+    }
+
+    fn write(&self, routes: &[(&str, &str)]) {
+        let entries: Vec<Value> =
+            routes.iter().map(|(prefix, target)| json!({ "prefix": prefix, "target": target })).collect();
+        std::fs::write(&self.0, serde_json::to_vec(&entries).unwrap()).unwrap();
+    }
+}
+
+impl Drop for RoutesFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A shard that no longer owns anything and says so with `status`.
+async fn stale_shard(status: StatusCode, moved_to: &str) -> String {
+    let location = moved_to.to_string();
+    spawn(Router::new().fallback(move || async move { (status, [(header::LOCATION, location)]) })).await
+}
+
+#[tokio::test]
+async fn stale_routes_are_reloaded_from_the_routes_file() {
+    let (apps, _) = key_server().await;
+    let (webs, _) = key_server().await;
+    let misdirected = stale_shard(StatusCode::MISDIRECTED_REQUEST, &apps).await;
+    let moved = stale_shard(StatusCode::PERMANENT_REDIRECT, &webs).await;
+
+    let routes = RoutesFile::new("reload");
+    routes.write(&[("app", &misdirected), ("web", &moved)]);
+    // Key requests never touch the base URL
+    let kv = KvClient::builder().base_url(dead_url().await).routes_file(&routes.0).build().unwrap();
+    assert!(kv.is_shard_aware());
+
+    // 421 from the old owner
+    routes.write(&[("app", &apps), ("web", &moved)]);
+    kv.set("app.name", &"demo").await.unwrap();
+    assert_eq!(get(&key_url(&apps, ".name")).await.body, "demo");
+
+    // A redirect from the old owner
+    routes.write(&[("app", &apps), ("web", &webs)]);
+    kv.set("web.port", &8080).await.unwrap();
+    assert_eq!(get(&key_url(&webs, ".port")).await.body, 8080);
+    assert_eq!(kv.get::<String>("app.name").await.unwrap(), "demo");
+
+    // A prefix the table has never seen
+    routes.write(&[("app", &apps), ("web", &webs), ("jobs", &webs)]);
+    kv.set("jobs.next", &1).await.unwrap();
+    assert_eq!(get(&key_url(&webs, ".next")).await.body, 1);
+}
+
+#[tokio::test]
+async fn routes_that_stay_stale_are_reported() {
+    let (apps, _) = key_server().await;
+    let misdirected = stale_shard(StatusCode::MISDIRECTED_REQUEST, &apps).await;
+    let routes = RoutesFile::new("stale");
+    routes.write(&[("app", &misdirected)]);
+    let kv = KvClient::builder().routes_file(&routes.0).build().unwrap();
+
+    // One reload, then the shard's answer is the caller's
+    match kv.set("app.name", &"demo").await {
+        Err(KvError::Status { status, .. }) => assert_eq!(status, StatusCode::MISDIRECTED_REQUEST),
+        other => panic!("expected a 421, got {:?}", other),
+    }
+    assert!(matches!(kv.set("other.key", &1).await, Err(KvError::NoRoute { .. })));
+
+    // The file is read when the client is built
+    routes.write(&[("app", &apps), ("app", &apps)]);
+    assert!(matches!(KvClient::builder().routes_file(&routes.0).build(), Err(KvError::InvalidInput(_))));
+    let missing = RoutesFile::new("missing");
+    assert!(matches!(KvClient::builder().routes_file(&missing.0).build(), Err(KvError::InvalidInput(_))));
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use axum::{
+    http::{header, StatusCode},
+    response::Json,
+    routing::get as route_get,
+    Router,
+};
+use common::{client, counted_key_server, dead_url, delete, get, key_server, key_url, post, put, router, send, spawn};
+use rust_key_store::server::TTL_HEADER;
+use serde_json::json;
+
+/// The seconds `url`'s key has left, as its GET reports them.
+async fn ttl(url: &str) -> Option<f64> {
+    let res = client().get(url).send().await.unwrap();
+    assert_eq!(res.status(), 200, "{}", url);
+    res.headers().get(TTL_HEADER).map(|ttl| ttl.to_str().unwrap().parse().unwrap())
+}
+
+#[tokio::test]
+async fn routes_by_longest_matching_prefix() {
+    let (foo, _) = key_server().await;
+    let (foo_bar, _) = key_server().await;
+    let router = router(&[("foo", &foo), ("foo.bar", &foo_bar)]).await;
+
+    assert_eq!(post(&key_url(&router, "foo.x"), &json!(1)).await.status, 201);
+    assert_eq!(post(&key_url(&router, "foo.bar.baz"), &json!(2)).await.status, 201);
+
+    // Backends store the part of the key after the matched prefix
+    assert_eq!(get(&key_url(&foo, ".x")).await.body, 1);
+    assert_eq!(get(&key_url(&foo_bar, ".baz")).await.body, 2);
+    assert_eq!(get(&key_url(&foo, ".bar.baz")).await.status, 404);
+
+    assert_eq!(get(&key_url(&router, "foo.x")).await.body, 1);
+    assert_eq!(get(&key_url(&router, "foo.bar.baz")).await.body, 2);
+}
+
+#[tokio::test]
+async fn unrouted_keys_are_404() {
+    let (foo, _) = key_server().await;
+    let router = router(&[("foo", &foo)]).await;
+
+    let reply = post(&key_url(&router, "other.key"), &json!(1)).await;
+    assert_eq!(reply.status, 404);
+    assert_eq!(reply.body["error"], "No route found for key");
+}
+
+#[tokio::test]
+async fn full_contract_through_the_router() {
+    let (foo, _) = key_server().await;
+    let router = router(&[("foo", &foo)]).await;
+    let url = key_url(&router, "foo.profile");
+
+    let created = post(&url, &json!({ "name": "Alice" })).await;
+    assert_eq!(created.status, 201);
+    assert!(created.etag.is_some());
+    assert_eq!(post(&url, &json!(0)).await.status, 409);
+    assert_eq!(get(&url).await.body, json!({ "name": "Alice" }));
+    assert_eq!(put(&url, &json!(2)).await.status, 200);
+    assert_eq!(delete(&url).await.status, 200);
+    assert_eq!(get(&url).await.status, 404);
+    assert_eq!(put(&url, &json!(3)).await.status, 404);
+    assert_eq!(delete(&url).await.status, 404);
+}
+
+#[tokio::test]
+async fn rewrites_uris_into_the_router_key_space() {
+    let (foo, _) = key_server().await;
+    let router = router(&[("foo", &foo)]).await;
+    let url = key_url(&router, "foo.a b");
+
+    assert_eq!(post(&url, &json!(1)).await.body, json!({ "uri": "/keys/foo.a%20b" }));
+    assert_eq!(post(&url, &json!(1)).await.body, json!({ "uri": "/keys/foo.a%20b" }));
+    assert_eq!(put(&url, &json!(2)).await.body, json!({ "uri": "/keys/foo.a%20b" }));
+    assert_eq!(delete(&url).await.body, json!({ "uri": "/keys/foo.a%20b" }));
+}
+
+#[tokio::test]
+async fn stored_values_with_a_uri_field_are_not_rewritten() {
+    let (foo, _) = key_server().await;
+    let router = router(&[("foo", &foo)]).await;
+    let url = key_url(&router, "foo.link");
+
+    post(&url, &json!({ "uri": "https://example.com" })).await;
+    assert_eq!(get(&url).await.body, json!({ "uri": "https://example.com" }));
+}
+
+#[tokio::test]
+async fn unreachable_backend_is_502() {
+    let (foo, _) = key_server().await;
+    let down = dead_url().await;
+    let router = router(&[("foo", &foo), ("down", &down)]).await;
+
+    let reply = post(&key_url(&router, "down.key"), &json!(1)).await;
+    assert_eq!(reply.status, 502);
+    assert_eq!(reply.body["error"], "Upstream key-server unavailable");
+    assert_eq!(get(&key_url(&router, "down.key")).await.status, 502);
+
+    // Other shards keep working
+    assert_eq!(post(&key_url(&router, "foo.key"), &json!(1)).await.status, 201);
+    assert_eq!(get(&format!("{}/ready", router)).await.status, 200);
+    let status = get(&format!("{}/status", router)).await.body;
+    assert_eq!(status["backends"][&down]["up"], false);
+}
+
+#[tokio::test]
+async fn not_ready_without_any_backend() {
+    let down = dead_url().await;
+    let router = router(&[("down", &down)]).await;
+
+    let ready = get(&format!("{}/ready", router)).await;
+    assert_eq!(ready.status, 503);
+    assert_eq!(ready.body["ready"], false);
+}
+
+#[tokio::test]
+async fn lists_and_exports_across_shards() {
+    let (foo, _) = key_server().await;
+    let (bar, _) = key_server().await;
+    let router = router(&[("foo", &foo), ("bar", &bar)]).await;
+    for key in ["foo.b", "bar.a", "foo.a"] {
+        post(&key_url(&router, key), &json!(key)).await;
+    }
+    // Written to the backend directly under a key the router never uses
+    post(&key_url(&foo, "stray"), &json!(0)).await;
+
+    let listed = get(&format!("{}/keys", router)).await;
+    assert_eq!(listed.body, json!({ "keys": ["bar.a", "foo.a", "foo.b"] }));
+
+    let dump = client().get(format!("{}/admin/export", router)).send().await.unwrap().text().await.unwrap();
+    let keys: Vec<String> = dump
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["key"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(keys, ["bar.a", "foo.a", "foo.b"]);
+}
+
+#[tokio::test]
+async fn backends_behind_several_prefixes_are_exported_once() {
+    let (shared, _, exports) = counted_key_server("/admin/export").await;
+    let (other, _) = key_server().await;
+    let router = router(&[("foo", &shared), ("bar", &shared), ("qux", &other)]).await;
+    // Stored under their full names, so each belongs to exactly one prefix
+    for key in ["foo", "bar", "qux"] {
+        post(&key_url(&router, key), &json!(key)).await;
+    }
+
+    let dump = client().get(format!("{}/admin/export", router)).send().await.unwrap().text().await.unwrap();
+    assert_eq!(
+        dump,
+        "{\"key\":\"bar\",\"value\":\"bar\"}\n{\"key\":\"foo\",\"value\":\"foo\"}\n{\"key\":\"qux\",\"value\":\"qux\"}\n"
+    );
+    assert_eq!(exports.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn backends_behind_several_prefixes_are_listed_once() {
+    let (shared, _, listings) = counted_key_server("/keys").await;
+    let (other, _) = key_server().await;
+    let router = router(&[("foo", &shared), ("foo.bar", &shared), ("baz", &shared), ("qux", &other)]).await;
+    for key in ["foo", "foo.bar", "baz", "qux"] {
+        post(&key_url(&router, key), &json!(key)).await;
+    }
+
+    let listed = get(&format!("{}/keys", router)).await;
+    assert_eq!(listed.body, json!({ "keys": ["baz", "foo", "foo.bar", "qux"] }));
+    assert_eq!(listings.load(Ordering::SeqCst), 1);
+
+    // `foo.` covers what `foo.bar` would list
+    let listed = get(&format!("{}/keys?prefix=foo.", router)).await;
+    assert_eq!(listed.body, json!({ "keys": ["foo.bar"] }));
+    assert_eq!(listings.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn imports_split_across_shards() {
+    let (foo, foo_store) = key_server().await;
+    let (bar, bar_store) = key_server().await;
+    let router = router(&[("foo", &foo), ("bar", &bar)]).await;
+
+    let dump = "{\"key\":\"foo.a\",\"value\":1}\n{\"key\":\"bar.a\",\"value\":2}\n";
+    let reply = send(client().post(format!("{}/admin/import", router)).body(dump)).await;
+    assert_eq!(reply.body, json!({ "imported": 2, "skipped": 0 }));
+    assert_eq!(foo_store.len(), 1);
+    assert_eq!(bar_store.len(), 1);
+    assert_eq!(get(&key_url(&router, "bar.a")).await.body, 2);
+}
+
+#[tokio::test]
+async fn renames_across_shards() {
+    let (user, user_store) = key_server().await;
+    let (users, _) = key_server().await;
+    let router = router(&[("user", &user), ("users", &users)]).await;
+    let client = client();
+    post(&key_url(&router, "user.profile"), &json!({ "n": 1 })).await;
+
+    let rename = |destination: &str, overwrite: bool| {
+        client
+            .post(format!("{}/rename", key_url(&router, "user.profile")))
+            .json(&json!({ "destination": destination, "overwrite": overwrite }))
+    };
+    let moved = send(rename("users.profile", false)).await;
+    assert_eq!(moved.status, 201);
+    assert_eq!(moved.body["uri"], "/keys/users.profile");
+    assert_eq!(get(&key_url(&router, "users.profile")).await.body, json!({ "n": 1 }));
+    assert_eq!(get(&key_url(&router, "user.profile")).await.status, 404);
+    assert_eq!(user_store.len(), 0);
+
+    post(&key_url(&router, "user.profile"), &json!({ "n": 2 })).await;
+    assert_eq!(send(rename("users.profile", false)).await.status, 409);
+    assert_eq!(send(rename("users.profile", true)).await.status, 200);
+    assert_eq!(get(&key_url(&router, "users.profile")).await.body, json!({ "n": 2 }));
+}
+
+#[tokio::test]
+async fn transfers_across_shards_keep_the_ttl() {
+    let (user, _) = key_server().await;
+    let (users, _) = key_server().await;
+    let router = router(&[("user", &user), ("users", &users)]).await;
+    let transfer = |action: &str, key: &str, destination: &str| {
+        let url = format!("{}/{}", key_url(&router, key), action);
+        send(client().post(url).json(&json!({ "destination": destination, "overwrite": true })))
+    };
+    send(client().post(format!("{}?ttl=300", key_url(&router, "user.session"))).json(&json!("token"))).await;
+    post(&key_url(&router, "user.plain"), &json!("forever")).await;
+
+    assert_eq!(transfer("copy", "user.session", "users.copy").await.status, 201);
+    assert!(ttl(&key_url(&router, "users.copy")).await.is_some_and(|ttl| ttl > 290.0 && ttl <= 300.0));
+    assert_eq!(transfer("rename", "user.session", "users.session").await.status, 201);
+    assert!(ttl(&key_url(&users, ".session")).await.is_some_and(|ttl| ttl > 290.0 && ttl <= 300.0));
+
+    // Replacing a key with a TTL by one without leaves none
+    assert_eq!(transfer("rename", "user.plain", "users.session").await.status, 200);
+    assert_eq!(ttl(&key_url(&router, "users.session")).await, None);
+}
+
+// The source changes between the router's read and its delete, so the
+// destination is put back, TTL and all.
+#[tokio::test]
+async fn rolled_back_renames_restore_the_destination_ttl() {
+    let read = || async {
+        let headers = [(header::ETAG, "\"1\""), (header::HeaderName::from_static(TTL_HEADER), "100")];
+        (headers, Json(json!("new")))
+    };
+    let delete = || async { (StatusCode::PRECONDITION_FAILED, Json(json!({ "error": "Version mismatch" }))) };
+    let racing = Router::new().route("/keys/{key}", route_get(read).delete(delete));
+    let source = spawn(racing).await;
+    let (destination, _) = key_server().await;
+    let router = router(&[("src", &source), ("dst", &destination)]).await;
+    send(client().post(format!("{}?ttl=600", key_url(&router, "dst.key"))).json(&json!("old"))).await;
+
+    let renamed = send(
+        client()
+            .post(format!("{}/rename", key_url(&router, "src.key")))
+            .json(&json!({ "destination": "dst.key", "overwrite": true })),
+    )
+    .await;
+    assert_eq!(renamed.status, 409);
+    assert_eq!(get(&key_url(&router, "dst.key")).await.body, "old");
+    assert!(ttl(&key_url(&router, "dst.key")).await.is_some_and(|ttl| ttl > 590.0));
+}
+
+#[tokio::test]
+async fn copies_within_a_shard() {
+    let (user, _) = key_server().await;
+    let router = router(&[("user", &user)]).await;
+    post(&key_url(&router, "user.a"), &json!(1)).await;
+
+    let copied = send(
+        client()
+            .post(format!("{}/copy", key_url(&router, "user.a")))
+            .json(&json!({ "destination": "user.b" })),
+    )
+    .await;
+    assert_eq!(copied.status, 201);
+    assert_eq!(copied.body["uri"], "/keys/user.b");
+    assert_eq!(get(&key_url(&user, ".b")).await.body, 1);
+    assert_eq!(get(&key_url(&router, "user.a")).await.body, 1);
+}
+
+#[tokio::test]
+async fn prefix_keys_and_trailing_dots_stay_distinct() {
+    let (foo, _) = key_server().await;
+    let router = router(&[("foo", &foo)]).await;
+    for (i, key) in ["foo", "foo.", "foo..", "foo...x"].into_iter().enumerate() {
+        assert_eq!(post(&key_url(&router, key), &json!(i)).await.status, 201, "{}", key);
+    }
+    for (i, key) in ["foo", "foo.", "foo..", "foo...x"].into_iter().enumerate() {
+        assert_eq!(get(&key_url(&router, key)).await.body, i, "{}", key);
+    }
+
+    // Keys that would be empty or a dot segment on the backend keep their full name
+    assert_eq!(get(&key_url(&foo, "foo")).await.body, 0);
+    assert_eq!(get(&key_url(&foo, "foo..")).await.body, 2);
+    assert_eq!(get(&key_url(&foo, "...x")).await.body, 3);
+
+    let listed = get(&format!("{}/keys?prefix=foo.", router)).await;
+    assert_eq!(listed.body, json!({ "keys": ["foo.", "foo..", "foo...x"] }));
+}